@@ -1,20 +1,45 @@
 import io
+import json
 import logging
 import socketserver
 import threading
 import time
+from collections import deque
+from concurrent.futures import ThreadPoolExecutor
+from datetime import datetime
 from http import server
 from http.server import BaseHTTPRequestHandler
 from threading import Condition
 from typing import Any
 
 import numpy as np
+import simplejpeg
+from PIL import Image, ImageDraw, ImageFont
 
 import libcamera
 from picamera2 import Picamera2
-from picamera2.encoders import MJPEGEncoder
+from picamera2.encoders import MJPEGEncoder, Quality
 from picamera2.outputs import FileOutput
-
+from picamera2.request import CompletedRequest, MappedArray
+
+# Still mode snapshots are downscaled by this factor
+SNAPSHOT_SCALE = 4
+SNAPSHOT_QUALITY = 85
+CLIP_QUALITY = 90
+# Clip frames are encoded on worker threads, so the capture loop keeps up with the sensor
+CLIP_ENCODE_WORKERS = 2
+# Encoded frames waiting to be written, capture blocks once this many are pending
+CLIP_MAX_PENDING_FRAMES = 8
+OVERLAY_COLOUR = (255, 255, 255, 255)
+OVERLAY_MARGIN = 8
+# Text is rendered for this frame width and scaled up for wider frames
+OVERLAY_TEXT_WIDTH = 640
+# Space between overlay text lines, before scaling
+OVERLAY_LINE_SPACING = 4
+# Frames to wait for an autofocus cycle to start scanning
+AF_START_FRAMES = 10
+# simplejpeg colour space of each pixel format, Picamera2 names formats by the little endian word
+JPEG_COLOURSPACES = {"BGR888": "RGB", "RGB888": "BGR", "XBGR8888": "RGBX", "XRGB8888": "BGRX"}
 
 class StreamingOutput(io.BufferedIOBase):
     def __init__(self):
@@ -30,12 +55,36 @@ class StreamingOutput(io.BufferedIOBase):
 
 
 class StreamingHandler(BaseHTTPRequestHandler):
-    def __init__(self, streaming_output: StreamingOutput, *args, **kwargs):
-        self.streaming_output = streaming_output
+    def __init__(self, camera_service: "CameraService", *args, **kwargs):
+        self.camera_service = camera_service
+        self.streaming_output = camera_service.streaming_output
         super().__init__(*args, **kwargs)
 
     def do_GET(self):
-        if self.path == '/stream.mjpg':
+        if self.path == '/snapshot.jpg':
+            # Only preview frames are served, still frames need the camera lock held by Rust
+            if not self.camera_service.encoder:
+                self.send_error(503, "Preview not running")
+                return
+            try:
+                frame = self.camera_service.get_stream_frame()
+            except Exception as e:
+                logging.warning("Failed to get snapshot: %s", str(e))
+                self.send_error(503)
+                return
+
+            self.send_response(200)
+            self.send_header('Cache-Control', 'no-cache, private')
+            self.send_header('Pragma', 'no-cache')
+            self.send_header('Content-Type', 'image/jpeg')
+            self.send_header('Content-Length', str(len(frame)))
+            self.end_headers()
+            self.wfile.write(frame)
+        elif self.path == '/stream.mjpg':
+            if not self.camera_service.encoder:
+                self.send_error(503, "Preview not running")
+                return
+
             self.send_response(200)
             self.send_header('Age', '0')
             self.send_header('Cache-Control', 'no-cache, private')
@@ -43,10 +92,12 @@ class StreamingHandler(BaseHTTPRequestHandler):
             self.send_header('Content-Type', 'multipart/x-mixed-replace; boundary=FRAME')
             self.end_headers()
 
+            self.camera_service.add_stream_client(1)
             try:
-                while True:
+                # Ends when preview is stopped
+                while self.camera_service.encoder:
                     with self.streaming_output.condition:
-                        self.streaming_output.condition.wait()
+                        self.streaming_output.condition.wait(timeout=1)
                         frame = self.streaming_output.frame
 
                     if frame is None:
@@ -60,6 +111,8 @@ class StreamingHandler(BaseHTTPRequestHandler):
                     self.wfile.write(b'\r\n')
             except Exception as e:
                 logging.warning("Removed streaming client %s: %s", self.client_address, str(e))
+            finally:
+                self.camera_service.add_stream_client(-1)
         else:
             self.send_error(404)
             self.end_headers()
@@ -77,20 +130,66 @@ class CameraService:
     streaming_output: StreamingOutput
     encoder: MJPEGEncoder | None
     http_server: StreamingServer | None
+    http_port: int | None
     server_thread: threading.Thread | None
+    # Sensor modes, read before the camera is started
+    sensor_modes: list[dict[str, Any]]
+    # Capture configs, set before the camera is started
+    still_capture_config: dict[str, Any]
+    video_capture_config: dict[str, Any]
+    # Flips of the camera orientation, quarter turns are done in Rust
+    transform: libcamera.Transform
+    # Preview overlay
+    overlay: dict[str, Any] | None
+    overlay_font: ImageFont.ImageFont | ImageFont.FreeTypeFont
+    # Rendered masks of the last frame's overlay text lines, by line and scale
+    overlay_line_masks: dict[tuple[str, int], np.ndarray]
+    # Focus assist, measured on preview frames
+    focus_assist: dict[str, Any] | None
+    # Score, running max and sensor timestamp of the latest measured frame
+    focus_score: tuple[float, float, int] | None
+    # Connected preview stream clients
+    stream_clients: int
+    stream_clients_lock: threading.Lock
 
-    def __init__(self, still_controls: dict[str, Any] | None = None):
+    def __init__(self):
         """
-        Initializes the camera service
+        Initializes the camera service. Camera is started with start_still, after capture configs are set
         """
         print("Python - CameraService init")
         self.cam = Picamera2()
         self.file_output = None
         self.encoder = None
         self.http_server = None
+        self.http_port = None
         self.server_thread = None
         self.streaming_output = StreamingOutput()
+        self.sensor_modes = self.cam.sensor_modes
+        self.overlay = None
+        self.overlay_font = ImageFont.load_default()
+        self.overlay_line_masks = {}
+        self.focus_assist = None
+        self.focus_score = None
+        self.stream_clients = 0
+        self.stream_clients_lock = threading.Lock()
+        self.transform = libcamera.Transform()
+
+    def set_capture_config(self, still_capture_config: dict[str, Any], video_capture_config: dict[str, Any]):
+        """
+        Sets capture configs, used the next time the camera is configured
+        """
+        print("Python - Setting capture config:\n", still_capture_config, "\n", video_capture_config)
+        self.still_capture_config = still_capture_config
+        self.video_capture_config = video_capture_config
+
+    def set_transform(self, hflip: bool, vflip: bool):
+        """
+        Sets transform, used the next time the camera is configured
+        """
+        print("Python - Setting transform: hflip", hflip, "vflip", vflip)
+        self.transform = libcamera.Transform(hflip=hflip, vflip=vflip)
 
+    def start_still(self, still_controls: dict[str, Any] | None = None):
         self.set_still_configuration(still_controls)
         print("Python - Starting camera")
         self.cam.start()
@@ -100,23 +199,37 @@ class CameraService:
         print("Python - Configuring camera")
         if still_controls is None:
             still_controls = {}
+        config = self.still_capture_config
         still_config = self.cam.create_still_configuration(
-            main={"size": (3280, 2464), "format": "BGR888"},
+            main={"size": config["size"], "format": config["format"]},
             lores=None,
             #raw
-            transform=libcamera.Transform(),
-            colour_space=libcamera.ColorSpace.Sycc(),
-            buffer_count=3,
+            transform=self.transform,
+            colour_space=self.colour_space(config),
+            buffer_count=config["buffer_count"],
             controls=still_controls,
             display=None,
             encode=None,
             queue=True,
-            sensor={"output_size": (3280, 2464), "bit_depth": 10},
+            sensor=self.sensor_config(config),
             use_case="still"
         )
         self.cam.stop()
         self.cam.configure(still_config)
 
+    @staticmethod
+    def colour_space(capture_config: dict[str, Any]) -> libcamera.ColorSpace:
+        return getattr(libcamera.ColorSpace, capture_config["colour_space"])()
+
+    @staticmethod
+    def sensor_config(capture_config: dict[str, Any]) -> dict[str, Any]:
+        """
+        :return: Sensor configuration, empty to let libcamera choose the sensor mode
+        """
+        if capture_config["sensor_size"] is None:
+            return {}
+        return {"output_size": capture_config["sensor_size"], "bit_depth": capture_config["bit_depth"]}
+
     def capture(self, monotonic_ns: int) -> tuple[np.ndarray, int, int, dict[str, Any]]:
         """
         :return: Jpeg bytes and metadata
@@ -131,6 +244,141 @@ class CameraService:
 
         return flattened_array, width, height, metadata
 
+    def capture_frame(self) -> tuple[np.ndarray, int, int, dict[str, Any]]:
+        """
+        :return: Array and metadata of the next frame in the current camera mode
+        """
+        return self.capture(time.monotonic_ns())
+
+    def capture_motion_frame(self, width: int) -> tuple[np.ndarray, int, int]:
+        """
+        :param width: Frames are subsampled to about this width
+        :return: Array, width and height of the next frame, the full frame is not copied
+        """
+        request = self.cam.capture_request()
+        try:
+            with MappedArray(request, "main") as m:
+                step = max(1, m.array.shape[1] // width)
+                array = np.ascontiguousarray(m.array[::step, ::step])
+        finally:
+            request.release()
+
+        height, frame_width, _ = array.shape
+        return array.flatten(), frame_width, height
+
+    def get_preview_frame(self) -> tuple[bytes, int, int, bool]:
+        """
+        :return: Jpeg bytes, width, height, is the frame from the preview stream
+        """
+        if self.encoder:
+            frame = self.get_stream_frame()
+            width, height = self.cam.camera_configuration()["main"]["size"]
+            return frame, width, height, True
+
+        # Subsampled while mapped, the full frame is not copied
+        request = self.cam.capture_request()
+        try:
+            with MappedArray(request, "main") as m:
+                array = np.ascontiguousarray(m.array[::SNAPSHOT_SCALE, ::SNAPSHOT_SCALE])
+        finally:
+            request.release()
+        height, width, _ = array.shape
+        colourspace = JPEG_COLOURSPACES[self.still_capture_config["format"]]
+        jpeg = simplejpeg.encode_jpeg(array, quality=SNAPSHOT_QUALITY, colorspace=colourspace)
+        return jpeg, width, height, False
+
+    def get_stream_frame(self) -> bytes:
+        """
+        :return: Jpeg bytes of the latest preview stream frame
+        """
+        with self.streaming_output.condition:
+            # Preview just started, wait for first frame
+            if self.streaming_output.frame is None:
+                self.streaming_output.condition.wait(timeout=5)
+            frame = self.streaming_output.frame
+        if frame is None:
+            raise RuntimeError("No preview frame available")
+        return bytes(frame)
+
+    def record_clip(
+            self,
+            video_controls: dict[str, Any] | None,
+            still_controls: dict[str, Any] | None,
+            clip_config: dict[str, Any],
+            start_ns: int,
+            end_ns: int,
+            path: str,
+    ) -> list[int]:
+        """
+        Records frames with sensor timestamps between start and end to file, then switches back
+        to still configuration
+        :return: Sensor timestamps of recorded frames
+        """
+        self.cam.stop()
+        timestamps = []
+        # Still configuration is restored even if the video configuration fails
+        try:
+            if video_controls is None:
+                video_controls = {}
+            # Fixed frame rate, so frames are evenly spaced
+            frame_duration = int(1_000_000 / clip_config["frame_rate"])
+            video_controls = {**video_controls, "FrameDurationLimits": (frame_duration, frame_duration)}
+            video_config = self.cam.create_video_configuration(
+                main={"size": clip_config["size"], "format": "BGR888"},
+                lores=None,
+                # raw
+                transform=self.transform,
+                colour_space=self.colour_space(self.video_capture_config),
+                buffer_count=self.video_capture_config["buffer_count"],
+                controls=video_controls,
+                display=None,
+                encode=None,
+                queue=True,
+                sensor={"output_size": clip_config["sensor_size"], "bit_depth": clip_config["bit_depth"]},
+                use_case="video"
+            )
+            self.cam.configure(video_config)
+            self.cam.start()
+
+            with open(path, "wb") as file, ThreadPoolExecutor(max_workers=CLIP_ENCODE_WORKERS) as executor:
+                # Futures are written in capture order
+                pending = deque()
+                request = self.cam.capture_request(flush=start_ns)
+                while True:
+                    timestamp = request.get_metadata()["SensorTimestamp"]
+                    if timestamp >= end_ns:
+                        request.release()
+                        break
+                    array = request.make_array("main")
+                    request.release()
+
+                    if clip_config["format"] == "mjpeg":
+                        pending.append(executor.submit(
+                            simplejpeg.encode_jpeg, array, quality=CLIP_QUALITY, colorspace="RGB"
+                        ))
+                        while len(pending) > CLIP_MAX_PENDING_FRAMES:
+                            file.write(pending.popleft().result())
+                    else:
+                        file.write(array.tobytes())
+                    timestamps.append(timestamp)
+
+                    request = self.cam.capture_request()
+                while pending:
+                    file.write(pending.popleft().result())
+        finally:
+            self.set_still_configuration(still_controls)
+            self.cam.start()
+
+        return timestamps
+
+    def add_stream_client(self, count: int):
+        with self.stream_clients_lock:
+            self.stream_clients += count
+
+    def get_stream_clients(self) -> int:
+        with self.stream_clients_lock:
+            return self.stream_clients
+
     def get_sync_status(self) -> tuple[bool, int]:
         """
         :return: Is sync ready, sync error in microseconds
@@ -144,55 +392,89 @@ class CameraService:
 
         return sync_ready, sync_timing
 
+    def get_sensor_modes(self) -> list[tuple[str, int, int, int, float]]:
+        """
+        :return: Format, bit depth, width, height and max fps of each sensor mode
+        """
+        return [
+            (str(mode["format"]), mode["bit_depth"], mode["size"][0], mode["size"][1], mode["fps"])
+            for mode in self.sensor_modes
+        ]
+
     def stop(self):
         print("Stopping camera")
         self.cam.stop()
 
-    def start_preview(self, video_controls: dict[str, Any] | None = None):
+    def start_preview(self, video_controls: dict[str, Any] | None, preview_config: dict[str, Any]):
+        # Restarting preview with different parameters
+        self.stop_encoder()
+
         self.cam.stop()
         if video_controls is None:
             video_controls = {}
+        frame_rate = preview_config["frame_rate"]
+        if frame_rate is not None:
+            # Limit frame rate, but keep the longest frame duration for AE
+            _, max_frame_duration, _ = self.cam.camera_controls["FrameDurationLimits"]
+            min_frame_duration = int(1_000_000 / frame_rate)
+            video_controls = {
+                **video_controls,
+                "FrameDurationLimits": (min_frame_duration, max(min_frame_duration, max_frame_duration)),
+            }
         video_config = self.cam.create_video_configuration(
-            main={"size": (1640, 1232), "format": "XBGR8888"},
+            main={"size": preview_config["size"], "format": self.video_capture_config["format"]},
             lores=None,
             # raw
-            transform=libcamera.Transform(),
-            colour_space=libcamera.ColorSpace.Rec709(),
-            buffer_count=6,
+            transform=self.transform,
+            colour_space=self.colour_space(self.video_capture_config),
+            buffer_count=self.video_capture_config["buffer_count"],
             controls=video_controls,
             display=None,
             encode="main",
             queue=True,
-            sensor={"output_size": (1640, 1232), "bit_depth": 10},
+            sensor={"output_size": preview_config["sensor_size"], "bit_depth": preview_config["bit_depth"]},
             use_case="still"
         )
 
         self.cam.configure(video_config)
+        # Focus and overlay are only processed on preview frames
+        self.cam.pre_callback = self.process_preview_frame
         self.cam.start()
 
         # Create encoder and start streaming
         self.encoder = MJPEGEncoder()
         self.file_output = FileOutput(self.streaming_output)
-        self.cam.start_encoder(self.encoder, self.file_output, name="main")
+        self.cam.start_encoder(
+            self.encoder, self.file_output, quality=Quality[preview_config["quality"]], name="main"
+        )
+
+        self.start_http_server(preview_config["port"])
 
-        if not self.http_server:
-            # Start HTTP server with threading support
-            def handler_factory(*args, **kwargs):
-                return StreamingHandler(self.streaming_output, *args, **kwargs)
+    def start_http_server(self, port: int):
+        """
+        Starts the HTTP server, restarts it if it is running on a different port
+        """
+        if self.http_server and self.http_port == port:
+            return
+        self.stop_http_server()
 
-            self.http_server = StreamingServer(('', 8000), handler_factory)
-            self.server_thread = threading.Thread(target=self.http_server.serve_forever, daemon=True)
-            self.server_thread.start()
+        # Start HTTP server with threading support
+        def handler_factory(*args, **kwargs):
+            return StreamingHandler(self, *args, **kwargs)
 
+        self.http_server = StreamingServer(('', port), handler_factory)
+        self.http_port = port
+        self.server_thread = threading.Thread(target=self.http_server.serve_forever, daemon=True)
+        self.server_thread.start()
 
-    def stop_preview(self, still_controls: dict[str, Any] | None = None):
-        # Stop HTTP server
+    def stop_http_server(self):
         if self.http_server:
             self.http_server.shutdown()
             self.http_server.server_close()
             self.http_server = None
+            self.http_port = None
 
-        # Stop encoder
+    def stop_encoder(self):
         if self.encoder:
             self.cam.stop_encoder()
             self.encoder = None
@@ -201,22 +483,252 @@ class CameraService:
             self.file_output.close()
             self.file_output = None
 
+        self.streaming_output.frame = None
+
+    def stop_preview(self, still_controls: dict[str, Any] | None = None):
+        # Stop encoder
+        self.stop_encoder()
+        self.cam.pre_callback = None
+        self.focus_assist = None
+        self.focus_score = None
+
+        # Stream and snapshots are only served while the preview runs
+        self.stop_http_server()
+
         # Switch back to still configuration
-        self.cam.stop()
-        if still_controls is None:
-            still_controls = {}
-        still_config = self.cam.create_still_configuration(still_controls)
-        self.cam.configure(still_config)
-        self.cam.start()
+        self.start_still(still_controls)
+
+    def set_overlay(self, overlay: dict[str, Any] | None):
+        print("Setting overlay:\n", overlay)
+        # Label does not change, so it is rendered before the next frame
+        if overlay is not None and overlay["label"] is not None and self.encoder:
+            width = self.cam.camera_configuration()["main"]["size"][0]
+            self.line_masks([overlay["label"]], max(1, width // OVERLAY_TEXT_WIDTH))
+        self.overlay = overlay
+
+    def set_focus_assist(self, focus_assist: dict[str, Any] | None):
+        print("Setting focus assist:\n", focus_assist)
+        self.focus_assist = focus_assist
+        self.focus_score = None
+
+    def get_focus_score(self) -> tuple[float, float, int] | None:
+        """
+        :return: Score, running max and sensor timestamp of the latest preview frame, None if not measured yet
+        """
+        return self.focus_score
+
+    def process_preview_frame(self, request: CompletedRequest):
+        """
+        Measures focus before the overlay is drawn, so overlay lines do not count as detail
+        """
+        self.measure_focus(request)
+        self.draw_overlay(request)
+
+    @staticmethod
+    def focus_region(roi: tuple[float, float, float, float], width: int, height: int) -> tuple[int, int, int, int]:
+        """
+        :return: Pixel x, y, width and height of the normalized region, at least 3x3
+        """
+        roi_x, roi_y, roi_width, roi_height = roi
+        x = min(int(roi_x * width), width - 3)
+        y = min(int(roi_y * height), height - 3)
+        return x, y, max(3, min(int(roi_width * width), width - x)), max(3, min(int(roi_height * height), height - y))
+
+    def measure_focus(self, request: CompletedRequest):
+        """
+        Variance of the Laplacian of the focus region's luma
+        """
+        focus_assist = self.focus_assist
+        if focus_assist is None:
+            return
+
+        with MappedArray(request, "main") as m:
+            array = m.array
+            x, y, width, height = self.focus_region(focus_assist["roi"], array.shape[1], array.shape[0])
+            # Channel order does not matter for the mean, 4th channel is padding
+            luma = array[y:y + height, x:x + width, :3].mean(axis=2, dtype=np.float32)
+
+        laplacian = (
+            4 * luma[1:-1, 1:-1] - luma[:-2, 1:-1] - luma[2:, 1:-1] - luma[1:-1, :-2] - luma[1:-1, 2:]
+        )
+        score = float(laplacian.var())
+        max_score = score if self.focus_score is None else max(score, self.focus_score[1])
+        self.focus_score = (score, max_score, request.get_metadata()["SensorTimestamp"])
+
+    def draw_overlay(self, request: CompletedRequest):
+        """
+        Draws overlay and focus assist onto the main stream before it is encoded
+        """
+        overlay = self.overlay
+        focus_assist = self.focus_assist
+        if focus_assist is not None and not focus_assist["draw"]:
+            focus_assist = None
+        if overlay is None and focus_assist is None:
+            return
+
+        lines = []
+        if overlay is not None:
+            if overlay["label"] is not None:
+                lines.append(overlay["label"])
+            if overlay["exposure"]:
+                metadata = request.get_metadata()
+                lines.append(
+                    f"Exposure {metadata.get('ExposureTime')} us, "
+                    f"analogue gain {metadata.get('AnalogueGain', 0):.2f}, "
+                    f"digital gain {metadata.get('DigitalGain', 0):.2f}"
+                )
+            if overlay["timestamp"]:
+                lines.append(datetime.now().strftime("%Y-%m-%d %H:%M:%S.%f")[:-3])
+        focus_score = self.focus_score
+        if focus_assist is not None and focus_score is not None:
+            score, max_score, _ = focus_score
+            lines.append(f"Focus {score:.1f}, max {max_score:.1f}")
+
+        with MappedArray(request, "main") as m:
+            array = m.array
+            height, width = array.shape[:2]
+            line_width = max(1, width // OVERLAY_TEXT_WIDTH)
+            # Preview format may have 3 or 4 channels
+            colour = OVERLAY_COLOUR[:array.shape[2]]
+
+            if overlay is not None and overlay["grid"] is not None:
+                rows, columns = overlay["grid"]
+                for row in range(1, rows):
+                    y = row * height // rows
+                    array[y:y + line_width, :] = colour
+                for column in range(1, columns):
+                    x = column * width // columns
+                    array[:, x:x + line_width] = colour
+
+            if overlay is not None and overlay["crosshair"]:
+                x, y = width // 2, height // 2
+                size = min(width, height) // 20
+                array[y:y + line_width, x - size:x + size] = colour
+                array[y - size:y + size, x:x + line_width] = colour
+
+            if focus_assist is not None:
+                # Outline just outside the measured region
+                x, y, region_width, region_height = self.focus_region(focus_assist["roi"], width, height)
+                top, left = max(0, y - line_width), max(0, x - line_width)
+                bottom, right = y + region_height, x + region_width
+                array[top:y, left:right + line_width] = colour
+                array[bottom:bottom + line_width, left:right + line_width] = colour
+                array[top:bottom + line_width, left:x] = colour
+                array[top:bottom + line_width, right:right + line_width] = colour
+
+            if lines:
+                self.draw_text(array, self.line_masks(lines, line_width), line_width)
+
+    def line_masks(self, lines: list[str], scale: int) -> list[np.ndarray]:
+        """
+        Text masks of the lines. Lines are only rendered when they change, so a static label
+        is rendered once.
+        """
+        masks = {}
+        for line in lines:
+            key = (line, scale)
+            mask = self.overlay_line_masks.get(key)
+            if mask is None:
+                _, _, right, bottom = self.overlay_font.getbbox(line)
+                text_image = Image.new("L", (right + 2, bottom + 2))
+                ImageDraw.Draw(text_image).text((1, 1), line, fill=255, font=self.overlay_font)
+                mask = (np.asarray(text_image) > 0).repeat(scale, axis=0).repeat(scale, axis=1)
+            masks[key] = mask
+        # Lines of older frames are dropped, so changing text does not pile up
+        self.overlay_line_masks = masks
+        return [masks[(line, scale)] for line in lines]
+
+    @staticmethod
+    def draw_text(array: np.ndarray, masks: list[np.ndarray], scale: int):
+        """
+        Draws text line masks in the top left corner, on a darkened background
+        """
+        y = OVERLAY_MARGIN
+        for mask in masks:
+            mask_height = min(mask.shape[0], array.shape[0] - y)
+            mask_width = min(mask.shape[1], array.shape[1] - OVERLAY_MARGIN)
+            if mask_height <= 0:
+                return
+            mask = mask[:mask_height, :mask_width]
+            region = array[y:y + mask_height, OVERLAY_MARGIN:OVERLAY_MARGIN + mask_width]
+            region //= 2
+            region[mask] = OVERLAY_COLOUR[:array.shape[2]]
+            y += mask_height + OVERLAY_LINE_SPACING * scale
 
     def set_controls(self, controls: dict[str, Any]):
         print("Settings controls:\n", controls)
         self.cam.set_controls(controls)
 
+    def get_frame_metadata(self) -> dict[str, Any]:
+        """
+        :return: Metadata of the next completed frame
+        """
+        return self.cam.capture_metadata()
+
+    def auto_focus(self, timeout: float) -> dict[str, Any]:
+        """
+        Triggers an autofocus cycle and waits for it to finish.
+        AfMode is left on Auto, the caller locks the position or restores the previous mode
+        :param timeout: Seconds to wait for the cycle
+        :return: Metadata of the frame the cycle finished on, or the last frame on timeout
+        """
+        if "AfMode" not in self.cam.camera_controls:
+            raise RuntimeError("Camera does not support autofocus")
+        af_state = libcamera.controls.AfStateEnum
+        self.cam.set_controls({
+            "AfMode": libcamera.controls.AfModeEnum.Auto,
+            "AfTrigger": libcamera.controls.AfTriggerEnum.Start,
+        })
+        deadline = time.monotonic() + timeout
+        scanning = False
+        frames = 0
+        while True:
+            metadata = self.cam.capture_metadata()
+            frames += 1
+            state = metadata.get("AfState")
+            if state == af_state.Scanning:
+                scanning = True
+            # Frames before the trigger is applied can still report the previous cycle
+            elif (scanning or frames > AF_START_FRAMES) and state in (af_state.Focused, af_state.Failed):
+                return metadata
+            if time.monotonic() > deadline:
+                return metadata
+
+    def reset_controls(self, names: list[str]):
+        """
+        Sets controls to camera defaults, controls without a default are left unchanged
+        """
+        defaults = {
+            name: self.cam.camera_controls[name][2]
+            for name in names
+            if name in self.cam.camera_controls and self.cam.camera_controls[name][2] is not None
+        }
+        print("Resetting controls:\n", defaults)
+        self.cam.set_controls(defaults)
+
     def get_controls(self,):
         print("Getting controls:\n", self.cam.camera_controls)
         return self.cam.camera_controls
 
+    def get_control_info(self) -> str:
+        """
+        Returns all camera controls with their type, limits and enum value names as json
+        """
+        types = {control.name: str(control.type).split('.')[-1] for control in self.cam.camera.controls}
+        info = []
+        for name, (minimum, maximum, default) in self.cam.camera_controls.items():
+            enum = getattr(libcamera.controls, f"{name}Enum", None)
+            enum_values = {key: int(value) for key, value in enum.__members__.items()} if enum else None
+            info.append({
+                "name": name,
+                "type": types.get(name, "Unknown"),
+                "min": minimum,
+                "max": maximum,
+                "default": default,
+                "enumValues": enum_values,
+            })
+        return json.dumps(info, default=str)
+
 # cameraService = CameraService()
 # cameraService.preview_full()
 #
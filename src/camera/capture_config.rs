@@ -0,0 +1,207 @@
+use crate::camera::{
+    CameraMode, Resolution, SensorMode, DEFAULT_PREVIEW_HEIGHT, DEFAULT_PREVIEW_WIDTH,
+};
+use jpeg_encoder::ColorType;
+use pyo3::types::{PyDict, PyDictMethods};
+use pyo3::{Bound, Python};
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_STILL_WIDTH: u32 = 3280;
+pub const DEFAULT_STILL_HEIGHT: u32 = 2464;
+
+/// Main stream pixel format, named as in Picamera2
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    Bgr888,
+    Rgb888,
+    Xbgr8888,
+    Xrgb8888,
+}
+
+impl PixelFormat {
+    pub fn python_name(&self) -> &'static str {
+        match self {
+            PixelFormat::Bgr888 => "BGR888",
+            PixelFormat::Rgb888 => "RGB888",
+            PixelFormat::Xbgr8888 => "XBGR8888",
+            PixelFormat::Xrgb8888 => "XRGB8888",
+        }
+    }
+
+    /// Byte order of the captured array. Picamera2 names formats by the
+    /// little endian word, so BGR888 is stored as RGB.
+    pub fn color_type(&self) -> ColorType {
+        match self {
+            PixelFormat::Bgr888 => ColorType::Rgb,
+            PixelFormat::Rgb888 => ColorType::Bgr,
+            PixelFormat::Xbgr8888 => ColorType::Rgba,
+            PixelFormat::Xrgb8888 => ColorType::Bgra,
+        }
+    }
+
+    /// Byte offsets of red, green and blue within a pixel, and bytes per pixel
+    pub fn rgb_offsets(&self) -> ([usize; 3], usize) {
+        match self.color_type() {
+            ColorType::Rgb => ([0, 1, 2], 3),
+            ColorType::Bgr => ([2, 1, 0], 3),
+            ColorType::Rgba => ([0, 1, 2], 4),
+            _ => ([2, 1, 0], 4),
+        }
+    }
+}
+
+/// Colour space, maps to libcamera's `ColorSpace` constructors
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColourSpace {
+    Sycc,
+    Smpte170m,
+    Rec709,
+}
+
+impl ColourSpace {
+    /// Name of the matching libcamera `ColorSpace` constructor
+    pub fn python_name(&self) -> &'static str {
+        match self {
+            ColourSpace::Sycc => "Sycc",
+            ColourSpace::Smpte170m => "Smpte170m",
+            ColourSpace::Rec709 => "Rec709",
+        }
+    }
+}
+
+/// Camera configuration of a camera mode.
+/// Still configuration is used for pictures, video configuration for preview and clips,
+/// which choose their own size and sensor mode.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CaptureConfig {
+    pub resolution: Resolution,
+    pub format: PixelFormat,
+    /// None to let libcamera choose
+    pub sensor_mode: Option<SensorMode>,
+    pub buffer_count: u32,
+    pub colour_space: ColourSpace,
+}
+
+impl CaptureConfig {
+    /// Fills in defaults of the camera mode and validates requested values against
+    /// available sensor modes. Sensor mode is an index into the sensor mode list.
+    pub fn new(
+        camera_mode: &CameraMode,
+        resolution: Option<Resolution>,
+        format: Option<PixelFormat>,
+        sensor_mode: Option<usize>,
+        buffer_count: Option<u32>,
+        colour_space: Option<ColourSpace>,
+        sensor_modes: &[SensorMode],
+    ) -> Result<Self, anyhow::Error> {
+        let default = CaptureConfig::default_for(camera_mode, sensor_modes);
+
+        let resolution = resolution.unwrap_or(default.resolution);
+        let sensor_mode = match sensor_mode {
+            Some(index) => Some(
+                sensor_modes
+                    .get(index)
+                    .ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "Invalid sensor mode {}, camera has {} sensor modes",
+                            index,
+                            sensor_modes.len()
+                        )
+                    })?
+                    .clone(),
+            ),
+            None => SensorMode::find_best(sensor_modes, resolution.width, resolution.height, None)
+                .cloned(),
+        };
+
+        let capture_config = CaptureConfig {
+            resolution,
+            format: format.unwrap_or(default.format),
+            sensor_mode,
+            buffer_count: buffer_count.unwrap_or(default.buffer_count),
+            colour_space: colour_space.unwrap_or(default.colour_space),
+        };
+        capture_config.validate(sensor_modes)?;
+        Ok(capture_config)
+    }
+
+    /// Checks the config against the camera's sensor modes.
+    /// Stored configs can be stale, if the camera was replaced.
+    pub fn validate(&self, sensor_modes: &[SensorMode]) -> Result<(), anyhow::Error> {
+        if self.resolution.width == 0 || self.resolution.height == 0 {
+            anyhow::bail!(
+                "Invalid resolution {}x{}",
+                self.resolution.width,
+                self.resolution.height
+            );
+        }
+        if self.buffer_count == 0 {
+            anyhow::bail!("Invalid buffer count {}", self.buffer_count);
+        }
+        if let Some(sensor_mode) = &self.sensor_mode
+            && !sensor_modes.contains(sensor_mode)
+        {
+            anyhow::bail!(
+                "Camera has no {}x{} {} bit sensor mode",
+                sensor_mode.width,
+                sensor_mode.height,
+                sensor_mode.bit_depth
+            );
+        }
+        Ok(())
+    }
+
+    /// Default configuration of the camera mode
+    pub fn default_for(camera_mode: &CameraMode, sensor_modes: &[SensorMode]) -> Self {
+        let (resolution, format, buffer_count, colour_space) = match camera_mode {
+            CameraMode::Still => (
+                Resolution {
+                    width: DEFAULT_STILL_WIDTH,
+                    height: DEFAULT_STILL_HEIGHT,
+                },
+                PixelFormat::Bgr888,
+                3,
+                ColourSpace::Sycc,
+            ),
+            CameraMode::Video => (
+                Resolution {
+                    width: DEFAULT_PREVIEW_WIDTH,
+                    height: DEFAULT_PREVIEW_HEIGHT,
+                },
+                PixelFormat::Xbgr8888,
+                6,
+                ColourSpace::Rec709,
+            ),
+        };
+        let sensor_mode =
+            SensorMode::find_best(sensor_modes, resolution.width, resolution.height, None).cloned();
+
+        CaptureConfig {
+            resolution,
+            format,
+            sensor_mode,
+            buffer_count,
+            colour_space,
+        }
+    }
+
+    pub fn to_pydict<'py>(&self, py: Python<'py>) -> Result<Bound<'py, PyDict>, anyhow::Error> {
+        let dict = PyDict::new(py);
+        dict.set_item("size", (self.resolution.width, self.resolution.height))?;
+        dict.set_item("format", self.format.python_name())?;
+        dict.set_item(
+            "sensor_size",
+            self.sensor_mode
+                .as_ref()
+                .map(|mode| (mode.width, mode.height)),
+        )?;
+        dict.set_item(
+            "bit_depth",
+            self.sensor_mode.as_ref().map(|mode| mode.bit_depth),
+        )?;
+        dict.set_item("buffer_count", self.buffer_count)?;
+        dict.set_item("colour_space", self.colour_space.python_name())?;
+        Ok(dict)
+    }
+}
@@ -0,0 +1,555 @@
+use crate::camera::{PixelFormat, Point};
+use std::collections::{HashMap, VecDeque};
+
+/// Corners are detected on a copy downscaled to about this width, then refined in full resolution
+const DETECTION_WIDTH: usize = 1024;
+/// Offset of the second differences of the saddle response, detection pixels
+const SADDLE_STEP: isize = 2;
+/// Candidates must reach this fraction of the strongest saddle response
+const RESPONSE_THRESHOLD: f32 = 0.05;
+/// Half size of the non-maximum suppression window, detection pixels
+const SUPPRESSION_RADIUS: isize = 3;
+/// Radius of the circle sampled around candidates, detection pixels. Squares must be larger.
+const RING_RADIUS: f32 = 4.0;
+const RING_SAMPLES: usize = 16;
+/// Smallest brightness difference between light and dark squares around a corner
+const MIN_RING_CONTRAST: f32 = 24.0;
+/// Largest distance of a corner from its predicted position, fraction of the corner spacing
+const GRID_TOLERANCE: f32 = 0.3;
+/// Strongest candidates tried as the first corner of the grid
+const MAX_SEEDS: usize = 30;
+const REFINE_ITERATIONS: usize = 20;
+/// Refinement stops once the corner moves less than this, pixels
+const REFINE_EPSILON: f32 = 0.01;
+
+/// 8 bit luma of a frame
+pub struct GrayImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl GrayImage {
+    pub fn new(pixels: &[u8], width: usize, height: usize, format: PixelFormat) -> Self {
+        let (offsets, channels) = format.rgb_offsets();
+        let pixels = pixels
+            .chunks_exact(channels)
+            .take(width * height)
+            .map(|pixel| {
+                let [r, g, b] = offsets.map(|offset| pixel[offset] as u32);
+                ((r * 77 + g * 150 + b * 29) >> 8) as u8
+            })
+            .collect();
+        GrayImage {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Value at the pixel, clamped to the frame
+    pub fn value(&self, x: isize, y: isize) -> f32 {
+        let x = x.clamp(0, self.width as isize - 1) as usize;
+        let y = y.clamp(0, self.height as isize - 1) as usize;
+        self.pixels[y * self.width + x] as f32
+    }
+
+    /// Box filtered copy, each side divided by factor
+    pub fn downscaled(&self, factor: usize) -> GrayImage {
+        let image = self.downscale(factor);
+        GrayImage {
+            width: image.width,
+            height: image.height,
+            pixels: image
+                .pixels
+                .iter()
+                .map(|&value| value.round() as u8)
+                .collect(),
+        }
+    }
+
+    fn downscale(&self, factor: usize) -> FloatImage {
+        let width = self.width / factor;
+        let height = self.height / factor;
+        let count = (factor * factor) as f32;
+        let mut pixels = vec![0.0; width * height];
+        for (y, row) in pixels.chunks_exact_mut(width).enumerate() {
+            for (x, pixel) in row.iter_mut().enumerate() {
+                let mut sum = 0u32;
+                for source_y in y * factor..(y + 1) * factor {
+                    let start = source_y * self.width + x * factor;
+                    sum += self.pixels[start..start + factor]
+                        .iter()
+                        .map(|&value| value as u32)
+                        .sum::<u32>();
+                }
+                *pixel = sum as f32 / count;
+            }
+        }
+        FloatImage {
+            width,
+            height,
+            pixels,
+        }
+    }
+}
+
+struct FloatImage {
+    width: usize,
+    height: usize,
+    pixels: Vec<f32>,
+}
+
+impl FloatImage {
+    fn get(&self, x: isize, y: isize) -> f32 {
+        let x = x.clamp(0, self.width as isize - 1) as usize;
+        let y = y.clamp(0, self.height as isize - 1) as usize;
+        self.pixels[y * self.width + x]
+    }
+
+    fn sample(&self, x: f32, y: f32) -> f32 {
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as isize, y0 as isize);
+        let top = self.get(x0, y0) * (1.0 - tx) + self.get(x0 + 1, y0) * tx;
+        let bottom = self.get(x0, y0 + 1) * (1.0 - tx) + self.get(x0 + 1, y0 + 1) * tx;
+        top * (1.0 - ty) + bottom * ty
+    }
+
+    /// Two passes of a 3x3 box filter
+    fn blur(&self) -> FloatImage {
+        self.box_filter(true)
+            .box_filter(false)
+            .box_filter(true)
+            .box_filter(false)
+    }
+
+    fn box_filter(&self, horizontal: bool) -> FloatImage {
+        let mut pixels = vec![0.0; self.pixels.len()];
+        for y in 0..self.height as isize {
+            for x in 0..self.width as isize {
+                let (dx, dy) = if horizontal { (1, 0) } else { (0, 1) };
+                pixels[y as usize * self.width + x as usize] =
+                    (self.get(x - dx, y - dy) + self.get(x, y) + self.get(x + dx, y + dy)) / 3.0;
+            }
+        }
+        FloatImage {
+            width: self.width,
+            height: self.height,
+            pixels,
+        }
+    }
+}
+
+struct Candidate {
+    position: Point,
+    response: f32,
+}
+
+/// Finds the inner corners of a checkerboard with columns x rows inner corners.
+/// Returns corners in frame pixels row by row, starting from the corner nearest the top left.
+pub fn detect_checkerboard(
+    image: &GrayImage,
+    columns: usize,
+    rows: usize,
+) -> Result<Vec<Point>, anyhow::Error> {
+    if columns < 2 || rows < 2 {
+        anyhow::bail!("Pattern must have at least 2 inner corners in each direction");
+    }
+    let factor = image.width.div_ceil(DETECTION_WIDTH).max(1);
+    let small = image.downscale(factor).blur();
+    let candidates = saddle_candidates(&small);
+    if candidates.len() < columns * rows {
+        anyhow::bail!(
+            "Found {} corner candidates, pattern has {} corners",
+            candidates.len(),
+            columns * rows
+        );
+    }
+
+    let grid = (0..candidates.len().min(MAX_SEEDS))
+        .find_map(|seed| grow_grid(&candidates, seed, columns, rows))
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "Checkerboard with {}x{} inner corners not found",
+                columns,
+                rows
+            )
+        })?;
+    // Detection pixel centres in frame pixels
+    let corners: Vec<Point> = grid
+        .iter()
+        .map(|&index| {
+            let position = candidates[index].position;
+            Point {
+                x: (position.x + 0.5) * factor as f32 - 0.5,
+                y: (position.y + 0.5) * factor as f32 - 0.5,
+            }
+        })
+        .collect();
+    let corners = normalize_order(&corners, columns, rows);
+
+    let spacing = (0..rows)
+        .flat_map(|row| (1..columns).map(move |column| row * columns + column))
+        .map(|index| distance(corners[index - 1], corners[index]))
+        .fold(f32::INFINITY, f32::min);
+    let radius = ((spacing / 4.0) as isize).max(2);
+    Ok(corners
+        .iter()
+        .map(|&corner| refine_corner(image, corner, radius))
+        .collect())
+}
+
+/// Local maxima of the Hessian saddle response, strongest first
+fn saddle_candidates(image: &FloatImage) -> Vec<Candidate> {
+    let (width, height) = (image.width as isize, image.height as isize);
+    let step = SADDLE_STEP;
+    let mut response = vec![0.0f32; image.pixels.len()];
+    for y in step..height - step {
+        for x in step..width - step {
+            let at = |dx: isize, dy: isize| image.get(x + dx, y + dy);
+            let dxx = at(step, 0) - 2.0 * at(0, 0) + at(-step, 0);
+            let dyy = at(0, step) - 2.0 * at(0, 0) + at(0, -step);
+            let dxy = (at(step, step) - at(step, -step) - at(-step, step) + at(-step, -step)) / 4.0;
+            response[(y * width + x) as usize] = (dxy * dxy - dxx * dyy).max(0.0);
+        }
+    }
+    let threshold = response.iter().fold(0.0f32, |max, &value| max.max(value)) * RESPONSE_THRESHOLD;
+
+    let radius = SUPPRESSION_RADIUS;
+    let mut candidates = Vec::new();
+    for y in radius..height - radius {
+        for x in radius..width - radius {
+            let index = (y * width + x) as usize;
+            let value = response[index];
+            if value <= threshold {
+                continue;
+            }
+            // Ties are kept by the first pixel
+            let is_maximum = (-radius..=radius).all(|dy| {
+                (-radius..=radius).all(|dx| {
+                    let other = ((y + dy) * width + x + dx) as usize;
+                    response[other] < value || (response[other] == value && other >= index)
+                })
+            });
+            let position = Point {
+                x: x as f32,
+                y: y as f32,
+            };
+            if is_maximum && is_checkerboard_corner(image, position) {
+                candidates.push(Candidate {
+                    position,
+                    response: value,
+                });
+            }
+        }
+    }
+    candidates.sort_by(|a, b| b.response.total_cmp(&a.response));
+    candidates
+}
+
+/// A circle around a checkerboard corner crosses light and dark squares twice each
+fn is_checkerboard_corner(image: &FloatImage, position: Point) -> bool {
+    let samples: Vec<f32> = (0..RING_SAMPLES)
+        .map(|sample| {
+            let angle = sample as f32 * std::f32::consts::TAU / RING_SAMPLES as f32;
+            image.sample(
+                position.x + RING_RADIUS * angle.cos(),
+                position.y + RING_RADIUS * angle.sin(),
+            )
+        })
+        .collect();
+    let min = samples
+        .iter()
+        .fold(f32::INFINITY, |min, &value| min.min(value));
+    let max = samples.iter().fold(0.0f32, |max, &value| max.max(value));
+    if max - min < MIN_RING_CONTRAST {
+        return false;
+    }
+    let middle = (min + max) / 2.0;
+    let transitions = (0..RING_SAMPLES)
+        .filter(|&sample| {
+            let next = (sample + 1) % RING_SAMPLES;
+            (samples[sample] > middle) != (samples[next] > middle)
+        })
+        .count();
+    transitions == 4
+}
+
+/// Grows a grid of corners from the seed by predicting each neighbour from the corner spacing.
+/// Returns candidate indices row by row if exactly columns x rows corners are found.
+fn grow_grid(
+    candidates: &[Candidate],
+    seed: usize,
+    columns: usize,
+    rows: usize,
+) -> Option<Vec<usize>> {
+    let position = |index: usize| candidates[index].position;
+    let origin = position(seed);
+
+    // Nearest neighbours of the seed give the two grid directions
+    let mut nearest: Vec<usize> = (0..candidates.len()).filter(|&i| i != seed).collect();
+    nearest
+        .sort_by(|&a, &b| distance(origin, position(a)).total_cmp(&distance(origin, position(b))));
+    let u = subtract(position(*nearest.first()?), origin);
+    let v = nearest
+        .iter()
+        .take(4)
+        .skip(1)
+        .map(|&index| subtract(position(index), origin))
+        .find(|&v| (dot(u, v) / (length(u) * length(v))).abs() < 0.5)?;
+
+    let mut grid: HashMap<(i32, i32), usize> = HashMap::from([((0, 0), seed)]);
+    let mut used = vec![false; candidates.len()];
+    used[seed] = true;
+    let mut queue = VecDeque::from([(0, 0)]);
+    while let Some((i, j)) = queue.pop_front() {
+        let current = position(grid[&(i, j)]);
+        for (di, dj, fallback) in [(1, 0, u), (-1, 0, negate(u)), (0, 1, v), (0, -1, negate(v))] {
+            let next = (i + di, j + dj);
+            if grid.contains_key(&next) {
+                continue;
+            }
+            // Spacing changes over the board with perspective, so the nearest known step is used
+            let step = grid
+                .get(&(i - di, j - dj))
+                .map(|&previous| subtract(current, position(previous)))
+                .or_else(|| {
+                    [(dj, di), (-dj, -di)].into_iter().find_map(|(oi, oj)| {
+                        let from = grid.get(&(i + oi, j + oj))?;
+                        let to = grid.get(&(i + oi + di, j + oj + dj))?;
+                        Some(subtract(position(*to), position(*from)))
+                    })
+                })
+                .unwrap_or(fallback);
+            let predicted = add(current, step);
+            let tolerance = length(step) * GRID_TOLERANCE;
+            let found = (0..candidates.len())
+                .filter(|&index| !used[index])
+                .min_by(|&a, &b| {
+                    distance(predicted, position(a)).total_cmp(&distance(predicted, position(b)))
+                })
+                .filter(|&index| distance(predicted, position(index)) <= tolerance);
+            if let Some(index) = found {
+                used[index] = true;
+                grid.insert(next, index);
+                queue.push_back(next);
+                if grid.len() > columns * rows {
+                    return None;
+                }
+            }
+        }
+    }
+    if grid.len() != columns * rows {
+        return None;
+    }
+
+    let min_i = grid.keys().map(|&(i, _)| i).min()?;
+    let max_i = grid.keys().map(|&(i, _)| i).max()?;
+    let min_j = grid.keys().map(|&(_, j)| j).min()?;
+    let max_j = grid.keys().map(|&(_, j)| j).max()?;
+    let size = ((max_i - min_i + 1) as usize, (max_j - min_j + 1) as usize);
+    let transpose = if size == (columns, rows) {
+        false
+    } else if size == (rows, columns) {
+        true
+    } else {
+        return None;
+    };
+    (0..rows as i32)
+        .flat_map(|row| (0..columns as i32).map(move |column| (column, row)))
+        .map(|(column, row)| {
+            let key = if transpose {
+                (min_i + row, min_j + column)
+            } else {
+                (min_i + column, min_j + row)
+            };
+            grid.get(&key).copied()
+        })
+        .collect()
+}
+
+/// Orders the grid to start at the corner nearest the top left, rows as horizontal as possible
+fn normalize_order(corners: &[Point], columns: usize, rows: usize) -> Vec<Point> {
+    let transposes: &[bool] = if columns == rows {
+        &[false, true]
+    } else {
+        &[false]
+    };
+    let mut orderings = Vec::new();
+    for &transpose in transposes {
+        for flip_columns in [false, true] {
+            for flip_rows in [false, true] {
+                let ordering: Vec<Point> = (0..rows)
+                    .flat_map(|row| (0..columns).map(move |column| (column, row)))
+                    .map(|(column, row)| {
+                        let (column, row) = if transpose {
+                            (row, column)
+                        } else {
+                            (column, row)
+                        };
+                        let column = if flip_columns {
+                            columns - 1 - column
+                        } else {
+                            column
+                        };
+                        let row = if flip_rows { rows - 1 - row } else { row };
+                        corners[row * columns + column]
+                    })
+                    .collect();
+                orderings.push(ordering);
+            }
+        }
+    }
+    let score = |ordering: &Vec<Point>| {
+        let row = subtract(ordering[1], ordering[0]);
+        (ordering[0].x + ordering[0].y, row.y.abs() - row.x.abs())
+    };
+    orderings
+        .into_iter()
+        .min_by(|a, b| {
+            let (a, b) = (score(a), score(b));
+            a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1))
+        })
+        .unwrap_or_default()
+}
+
+/// Moves the corner to where image gradients in the window are orthogonal to the offset from it.
+/// Works for saddle corners of a checkerboard and the outer corners of squares.
+pub fn refine_corner(image: &GrayImage, corner: Point, radius: isize) -> Point {
+    let mut refined = corner;
+    for _ in 0..REFINE_ITERATIONS {
+        let (cx, cy) = (refined.x.round() as isize, refined.y.round() as isize);
+        let (mut gxx, mut gxy, mut gyy) = (0.0f64, 0.0f64, 0.0f64);
+        let (mut bx, mut by) = (0.0f64, 0.0f64);
+        for y in cy - radius..=cy + radius {
+            for x in cx - radius..=cx + radius {
+                let gx = ((image.value(x + 1, y) - image.value(x - 1, y)) / 2.0) as f64;
+                let gy = ((image.value(x, y + 1) - image.value(x, y - 1)) / 2.0) as f64;
+                gxx += gx * gx;
+                gxy += gx * gy;
+                gyy += gy * gy;
+                bx += gx * gx * x as f64 + gx * gy * y as f64;
+                by += gx * gy * x as f64 + gy * gy * y as f64;
+            }
+        }
+        let determinant = gxx * gyy - gxy * gxy;
+        if determinant.abs() < 1e-9 {
+            break;
+        }
+        let next = Point {
+            x: ((gyy * bx - gxy * by) / determinant) as f32,
+            y: ((gxx * by - gxy * bx) / determinant) as f32,
+        };
+        // Diverged out of the window, keep the last position
+        if distance(next, corner) > radius as f32 {
+            break;
+        }
+        let moved = distance(next, refined);
+        refined = next;
+        if moved < REFINE_EPSILON {
+            break;
+        }
+    }
+    refined
+}
+
+fn add(a: Point, b: Point) -> Point {
+    Point {
+        x: a.x + b.x,
+        y: a.y + b.y,
+    }
+}
+
+fn subtract(a: Point, b: Point) -> Point {
+    Point {
+        x: a.x - b.x,
+        y: a.y - b.y,
+    }
+}
+
+fn negate(a: Point) -> Point {
+    Point { x: -a.x, y: -a.y }
+}
+
+fn dot(a: Point, b: Point) -> f32 {
+    a.x * b.x + a.y * b.y
+}
+
+fn length(a: Point) -> f32 {
+    dot(a, a).sqrt()
+}
+
+fn distance(a: Point, b: Point) -> f32 {
+    length(subtract(a, b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SQUARE: usize = 40;
+    const ORIGIN_X: usize = 100;
+    const ORIGIN_Y: usize = 80;
+
+    /// Board of squares_x by squares_y squares on a light background
+    fn render_checkerboard(
+        width: usize,
+        height: usize,
+        squares_x: usize,
+        squares_y: usize,
+    ) -> GrayImage {
+        let pixels = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let on_board = (ORIGIN_X..ORIGIN_X + squares_x * SQUARE).contains(&x)
+                    && (ORIGIN_Y..ORIGIN_Y + squares_y * SQUARE).contains(&y);
+                if on_board && ((x - ORIGIN_X) / SQUARE + (y - ORIGIN_Y) / SQUARE).is_multiple_of(2)
+                {
+                    30
+                } else {
+                    220
+                }
+            })
+            .collect();
+        GrayImage {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    #[test]
+    fn detects_rendered_checkerboard() {
+        let (columns, rows) = (7, 5);
+        let image = render_checkerboard(640, 480, columns + 1, rows + 1);
+
+        let corners = detect_checkerboard(&image, columns, rows).unwrap();
+
+        assert_eq!(corners.len(), columns * rows);
+        for (index, corner) in corners.iter().enumerate() {
+            // Square edges fall between pixels, pixel centres are integers
+            let expected = Point {
+                x: (ORIGIN_X + (index % columns + 1) * SQUARE) as f32 - 0.5,
+                y: (ORIGIN_Y + (index / columns + 1) * SQUARE) as f32 - 0.5,
+            };
+            assert!(
+                distance(*corner, expected) < 0.5,
+                "corner {} at {:?}, expected {:?}",
+                index,
+                corner,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_missing_checkerboard() {
+        let image = GrayImage {
+            width: 320,
+            height: 240,
+            pixels: vec![128; 320 * 240],
+        };
+        assert!(detect_checkerboard(&image, 7, 5).is_err());
+    }
+}
@@ -0,0 +1,119 @@
+use crate::camera::{Resolution, SensorMode};
+use pyo3::types::{PyDict, PyDictMethods};
+use pyo3::{Bound, Python};
+use serde::{Deserialize, Serialize};
+
+/// Longest clip that can be recorded, frames are written to the sd card as they arrive
+pub const MAX_CLIP_DURATION_MILLIS: u64 = 5 * 60 * 1000;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ClipFormat {
+    /// Concatenated jpeg frames
+    #[default]
+    Mjpeg,
+    /// Concatenated RGB888 frames
+    Raw,
+}
+
+impl ClipFormat {
+    pub const ALL: [ClipFormat; 2] = [ClipFormat::Mjpeg, ClipFormat::Raw];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ClipFormat::Mjpeg => "mjpeg",
+            ClipFormat::Raw => "raw",
+        }
+    }
+}
+
+/// Video clip configuration, after validation against the sensor modes
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ClipConfig {
+    pub resolution: Resolution,
+    pub frame_rate: f32,
+    pub format: ClipFormat,
+    pub sensor_mode: SensorMode,
+}
+
+impl ClipConfig {
+    pub fn new(
+        resolution: Resolution,
+        frame_rate: f32,
+        duration_millis: u64,
+        format: Option<ClipFormat>,
+        sensor_modes: &[SensorMode],
+    ) -> Result<Self, anyhow::Error> {
+        if resolution.width == 0 || resolution.height == 0 {
+            anyhow::bail!(
+                "Invalid resolution {}x{}",
+                resolution.width,
+                resolution.height
+            );
+        }
+        if !frame_rate.is_finite() || frame_rate <= 0.0 {
+            anyhow::bail!("Invalid frame rate {}", frame_rate);
+        }
+        if duration_millis == 0 || duration_millis > MAX_CLIP_DURATION_MILLIS {
+            anyhow::bail!(
+                "Duration must be between 1 and {} ms, got {}",
+                MAX_CLIP_DURATION_MILLIS,
+                duration_millis
+            );
+        }
+
+        let sensor_mode = SensorMode::find_best(
+            sensor_modes,
+            resolution.width,
+            resolution.height,
+            Some(frame_rate),
+        )
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "No sensor mode supports {}x{} at {} fps",
+                resolution.width,
+                resolution.height,
+                frame_rate
+            )
+        })?
+        .clone();
+
+        Ok(ClipConfig {
+            resolution,
+            frame_rate,
+            format: format.unwrap_or_default(),
+            sensor_mode,
+        })
+    }
+
+    /// Frames a clip of this duration should have at the fixed frame rate
+    pub fn expected_frame_count(&self, duration_millis: u64) -> usize {
+        (self.frame_rate as f64 * duration_millis as f64 / 1000.0).round() as usize
+    }
+
+    pub fn to_pydict<'py>(&self, py: Python<'py>) -> Result<Bound<'py, PyDict>, anyhow::Error> {
+        let dict = PyDict::new(py);
+        dict.set_item("size", (self.resolution.width, self.resolution.height))?;
+        dict.set_item("frame_rate", self.frame_rate)?;
+        dict.set_item("format", self.format.extension())?;
+        dict.set_item(
+            "sensor_size",
+            (self.sensor_mode.width, self.sensor_mode.height),
+        )?;
+        dict.set_item("bit_depth", self.sensor_mode.bit_depth)?;
+        Ok(dict)
+    }
+}
+
+/// Sidecar saved next to a clip
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ClipMetadata {
+    #[serde(flatten)]
+    pub clip_config: ClipConfig,
+    /// Requested start, monotonic nanoseconds
+    pub start_monotonic_nanos: i64,
+    pub duration_millis: u64,
+    /// Sensor timestamp of each frame, monotonic nanoseconds
+    pub frame_timestamps: Vec<i64>,
+}
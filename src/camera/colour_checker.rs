@@ -0,0 +1,443 @@
+use crate::camera::{correct_colour, delta_e, fit_colour_matrix, PixelFormat};
+use serde::{Deserialize, Serialize};
+
+pub const CHECKER_COLUMNS: usize = 6;
+pub const CHECKER_ROWS: usize = 4;
+/// 8 bit sRGB of the 24 patch colour checker, row by row from dark skin to black
+pub const CHECKER_REFERENCE: [[f32; 3]; CHECKER_COLUMNS * CHECKER_ROWS] = [
+    [115.0, 82.0, 68.0],
+    [194.0, 150.0, 130.0],
+    [98.0, 122.0, 157.0],
+    [87.0, 108.0, 67.0],
+    [133.0, 128.0, 177.0],
+    [103.0, 189.0, 170.0],
+    [214.0, 126.0, 44.0],
+    [80.0, 91.0, 166.0],
+    [193.0, 90.0, 99.0],
+    [94.0, 60.0, 108.0],
+    [157.0, 188.0, 64.0],
+    [224.0, 163.0, 46.0],
+    [56.0, 61.0, 150.0],
+    [70.0, 148.0, 73.0],
+    [175.0, 54.0, 60.0],
+    [231.0, 199.0, 31.0],
+    [187.0, 86.0, 149.0],
+    [8.0, 133.0, 161.0],
+    [243.0, 243.0, 242.0],
+    [200.0, 200.0, 200.0],
+    [160.0, 160.0, 160.0],
+    [122.0, 122.0, 121.0],
+    [85.0, 85.0, 85.0],
+    [52.0, 52.0, 52.0],
+];
+
+/// Frames are downscaled to at most this width for detection
+const DETECTION_WIDTH: usize = 640;
+/// Difference to neighbouring pixels, summed over channels, below which pixels are flat
+const FLAT_THRESHOLD: f32 = 12.0;
+/// Smallest flat region counted as a patch, in downscaled pixels
+const MIN_PATCH_AREA: usize = 30;
+/// Share of the bounding box a patch has to fill
+const MIN_PATCH_FILL: f32 = 0.6;
+/// Patch centres must be within this share of the pitch from the lattice
+const LATTICE_TOLERANCE: f32 = 0.25;
+/// Lattice positions with a patch needed to accept a detection
+const MIN_LATTICE_MATCHES: usize = 20;
+/// Share of the pitch averaged around each patch centre
+const SAMPLE_SIZE: f32 = 0.4;
+
+/// Position in frame pixels, or normalized to 0..1 of the frame where noted
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Point {
+    pub x: f32,
+    pub y: f32,
+}
+
+/// Colour checker found in a frame
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ColourChecker {
+    /// Patch centres in frame pixels, in reference order
+    pub centres: Vec<Point>,
+    /// Mean 8 bit sRGB of each patch, in reference order
+    pub colours: Vec<[f32; 3]>,
+}
+
+/// Fitted matrix with its error against the reference colours
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ColourFit {
+    pub matrix: [[f32; 3]; 3],
+    /// CIE76 colour difference of each patch after correction
+    pub patch_errors: Vec<f32>,
+    pub mean_error: f32,
+    pub max_error: f32,
+}
+
+impl ColourFit {
+    pub fn new(colours: &[[f32; 3]]) -> Result<Self, anyhow::Error> {
+        let matrix = fit_colour_matrix(colours, &CHECKER_REFERENCE)?;
+        let patch_errors: Vec<f32> = colours
+            .iter()
+            .zip(CHECKER_REFERENCE)
+            .map(|(colour, reference)| delta_e(correct_colour(&matrix, *colour), reference))
+            .collect();
+        let mean_error = patch_errors.iter().sum::<f32>() / patch_errors.len() as f32;
+        let max_error = patch_errors.iter().copied().fold(0.0, f32::max);
+
+        Ok(ColourFit {
+            matrix,
+            patch_errors,
+            mean_error,
+            max_error,
+        })
+    }
+}
+
+/// Frame converted to RGB, used for detection and sampling
+pub struct RgbImage {
+    width: usize,
+    height: usize,
+    pixels: Vec<[u8; 3]>,
+}
+
+impl RgbImage {
+    pub fn new(pixels: &[u8], width: usize, height: usize, format: PixelFormat) -> Self {
+        let (offsets, channels) = format.rgb_offsets();
+        let pixels = pixels
+            .chunks_exact(channels)
+            .take(width * height)
+            .map(|pixel| offsets.map(|offset| pixel[offset]))
+            .collect();
+        RgbImage {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Box filtered copy, each side divided by factor
+    fn downscale(&self, factor: usize) -> RgbImage {
+        let width = self.width / factor;
+        let height = self.height / factor;
+        let mut pixels = vec![[0; 3]; width * height];
+        for (y, row) in pixels.chunks_exact_mut(width).enumerate() {
+            for (x, pixel) in row.iter_mut().enumerate() {
+                let mut sum = [0u32; 3];
+                for source_y in y * factor..(y + 1) * factor {
+                    let start = source_y * self.width + x * factor;
+                    for source in &self.pixels[start..start + factor] {
+                        for channel in 0..3 {
+                            sum[channel] += source[channel] as u32;
+                        }
+                    }
+                }
+                let count = (factor * factor) as u32;
+                *pixel = sum.map(|sum| (sum / count) as u8);
+            }
+        }
+        RgbImage {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Mean colour of a square around a point, clipped to the frame
+    fn sample(&self, centre: Point, half_size: f32) -> Result<[f32; 3], anyhow::Error> {
+        let range = |centre: f32, size: usize| {
+            let start = (centre - half_size).max(0.0) as usize;
+            let end = ((centre + half_size).ceil() as usize).min(size);
+            start..end
+        };
+        let (xs, ys) = (range(centre.x, self.width), range(centre.y, self.height));
+        if xs.is_empty() || ys.is_empty() {
+            anyhow::bail!("Patch at {:?} is outside of the frame", centre);
+        }
+
+        let mut sum = [0.0; 3];
+        for y in ys.clone() {
+            for pixel in &self.pixels[y * self.width + xs.start..y * self.width + xs.end] {
+                for channel in 0..3 {
+                    sum[channel] += pixel[channel] as f32;
+                }
+            }
+        }
+        let count = (xs.len() * ys.len()) as f32;
+        Ok(sum.map(|sum| sum / count))
+    }
+}
+
+/// Flat region of the downscaled frame
+#[derive(Debug, Clone, Copy)]
+struct Patch {
+    centre: Point,
+    area: usize,
+}
+
+/// Finds the colour checker patches in the frame
+pub fn detect_colour_checker(image: &RgbImage) -> Result<ColourChecker, anyhow::Error> {
+    let factor = image.width.div_ceil(DETECTION_WIDTH).max(1);
+    let small = image.downscale(factor);
+    let patches = find_patches(&small);
+
+    // Try every pair of neighbouring patches as one step of the patch lattice
+    let mut best: Option<(usize, Point, Point, Point)> = None;
+    for a in &patches {
+        let pitch_min = (a.area as f32).sqrt() * 1.05;
+        let pitch_max = (a.area as f32).sqrt() * 2.0;
+        for b in &patches {
+            let similar = b.area * 2 >= a.area && b.area <= a.area * 2;
+            let step = Point {
+                x: b.centre.x - a.centre.x,
+                y: b.centre.y - a.centre.y,
+            };
+            let pitch = (step.x * step.x + step.y * step.y).sqrt();
+            if !similar || pitch < pitch_min || pitch > pitch_max {
+                continue;
+            }
+            for sign in [1.0, -1.0] {
+                let across = Point {
+                    x: -step.y * sign,
+                    y: step.x * sign,
+                };
+                for (columns, rows) in [
+                    (CHECKER_COLUMNS, CHECKER_ROWS),
+                    (CHECKER_ROWS, CHECKER_COLUMNS),
+                ] {
+                    let matches =
+                        count_lattice_matches(&patches, a.centre, step, across, columns, rows);
+                    if best.is_none_or(|(best_matches, ..)| matches > best_matches) {
+                        best = Some((matches, a.centre, step, across));
+                    }
+                }
+            }
+        }
+    }
+
+    let Some((_, origin, step, across)) =
+        best.filter(|(matches, ..)| *matches >= MIN_LATTICE_MATCHES)
+    else {
+        anyhow::bail!("No colour checker found, {} flat patches", patches.len());
+    };
+
+    // Lattice positions in frame pixels, then the patch order with the best fit
+    let scale = |point: Point| Point {
+        x: (point.x + 0.5) * factor as f32,
+        y: (point.y + 0.5) * factor as f32,
+    };
+    let lattice_point = |i: usize, j: usize| {
+        scale(Point {
+            x: origin.x + step.x * i as f32 + across.x * j as f32,
+            y: origin.y + step.y * i as f32 + across.y * j as f32,
+        })
+    };
+    let pitch = (step.x * step.x + step.y * step.y).sqrt() * factor as f32;
+
+    let mut candidates = Vec::new();
+    for portrait in [false, true] {
+        let (columns, rows) = if portrait {
+            (CHECKER_ROWS, CHECKER_COLUMNS)
+        } else {
+            (CHECKER_COLUMNS, CHECKER_ROWS)
+        };
+        // Skip layouts that don't fit the lattice
+        if count_lattice_matches(&patches, origin, step, across, columns, rows)
+            < MIN_LATTICE_MATCHES
+        {
+            continue;
+        }
+        for (flip_column, flip_row) in [(false, false), (true, false), (false, true), (true, true)]
+        {
+            let centres: Vec<Point> = (0..CHECKER_ROWS)
+                .flat_map(|row| (0..CHECKER_COLUMNS).map(move |column| (column, row)))
+                .map(|(column, row)| {
+                    let column = if flip_column {
+                        CHECKER_COLUMNS - 1 - column
+                    } else {
+                        column
+                    };
+                    let row = if flip_row {
+                        CHECKER_ROWS - 1 - row
+                    } else {
+                        row
+                    };
+                    if portrait {
+                        lattice_point(row, column)
+                    } else {
+                        lattice_point(column, row)
+                    }
+                })
+                .collect();
+            candidates.push(centres);
+        }
+    }
+
+    let mut best_checker: Option<(f32, ColourChecker)> = None;
+    for centres in candidates {
+        // Lattices partly outside of the frame or without a fit are not the checker
+        let Ok(checker) = sample_checker(image, centres, pitch) else {
+            continue;
+        };
+        let Ok(fit) = ColourFit::new(&checker.colours) else {
+            continue;
+        };
+        if best_checker
+            .as_ref()
+            .is_none_or(|(error, _)| fit.mean_error < *error)
+        {
+            best_checker = Some((fit.mean_error, checker));
+        }
+    }
+    best_checker
+        .map(|(_, checker)| checker)
+        .ok_or_else(|| anyhow::anyhow!("No colour checker found"))
+}
+
+/// Samples the patches from the centres of the four corner patches, normalized to 0..1 of
+/// the frame. Corners are dark skin, bluish green, black and white.
+pub fn colour_checker_from_corners(
+    image: &RgbImage,
+    corners: &[Point; 4],
+) -> Result<ColourChecker, anyhow::Error> {
+    let corners = corners.map(|corner| Point {
+        x: corner.x * image.width as f32,
+        y: corner.y * image.height as f32,
+    });
+    let [top_left, top_right, bottom_right, bottom_left] = corners;
+    let lerp = |a: Point, b: Point, t: f32| Point {
+        x: a.x + (b.x - a.x) * t,
+        y: a.y + (b.y - a.y) * t,
+    };
+
+    let mut centres = Vec::new();
+    for row in 0..CHECKER_ROWS {
+        let v = row as f32 / (CHECKER_ROWS - 1) as f32;
+        for column in 0..CHECKER_COLUMNS {
+            let u = column as f32 / (CHECKER_COLUMNS - 1) as f32;
+            let top = lerp(top_left, top_right, u);
+            let bottom = lerp(bottom_left, bottom_right, u);
+            centres.push(lerp(top, bottom, v));
+        }
+    }
+    let width = ((top_right.x - top_left.x).powi(2) + (top_right.y - top_left.y).powi(2)).sqrt();
+    let pitch = width / (CHECKER_COLUMNS - 1) as f32;
+
+    sample_checker(image, centres, pitch)
+}
+
+fn sample_checker(
+    image: &RgbImage,
+    centres: Vec<Point>,
+    pitch: f32,
+) -> Result<ColourChecker, anyhow::Error> {
+    let half_size = (pitch * SAMPLE_SIZE / 2.0).max(1.0);
+    let colours = centres
+        .iter()
+        .map(|centre| image.sample(*centre, half_size))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(ColourChecker { centres, colours })
+}
+
+fn count_lattice_matches(
+    patches: &[Patch],
+    origin: Point,
+    step: Point,
+    across: Point,
+    columns: usize,
+    rows: usize,
+) -> usize {
+    let pitch = (step.x * step.x + step.y * step.y).sqrt();
+    let tolerance = (pitch * LATTICE_TOLERANCE).powi(2);
+    let mut matches = 0;
+    for i in 0..columns {
+        for j in 0..rows {
+            let x = origin.x + step.x * i as f32 + across.x * j as f32;
+            let y = origin.y + step.y * i as f32 + across.y * j as f32;
+            let found = patches.iter().any(|patch| {
+                (patch.centre.x - x).powi(2) + (patch.centre.y - y).powi(2) <= tolerance
+            });
+            if found {
+                matches += 1;
+            }
+        }
+    }
+    matches
+}
+
+/// Connected regions of flat pixels, shaped like patches
+fn find_patches(image: &RgbImage) -> Vec<Patch> {
+    let (width, height) = (image.width, image.height);
+    let difference = |a: &[u8; 3], b: &[u8; 3]| {
+        (0..3)
+            .map(|channel| a[channel].abs_diff(b[channel]) as f32)
+            .sum::<f32>()
+    };
+    let flat: Vec<bool> = (0..width * height)
+        .map(|index| {
+            let (x, y) = (index % width, index / width);
+            let pixel = &image.pixels[index];
+            let right =
+                x + 1 < width && difference(pixel, &image.pixels[index + 1]) > FLAT_THRESHOLD;
+            let down =
+                y + 1 < height && difference(pixel, &image.pixels[index + width]) > FLAT_THRESHOLD;
+            !right && !down
+        })
+        .collect();
+
+    let mut visited = vec![false; width * height];
+    let mut patches = Vec::new();
+    let mut stack = Vec::new();
+    for start in 0..width * height {
+        if !flat[start] || visited[start] {
+            continue;
+        }
+        visited[start] = true;
+        stack.push(start);
+        let (mut area, mut sum_x, mut sum_y) = (0, 0.0, 0.0);
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (width, height, 0, 0);
+        while let Some(index) = stack.pop() {
+            let (x, y) = (index % width, index / width);
+            area += 1;
+            sum_x += x as f32;
+            sum_y += y as f32;
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+            let neighbours = [
+                (x > 0).then(|| index - 1),
+                (x + 1 < width).then(|| index + 1),
+                (y > 0).then(|| index - width),
+                (y + 1 < height).then(|| index + width),
+            ];
+            for neighbour in neighbours.into_iter().flatten() {
+                if flat[neighbour] && !visited[neighbour] {
+                    visited[neighbour] = true;
+                    stack.push(neighbour);
+                }
+            }
+        }
+
+        let box_width = max_x - min_x + 1;
+        let box_height = max_y - min_y + 1;
+        let fill = area as f32 / (box_width * box_height) as f32;
+        let aspect = box_width as f32 / box_height as f32;
+        // A checker has 24 patches, larger regions are background
+        let small_enough = area * CHECKER_COLUMNS * CHECKER_ROWS < width * height;
+        if area >= MIN_PATCH_AREA
+            && small_enough
+            && fill >= MIN_PATCH_FILL
+            && (0.5..=2.0).contains(&aspect)
+        {
+            patches.push(Patch {
+                centre: Point {
+                    x: sum_x / area as f32,
+                    y: sum_y / area as f32,
+                },
+                area,
+            });
+        }
+    }
+    patches
+}
@@ -0,0 +1,216 @@
+use crate::camera::{srgb_to_linear, PixelFormat};
+use serde::{Deserialize, Serialize};
+
+/// Levels of the linear to sRGB lookup table
+const ENCODE_LEVELS: usize = 4096;
+
+/// 3x3 colour correction matrix applied in linear light before JPEG encoding
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ColourCorrection {
+    /// Rows produce red, green and blue from linear camera red, green and blue
+    pub matrix: [[f32; 3]; 3],
+    /// Applied to captures when enabled
+    pub enabled: bool,
+    /// Mean CIE76 colour difference of the fitted patches
+    pub mean_error: f32,
+    /// Largest CIE76 colour difference of the fitted patches
+    pub max_error: f32,
+}
+
+impl ColourCorrection {
+    /// Corrects the pixels in place, alpha bytes are left unchanged
+    pub fn apply(&self, pixels: &mut [u8], format: PixelFormat) {
+        let tables = SrgbTables::new();
+        let (offsets, channels) = format.rgb_offsets();
+        for pixel in pixels.chunks_exact_mut(channels) {
+            let rgb = offsets.map(|offset| tables.to_linear[pixel[offset] as usize]);
+            for (row, offset) in self.matrix.iter().zip(offsets) {
+                let value = row[0] * rgb[0] + row[1] * rgb[1] + row[2] * rgb[2];
+                pixel[offset] = tables.encode(value);
+            }
+        }
+    }
+}
+
+/// Lookup tables between 8 bit sRGB and linear light
+pub struct SrgbTables {
+    /// Linear value of each 8 bit sRGB value, 0 to 1
+    pub to_linear: Vec<f32>,
+    to_srgb: Vec<u8>,
+}
+
+impl SrgbTables {
+    pub fn new() -> Self {
+        let to_linear = (0..=255)
+            .map(|value| srgb_to_linear(value as f32 / 255.0))
+            .collect();
+        let to_srgb = (0..ENCODE_LEVELS)
+            .map(|level| {
+                let linear = level as f32 / (ENCODE_LEVELS - 1) as f32;
+                (linear_to_srgb(linear) * 255.0).round() as u8
+            })
+            .collect();
+        SrgbTables { to_linear, to_srgb }
+    }
+
+    /// 8 bit sRGB value of a linear value, clamped to 0 to 1
+    pub fn encode(&self, linear: f32) -> u8 {
+        self.to_srgb[(linear.clamp(0.0, 1.0) * (ENCODE_LEVELS - 1) as f32) as usize]
+    }
+}
+
+/// Least squares matrix mapping measured to reference colours, both 8 bit sRGB.
+/// The fit is done in linear light.
+pub fn fit_colour_matrix(
+    measured: &[[f32; 3]],
+    reference: &[[f32; 3]],
+) -> Result<[[f32; 3]; 3], anyhow::Error> {
+    if measured.len() != reference.len() || measured.len() < 3 {
+        anyhow::bail!("At least 3 measured and reference colours are needed");
+    }
+    let linear = |colour: &[f32; 3]| colour.map(|value| srgb_to_linear(value / 255.0) as f64);
+
+    // Normal equations: (X^T X) M^T = X^T Y
+    let mut xtx = [[0.0f64; 3]; 3];
+    let mut xty = [[0.0f64; 3]; 3];
+    for (measured, reference) in measured.iter().zip(reference) {
+        let x = linear(measured);
+        let y = linear(reference);
+        for i in 0..3 {
+            for j in 0..3 {
+                xtx[i][j] += x[i] * x[j];
+                xty[i][j] += x[i] * y[j];
+            }
+        }
+    }
+    let inverse = invert_3x3(&xtx)
+        .ok_or_else(|| anyhow::anyhow!("Measured colours are degenerate, can't fit a matrix"))?;
+
+    let mut matrix = [[0.0f32; 3]; 3];
+    for (output, row) in matrix.iter_mut().enumerate() {
+        for (input, value) in row.iter_mut().enumerate() {
+            *value = (0..3)
+                .map(|k| inverse[input][k] * xty[k][output])
+                .sum::<f64>() as f32;
+        }
+    }
+    Ok(matrix)
+}
+
+/// Applies the matrix to an 8 bit sRGB colour
+pub fn correct_colour(matrix: &[[f32; 3]; 3], colour: [f32; 3]) -> [f32; 3] {
+    let rgb = colour.map(|value| srgb_to_linear(value / 255.0));
+    matrix.map(|row| {
+        let value = row[0] * rgb[0] + row[1] * rgb[1] + row[2] * rgb[2];
+        linear_to_srgb(value.clamp(0.0, 1.0)) * 255.0
+    })
+}
+
+/// CIE76 colour difference between two 8 bit sRGB colours
+pub fn delta_e(a: [f32; 3], b: [f32; 3]) -> f32 {
+    let [l1, a1, b1] = srgb_to_lab(a);
+    let [l2, a2, b2] = srgb_to_lab(b);
+    ((l1 - l2).powi(2) + (a1 - a2).powi(2) + (b1 - b2).powi(2)).sqrt()
+}
+
+pub fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// CIELAB of an 8 bit sRGB colour, D65 white point
+fn srgb_to_lab(colour: [f32; 3]) -> [f32; 3] {
+    let [r, g, b] = colour.map(|value| srgb_to_linear(value / 255.0));
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+    let f = |t: f32| {
+        if t > 0.008856 {
+            t.cbrt()
+        } else {
+            7.787 * t + 16.0 / 116.0
+        }
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+fn invert_3x3(m: &[[f64; 3]; 3]) -> Option<[[f64; 3]; 3]> {
+    let cofactor =
+        |r0: usize, r1: usize, c0: usize, c1: usize| m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0];
+    let determinant = m[0][0] * cofactor(1, 2, 1, 2) - m[0][1] * cofactor(1, 2, 0, 2)
+        + m[0][2] * cofactor(1, 2, 0, 1);
+    if determinant.abs() < 1e-12 {
+        return None;
+    }
+    let adjugate = [
+        [
+            cofactor(1, 2, 1, 2),
+            -cofactor(0, 2, 1, 2),
+            cofactor(0, 1, 1, 2),
+        ],
+        [
+            -cofactor(1, 2, 0, 2),
+            cofactor(0, 2, 0, 2),
+            -cofactor(0, 1, 0, 2),
+        ],
+        [
+            cofactor(1, 2, 0, 1),
+            -cofactor(0, 2, 0, 1),
+            cofactor(0, 1, 0, 1),
+        ],
+    ];
+    Some(adjugate.map(|row| row.map(|value| value / determinant)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATCHES: [[f32; 3]; 6] = [
+        [115.0, 82.0, 68.0],
+        [194.0, 150.0, 130.0],
+        [98.0, 122.0, 157.0],
+        [87.0, 108.0, 67.0],
+        [214.0, 126.0, 44.0],
+        [56.0, 61.0, 150.0],
+    ];
+
+    #[test]
+    fn fits_identity_to_matching_colours() {
+        let matrix = fit_colour_matrix(&PATCHES, &PATCHES).unwrap();
+        for (i, row) in matrix.iter().enumerate() {
+            for (j, value) in row.iter().enumerate() {
+                let expected = if i == j { 1.0 } else { 0.0 };
+                assert!((value - expected).abs() < 1e-4, "{:?}", matrix);
+            }
+        }
+        for patch in PATCHES {
+            assert!(delta_e(correct_colour(&matrix, patch), patch) < 0.1);
+        }
+    }
+
+    #[test]
+    fn rejects_degenerate_colours() {
+        let grey = [[128.0; 3]; 4];
+        assert!(fit_colour_matrix(&grey, &grey).is_err());
+    }
+
+    #[test]
+    fn inverts_3x3() {
+        let m = [[2.0, 1.0, 0.0], [0.0, 3.0, 1.0], [1.0, 0.0, 4.0]];
+        let inverse = invert_3x3(&m).unwrap();
+        for (i, row) in m.iter().enumerate() {
+            for j in 0..3 {
+                let value: f64 = row.iter().zip(&inverse).map(|(a, b)| a * b[j]).sum();
+                let expected = if i == j { 1.0 } else { 0.0 };
+                assert!((value - expected).abs() < 1e-12);
+            }
+        }
+        assert!(invert_3x3(&[[1.0, 2.0, 3.0], [2.0, 4.0, 6.0], [0.0, 1.0, 1.0]]).is_none());
+    }
+}
@@ -0,0 +1,111 @@
+use pyo3::types::PyAnyMethods;
+use pyo3::{Bound, FromPyObject, PyAny, PyResult};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+/// Control as reported by libcamera, discovered at runtime
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ControlInfo {
+    /// libcamera name
+    pub name: String,
+    /// libcamera control type, e.g. Integer32, Float, Rectangle
+    #[serde(rename = "type")]
+    pub control_type: String,
+    pub min: Value,
+    pub max: Value,
+    pub default: Value,
+    /// Value of each enum value name, None if not an enum control
+    pub enum_values: Option<BTreeMap<String, i64>>,
+}
+
+impl ControlInfo {
+    pub fn find<'a>(control_info: &'a [ControlInfo], name: &str) -> Option<&'a ControlInfo> {
+        control_info.iter().find(|info| info.name == name)
+    }
+
+    /// Value of the enum value name
+    pub fn enum_value(&self, value_name: &str) -> Result<i64, anyhow::Error> {
+        let enum_values = self
+            .enum_values
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Control {} has no named values", self.name))?;
+        enum_values.get(value_name).copied().ok_or_else(|| {
+            let names: Vec<&str> = enum_values.keys().map(|name| name.as_str()).collect();
+            anyhow::anyhow!(
+                "Unknown value {} of {}, expected one of {}",
+                value_name,
+                self.name,
+                names.join(", ")
+            )
+        })
+    }
+
+    /// Name of an enum value, None if not an enum control or the value has no name
+    pub fn enum_name(&self, value: i64) -> Option<&str> {
+        self.enum_values
+            .as_ref()?
+            .iter()
+            .find(|(_, enum_value)| **enum_value == value)
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Replaces an enum value name with its value, other values are left as is
+    pub fn resolve_value(&self, value: &mut Value) -> Result<(), anyhow::Error> {
+        if let Value::String(value_name) = value {
+            *value = json!(self.enum_value(value_name)?);
+        }
+        Ok(())
+    }
+}
+
+/// Value of an enum control, either the number or the libcamera value name
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum EnumControl {
+    Value(u8),
+    Name(String),
+}
+
+impl EnumControl {
+    /// Replaces a value name with its value
+    pub fn resolve(
+        &mut self,
+        control_info: &[ControlInfo],
+        name: &str,
+    ) -> Result<(), anyhow::Error> {
+        if let EnumControl::Name(value_name) = self {
+            let info = ControlInfo::find(control_info, name)
+                .ok_or_else(|| anyhow::anyhow!("Camera does not support {}", name))?;
+            let value = info.enum_value(value_name)?;
+            *self = EnumControl::Value(u8::try_from(value)?);
+        }
+        Ok(())
+    }
+
+    /// Numeric value, errors if the name was not resolved
+    pub fn value(&self) -> Result<u8, anyhow::Error> {
+        match self {
+            EnumControl::Value(value) => Ok(*value),
+            EnumControl::Name(name) => anyhow::bail!("Enum value name {} was not resolved", name),
+        }
+    }
+}
+
+/// Limit or default of an enum control, with the value name if the camera has one
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct EnumLimit {
+    pub value: u8,
+    pub name: Option<String>,
+}
+
+impl<'py> FromPyObject<'py> for EnumLimit {
+    /// Names are added from the control info afterwards
+    fn extract_bound(ob: &Bound<'py, PyAny>) -> PyResult<Self> {
+        Ok(EnumLimit {
+            value: ob.extract()?,
+            name: None,
+        })
+    }
+}
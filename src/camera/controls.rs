@@ -1,6 +1,13 @@
+use crate::camera::{ControlInfo, EnumControl, EnumLimit};
 use pyo3::types::{PyAnyMethods, PyDict, PyDictMethods, PyTuple};
-use pyo3::{Bound, FromPyObject, PyAny, Python};
+use pyo3::{Bound, FromPyObject, IntoPyObjectExt, PyAny, Python};
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// libcamera's AeStateConverged
+const AE_STATE_CONVERGED: u8 = 2;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum ControlConfig {
@@ -34,63 +41,78 @@ pub struct ScalerCrop {
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct CameraControls {
-    pub ae_constraint_mode: Option<u8>,
+    pub ae_constraint_mode: Option<EnumControl>,
     pub ae_enable: Option<bool>,
-    pub ae_exposure_mode: Option<u8>,
-    pub ae_flicker_mode: Option<u8>,
+    pub ae_exposure_mode: Option<EnumControl>,
+    pub ae_flicker_mode: Option<EnumControl>,
     pub ae_flicker_period: Option<i64>,
-    pub ae_metering_mode: Option<u8>,
+    pub ae_metering_mode: Option<EnumControl>,
+    pub af_metering: Option<EnumControl>,
+    pub af_mode: Option<EnumControl>,
+    pub af_range: Option<EnumControl>,
+    pub af_trigger: Option<EnumControl>,
+    /// Used when af_metering is windows
+    pub af_windows: Option<Vec<ScalerCrop>>,
     pub analogue_gain: Option<f32>,
-    pub analogue_gain_mode: Option<u8>,
+    pub analogue_gain_mode: Option<EnumControl>,
     pub awb_enable: Option<bool>,
-    pub awb_mode: Option<u8>,
+    pub awb_mode: Option<EnumControl>,
     pub brightness: Option<f32>,
     pub colour_gains: Option<ColourGain>,
     pub colour_temperature: Option<i64>,
     pub contrast: Option<f32>,
     pub cnn_enable_input_tensor: Option<bool>,
     pub exposure_time: Option<i64>,
-    pub exposure_time_mode: Option<u8>,
+    pub exposure_time_mode: Option<EnumControl>,
     pub exposure_value: Option<f32>,
     pub frame_duration_limits: Option<FrameDurationLimits>,
-    pub hdr_mode: Option<u8>,
-    pub noise_reduction_mode: Option<u8>,
+    pub hdr_mode: Option<EnumControl>,
+    pub lens_position: Option<f32>,
+    pub noise_reduction_mode: Option<EnumControl>,
     pub saturation: Option<f32>,
     pub scaler_crop: Option<ScalerCrop>,
     pub sharpness: Option<f32>,
-    pub sync_mode: Option<u8>,
+    pub sync_mode: Option<EnumControl>,
     pub sync_frames: Option<i64>,
     pub stats_output_enable: Option<bool>,
+    /// Controls without a field, by libcamera name. Enum values can be given by name.
+    #[serde(flatten)]
+    pub other_controls: BTreeMap<String, Value>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct CameraControlsLimit {
-    pub ae_constraint_mode: Option<u8>,
+    pub ae_constraint_mode: Option<EnumLimit>,
     pub ae_enable: Option<bool>,
-    pub ae_exposure_mode: Option<u8>,
-    pub ae_flicker_mode: Option<u8>,
+    pub ae_exposure_mode: Option<EnumLimit>,
+    pub ae_flicker_mode: Option<EnumLimit>,
     pub ae_flicker_period: Option<i64>,
-    pub ae_metering_mode: Option<u8>,
+    pub ae_metering_mode: Option<EnumLimit>,
+    pub af_metering: Option<EnumLimit>,
+    pub af_mode: Option<EnumLimit>,
+    pub af_range: Option<EnumLimit>,
+    pub af_trigger: Option<EnumLimit>,
     pub analogue_gain: Option<f32>,
-    pub analogue_gain_mode: Option<u8>,
+    pub analogue_gain_mode: Option<EnumLimit>,
     pub awb_enable: Option<bool>,
-    pub awb_mode: Option<u8>,
+    pub awb_mode: Option<EnumLimit>,
     pub brightness: Option<f32>,
     pub colour_gains: Option<f32>,
     pub colour_temperature: Option<i64>,
     pub contrast: Option<f32>,
     pub cnn_enable_input_tensor: Option<bool>,
     pub exposure_time: Option<i64>,
-    pub exposure_time_mode: Option<u8>,
+    pub exposure_time_mode: Option<EnumLimit>,
     pub exposure_value: Option<f32>,
     pub frame_duration_limits: Option<i64>,
-    pub hdr_mode: Option<u8>,
-    pub noise_reduction_mode: Option<u8>,
+    pub hdr_mode: Option<EnumLimit>,
+    pub lens_position: Option<f32>,
+    pub noise_reduction_mode: Option<EnumLimit>,
     pub saturation: Option<f32>,
     pub scaler_crop: Option<ScalerCrop>,
     pub sharpness: Option<f32>,
-    pub sync_mode: Option<u8>,
+    pub sync_mode: Option<EnumLimit>,
     pub sync_frames: Option<i64>,
     pub stats_output_enable: Option<bool>,
 }
@@ -108,8 +130,8 @@ impl CameraControls {
         if let Some(v) = self.sharpness {
             dict.set_item("Sharpness", v)?;
         }
-        if let Some(v) = self.noise_reduction_mode {
-            dict.set_item("NoiseReductionMode", v)?;
+        if let Some(v) = &self.noise_reduction_mode {
+            dict.set_item("NoiseReductionMode", v.value()?)?;
         }
         if let Some(v) = self.ae_flicker_period {
             dict.set_item("AeFlickerPeriod", v)?;
@@ -120,29 +142,29 @@ impl CameraControls {
         if let Some(v) = self.colour_temperature {
             dict.set_item("ColourTemperature", v)?;
         }
-        if let Some(v) = self.ae_flicker_mode {
-            dict.set_item("AeFlickerMode", v)?;
+        if let Some(v) = &self.ae_flicker_mode {
+            dict.set_item("AeFlickerMode", v.value()?)?;
         }
-        if let Some(v) = self.exposure_time_mode {
-            dict.set_item("ExposureTimeMode", v)?;
+        if let Some(v) = &self.exposure_time_mode {
+            dict.set_item("ExposureTimeMode", v.value()?)?;
         }
         if let Some(ColourGain { red, blue }) = self.colour_gains {
             dict.set_item("ColourGains", (red, blue))?;
         }
-        if let Some(v) = self.ae_exposure_mode {
-            dict.set_item("AeExposureMode", v)?;
+        if let Some(v) = &self.ae_exposure_mode {
+            dict.set_item("AeExposureMode", v.value()?)?;
         }
         if let Some(v) = self.exposure_value {
             dict.set_item("ExposureValue", v)?;
         }
-        if let Some(v) = self.ae_constraint_mode {
-            dict.set_item("AeConstraintMode", v)?;
+        if let Some(v) = &self.ae_constraint_mode {
+            dict.set_item("AeConstraintMode", v.value()?)?;
         }
         if let Some(v) = self.brightness {
             dict.set_item("Brightness", v)?;
         }
-        if let Some(v) = self.awb_mode {
-            dict.set_item("AwbMode", v)?;
+        if let Some(v) = &self.awb_mode {
+            dict.set_item("AwbMode", v.value()?)?;
         }
         if let Some(v) = self.cnn_enable_input_tensor {
             dict.set_item("CnnEnableInputTensor", v)?;
@@ -156,8 +178,8 @@ impl CameraControls {
         if let Some(v) = self.stats_output_enable {
             dict.set_item("StatsOutputEnable", v)?;
         }
-        if let Some(v) = self.sync_mode {
-            dict.set_item("SyncMode", v)?;
+        if let Some(v) = &self.sync_mode {
+            dict.set_item("SyncMode", v.value()?)?;
         }
         if let Some(FrameDurationLimits { min, max }) = self.frame_duration_limits {
             dict.set_item("FrameDurationLimits", (min, max))?;
@@ -165,8 +187,8 @@ impl CameraControls {
         if let Some(v) = self.exposure_time {
             dict.set_item("ExposureTime", v)?;
         }
-        if let Some(v) = self.ae_metering_mode {
-            dict.set_item("AeMeteringMode", v)?;
+        if let Some(v) = &self.ae_metering_mode {
+            dict.set_item("AeMeteringMode", v.value()?)?;
         }
         if let Some(ScalerCrop {
             x,
@@ -180,11 +202,36 @@ impl CameraControls {
         if let Some(v) = self.awb_enable {
             dict.set_item("AwbEnable", v)?;
         }
-        if let Some(v) = self.hdr_mode {
-            dict.set_item("HdrMode", v)?;
+        if let Some(v) = &self.hdr_mode {
+            dict.set_item("HdrMode", v.value()?)?;
+        }
+        if let Some(v) = &self.analogue_gain_mode {
+            dict.set_item("AnalogueGainMode", v.value()?)?;
+        }
+        if let Some(v) = &self.af_metering {
+            dict.set_item("AfMetering", v.value()?)?;
+        }
+        if let Some(v) = &self.af_mode {
+            dict.set_item("AfMode", v.value()?)?;
+        }
+        if let Some(v) = &self.af_range {
+            dict.set_item("AfRange", v.value()?)?;
         }
-        if let Some(v) = self.analogue_gain_mode {
-            dict.set_item("AnalogueGainMode", v)?;
+        if let Some(v) = &self.af_trigger {
+            dict.set_item("AfTrigger", v.value()?)?;
+        }
+        if let Some(windows) = &self.af_windows {
+            let windows: Vec<(u32, u32, u32, u32)> = windows
+                .iter()
+                .map(|w| (w.x, w.y, w.width, w.height))
+                .collect();
+            dict.set_item("AfWindows", windows)?;
+        }
+        if let Some(v) = self.lens_position {
+            dict.set_item("LensPosition", v)?;
+        }
+        for (name, value) in &self.other_controls {
+            dict.set_item(name, json_to_py(py, value)?)?;
         }
 
         Ok(dict)
@@ -192,6 +239,33 @@ impl CameraControls {
 }
 
 impl CameraControlsLimit {
+    /// Adds the value names of enum limits
+    pub fn name_enum_values(&mut self, control_info: &[ControlInfo]) {
+        let enum_limits = [
+            (&mut self.ae_constraint_mode, "AeConstraintMode"),
+            (&mut self.ae_exposure_mode, "AeExposureMode"),
+            (&mut self.ae_flicker_mode, "AeFlickerMode"),
+            (&mut self.ae_metering_mode, "AeMeteringMode"),
+            (&mut self.af_metering, "AfMetering"),
+            (&mut self.af_mode, "AfMode"),
+            (&mut self.af_range, "AfRange"),
+            (&mut self.af_trigger, "AfTrigger"),
+            (&mut self.analogue_gain_mode, "AnalogueGainMode"),
+            (&mut self.awb_mode, "AwbMode"),
+            (&mut self.exposure_time_mode, "ExposureTimeMode"),
+            (&mut self.hdr_mode, "HdrMode"),
+            (&mut self.noise_reduction_mode, "NoiseReductionMode"),
+            (&mut self.sync_mode, "SyncMode"),
+        ];
+        for (limit, name) in enum_limits {
+            if let Some(limit) = limit.as_mut() {
+                limit.name = ControlInfo::find(control_info, name)
+                    .and_then(|info| info.enum_name(limit.value as i64))
+                    .map(str::to_string);
+            }
+        }
+    }
+
     fn extract_option<'py, T>(val: Bound<'py, PyAny>) -> Result<Option<T>, anyhow::Error>
     where
         T: FromPyObject<'py>,
@@ -406,7 +480,700 @@ impl CameraControlsLimit {
             controls_max.analogue_gain_mode = Self::extract_option(max)?;
             controls_def.analogue_gain_mode = Self::extract_option(def)?;
         }
+        if let Some(obj) = dict.get_item("AfMetering")? {
+            let (min, max, def) = get_triplet(obj)?;
+            controls_min.af_metering = Self::extract_option(min)?;
+            controls_max.af_metering = Self::extract_option(max)?;
+            controls_def.af_metering = Self::extract_option(def)?;
+        }
+        if let Some(obj) = dict.get_item("AfMode")? {
+            let (min, max, def) = get_triplet(obj)?;
+            controls_min.af_mode = Self::extract_option(min)?;
+            controls_max.af_mode = Self::extract_option(max)?;
+            controls_def.af_mode = Self::extract_option(def)?;
+        }
+        if let Some(obj) = dict.get_item("AfRange")? {
+            let (min, max, def) = get_triplet(obj)?;
+            controls_min.af_range = Self::extract_option(min)?;
+            controls_max.af_range = Self::extract_option(max)?;
+            controls_def.af_range = Self::extract_option(def)?;
+        }
+        if let Some(obj) = dict.get_item("AfTrigger")? {
+            let (min, max, def) = get_triplet(obj)?;
+            controls_min.af_trigger = Self::extract_option(min)?;
+            controls_max.af_trigger = Self::extract_option(max)?;
+            controls_def.af_trigger = Self::extract_option(def)?;
+        }
+        if let Some(obj) = dict.get_item("LensPosition")? {
+            let (min, max, def) = get_triplet(obj)?;
+            controls_min.lens_position = Self::extract_option(min)?;
+            controls_max.lens_position = Self::extract_option(max)?;
+            controls_def.lens_position = Self::extract_option(def)?;
+        }
 
         Ok((controls_min, controls_max, controls_def))
     }
 }
+
+/// Control value outside of the camera limits
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ControlLimitViolation {
+    /// Json name of the field, with subfield for compound controls
+    pub field: String,
+    pub value: Value,
+    pub min: Option<Value>,
+    pub max: Option<Value>,
+    /// Value used instead, None if not clamped
+    pub clamped: Option<Value>,
+}
+
+/// Controls are outside of the camera limits and were not clamped
+#[derive(Debug)]
+pub struct ControlLimitError {
+    pub violations: Vec<ControlLimitViolation>,
+}
+
+impl fmt::Display for ControlLimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fields: Vec<&str> = self
+            .violations
+            .iter()
+            .map(|violation| violation.field.as_str())
+            .collect();
+        write!(
+            f,
+            "Controls outside of camera limits: {}",
+            fields.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for ControlLimitError {}
+
+impl CameraControls {
+    /// Merges json fields into the controls, null resets the field.
+    /// Other controls must be in the control info.
+    /// Returns merged controls and json names of reset fields.
+    pub fn patch(
+        &self,
+        patch: &Map<String, Value>,
+        control_info: &[ControlInfo],
+    ) -> Result<(CameraControls, Vec<String>), anyhow::Error> {
+        let Value::Object(mut fields) = serde_json::to_value(self)? else {
+            anyhow::bail!("Controls are not a json object");
+        };
+
+        let mut reset_fields = Vec::new();
+        for (field, value) in patch {
+            // Unset fields are serialized as null, so every known field is present.
+            // Other controls use libcamera names.
+            let other_control = ControlInfo::find(control_info, field).is_some();
+            if !fields.contains_key(field) && !other_control {
+                anyhow::bail!("Unknown control {}", field);
+            }
+            if value.is_null() {
+                reset_fields.push(field.clone());
+            }
+            fields.insert(field.clone(), value.clone());
+        }
+        // Null other controls would stay in the map
+        fields.retain(|_, value| !value.is_null());
+
+        let controls = serde_json::from_value(Value::Object(fields))?;
+        Ok((controls, reset_fields))
+    }
+
+    /// Replaces enum value names with values and checks that other controls are supported
+    pub fn resolve_names(&mut self, control_info: &[ControlInfo]) -> Result<(), anyhow::Error> {
+        let enum_controls = [
+            (&mut self.ae_constraint_mode, "AeConstraintMode"),
+            (&mut self.ae_exposure_mode, "AeExposureMode"),
+            (&mut self.ae_flicker_mode, "AeFlickerMode"),
+            (&mut self.ae_metering_mode, "AeMeteringMode"),
+            (&mut self.af_metering, "AfMetering"),
+            (&mut self.af_mode, "AfMode"),
+            (&mut self.af_range, "AfRange"),
+            (&mut self.af_trigger, "AfTrigger"),
+            (&mut self.analogue_gain_mode, "AnalogueGainMode"),
+            (&mut self.awb_mode, "AwbMode"),
+            (&mut self.exposure_time_mode, "ExposureTimeMode"),
+            (&mut self.hdr_mode, "HdrMode"),
+            (&mut self.noise_reduction_mode, "NoiseReductionMode"),
+            (&mut self.sync_mode, "SyncMode"),
+        ];
+        for (value, name) in enum_controls {
+            if let Some(value) = value.as_mut() {
+                value.resolve(control_info, name)?;
+            }
+        }
+
+        let Value::Object(fields) = serde_json::to_value(CameraControls::default())? else {
+            anyhow::bail!("Controls are not a json object");
+        };
+        for (name, value) in self.other_controls.iter_mut() {
+            if let Some(field) = fields
+                .keys()
+                .find(|field| libcamera_control_name(field) == *name)
+            {
+                anyhow::bail!("Control {} must be set with {}", name, field);
+            }
+            let info = ControlInfo::find(control_info, name)
+                .ok_or_else(|| anyhow::anyhow!("Camera does not support {}", name))?;
+            info.resolve_value(value)?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks range controls against camera limits, out of range values are clamped if clamp is
+    /// set. Other controls are checked against their control info. Returns out of range values.
+    pub fn validate(
+        &mut self,
+        min: &CameraControlsLimit,
+        max: &CameraControlsLimit,
+        control_info: &[ControlInfo],
+        clamp: bool,
+    ) -> Vec<ControlLimitViolation> {
+        let mut violations = Vec::new();
+        let v = &mut violations;
+
+        check_enum(
+            v,
+            clamp,
+            "aeConstraintMode",
+            &mut self.ae_constraint_mode,
+            min.ae_constraint_mode.as_ref(),
+            max.ae_constraint_mode.as_ref(),
+        );
+        check_enum(
+            v,
+            clamp,
+            "aeExposureMode",
+            &mut self.ae_exposure_mode,
+            min.ae_exposure_mode.as_ref(),
+            max.ae_exposure_mode.as_ref(),
+        );
+        check_enum(
+            v,
+            clamp,
+            "aeFlickerMode",
+            &mut self.ae_flicker_mode,
+            min.ae_flicker_mode.as_ref(),
+            max.ae_flicker_mode.as_ref(),
+        );
+        check_limit(
+            v,
+            clamp,
+            "aeFlickerPeriod",
+            &mut self.ae_flicker_period,
+            min.ae_flicker_period,
+            max.ae_flicker_period,
+        );
+        check_enum(
+            v,
+            clamp,
+            "aeMeteringMode",
+            &mut self.ae_metering_mode,
+            min.ae_metering_mode.as_ref(),
+            max.ae_metering_mode.as_ref(),
+        );
+        check_enum(
+            v,
+            clamp,
+            "afMetering",
+            &mut self.af_metering,
+            min.af_metering.as_ref(),
+            max.af_metering.as_ref(),
+        );
+        check_enum(
+            v,
+            clamp,
+            "afMode",
+            &mut self.af_mode,
+            min.af_mode.as_ref(),
+            max.af_mode.as_ref(),
+        );
+        check_enum(
+            v,
+            clamp,
+            "afRange",
+            &mut self.af_range,
+            min.af_range.as_ref(),
+            max.af_range.as_ref(),
+        );
+        check_enum(
+            v,
+            clamp,
+            "afTrigger",
+            &mut self.af_trigger,
+            min.af_trigger.as_ref(),
+            max.af_trigger.as_ref(),
+        );
+        check_limit(
+            v,
+            clamp,
+            "analogueGain",
+            &mut self.analogue_gain,
+            min.analogue_gain,
+            max.analogue_gain,
+        );
+        check_enum(
+            v,
+            clamp,
+            "analogueGainMode",
+            &mut self.analogue_gain_mode,
+            min.analogue_gain_mode.as_ref(),
+            max.analogue_gain_mode.as_ref(),
+        );
+        check_enum(
+            v,
+            clamp,
+            "awbMode",
+            &mut self.awb_mode,
+            min.awb_mode.as_ref(),
+            max.awb_mode.as_ref(),
+        );
+        check_limit(
+            v,
+            clamp,
+            "brightness",
+            &mut self.brightness,
+            min.brightness,
+            max.brightness,
+        );
+        check_limit(
+            v,
+            clamp,
+            "colourTemperature",
+            &mut self.colour_temperature,
+            min.colour_temperature,
+            max.colour_temperature,
+        );
+        check_limit(
+            v,
+            clamp,
+            "contrast",
+            &mut self.contrast,
+            min.contrast,
+            max.contrast,
+        );
+        check_limit(
+            v,
+            clamp,
+            "exposureTime",
+            &mut self.exposure_time,
+            min.exposure_time,
+            max.exposure_time,
+        );
+        check_enum(
+            v,
+            clamp,
+            "exposureTimeMode",
+            &mut self.exposure_time_mode,
+            min.exposure_time_mode.as_ref(),
+            max.exposure_time_mode.as_ref(),
+        );
+        check_limit(
+            v,
+            clamp,
+            "exposureValue",
+            &mut self.exposure_value,
+            min.exposure_value,
+            max.exposure_value,
+        );
+        check_limit(
+            v,
+            clamp,
+            "lensPosition",
+            &mut self.lens_position,
+            min.lens_position,
+            max.lens_position,
+        );
+        check_enum(
+            v,
+            clamp,
+            "hdrMode",
+            &mut self.hdr_mode,
+            min.hdr_mode.as_ref(),
+            max.hdr_mode.as_ref(),
+        );
+        check_enum(
+            v,
+            clamp,
+            "noiseReductionMode",
+            &mut self.noise_reduction_mode,
+            min.noise_reduction_mode.as_ref(),
+            max.noise_reduction_mode.as_ref(),
+        );
+        check_limit(
+            v,
+            clamp,
+            "saturation",
+            &mut self.saturation,
+            min.saturation,
+            max.saturation,
+        );
+        check_limit(
+            v,
+            clamp,
+            "sharpness",
+            &mut self.sharpness,
+            min.sharpness,
+            max.sharpness,
+        );
+        check_enum(
+            v,
+            clamp,
+            "syncMode",
+            &mut self.sync_mode,
+            min.sync_mode.as_ref(),
+            max.sync_mode.as_ref(),
+        );
+        check_limit(
+            v,
+            clamp,
+            "syncFrames",
+            &mut self.sync_frames,
+            min.sync_frames,
+            max.sync_frames,
+        );
+
+        // Both gains share the same limits
+        if let Some(colour_gains) = self.colour_gains.as_mut() {
+            check_value(
+                v,
+                clamp,
+                "colourGains.red",
+                &mut colour_gains.red,
+                min.colour_gains,
+                max.colour_gains,
+            );
+            check_value(
+                v,
+                clamp,
+                "colourGains.blue",
+                &mut colour_gains.blue,
+                min.colour_gains,
+                max.colour_gains,
+            );
+        }
+        // Both ends share the same limits
+        if let Some(frame_duration_limits) = self.frame_duration_limits.as_mut() {
+            let min_duration = min.frame_duration_limits.map(|v| v.max(0) as u64);
+            let max_duration = max.frame_duration_limits.map(|v| v.max(0) as u64);
+            check_value(
+                v,
+                clamp,
+                "frameDurationLimits.min",
+                &mut frame_duration_limits.min,
+                min_duration,
+                max_duration,
+            );
+            // The max end can't be below the min end, clamping raises it to the min end
+            let lowest_max = min_duration.map_or(frame_duration_limits.min, |min_duration| {
+                min_duration.max(frame_duration_limits.min)
+            });
+            check_value(
+                v,
+                clamp,
+                "frameDurationLimits.max",
+                &mut frame_duration_limits.max,
+                Some(lowest_max),
+                max_duration,
+            );
+        }
+
+        for (name, value) in self.other_controls.iter_mut() {
+            // Only scalar numeric controls have comparable limits
+            let limits = ControlInfo::find(control_info, name)
+                .and_then(|info| Some((value.as_f64()?, info.min.as_f64(), info.max.as_f64())));
+            if let Some((mut number, min, max)) = limits {
+                let count = v.len();
+                check_value(v, clamp, name, &mut number, min, max);
+                if clamp && v.len() > count {
+                    // Keep integers as integers
+                    *value = if value.is_f64() {
+                        json!(number)
+                    } else {
+                        json!(number as i64)
+                    };
+                }
+            }
+        }
+
+        violations
+    }
+}
+
+fn check_enum(
+    violations: &mut Vec<ControlLimitViolation>,
+    clamp: bool,
+    field: &str,
+    value: &mut Option<EnumControl>,
+    min: Option<&EnumLimit>,
+    max: Option<&EnumLimit>,
+) {
+    // Names are checked when resolved
+    if let Some(EnumControl::Value(value)) = value.as_mut() {
+        let (min, max) = (min.map(|min| min.value), max.map(|max| max.value));
+        check_value(violations, clamp, field, value, min, max);
+    }
+}
+
+fn check_limit<T: PartialOrd + Copy + Serialize>(
+    violations: &mut Vec<ControlLimitViolation>,
+    clamp: bool,
+    field: &str,
+    value: &mut Option<T>,
+    min: Option<T>,
+    max: Option<T>,
+) {
+    if let Some(value) = value.as_mut() {
+        check_value(violations, clamp, field, value, min, max);
+    }
+}
+
+fn check_value<T: PartialOrd + Copy + Serialize>(
+    violations: &mut Vec<ControlLimitViolation>,
+    clamp: bool,
+    field: &str,
+    value: &mut T,
+    min: Option<T>,
+    max: Option<T>,
+) {
+    let below = min.filter(|min| *value < *min);
+    let above = max.filter(|max| *value > *max);
+    let Some(limit) = below.or(above) else {
+        return;
+    };
+
+    let original = *value;
+    if clamp {
+        *value = limit;
+    }
+    violations.push(ControlLimitViolation {
+        field: field.to_string(),
+        value: json!(original),
+        min: min.map(|min| json!(min)),
+        max: max.map(|max| json!(max)),
+        clamped: clamp.then(|| json!(limit)),
+    });
+}
+
+/// Libcamera name of a control json field
+pub fn libcamera_control_name(field: &str) -> String {
+    let mut chars = field.chars();
+    match chars.next() {
+        Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+        None => String::new(),
+    }
+}
+
+/// Control values the camera is using, read from frame metadata
+#[derive(Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct EffectiveControls {
+    pub exposure_time: Option<i64>,
+    pub analogue_gain: Option<f32>,
+    pub digital_gain: Option<f32>,
+    pub colour_gains: Option<ColourGain>,
+    pub colour_temperature: Option<i64>,
+    pub lux: Option<f32>,
+    /// None if not reported by the camera
+    pub ae_converged: Option<bool>,
+    /// None if not reported by the camera
+    pub awb_converged: Option<bool>,
+    pub lens_position: Option<f32>,
+    pub af_state: Option<AfState>,
+}
+
+/// Autofocus state reported in frame metadata, values match libcamera AfStateEnum
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum AfState {
+    Idle,
+    Scanning,
+    Focused,
+    Failed,
+}
+
+impl AfState {
+    fn from_value(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(AfState::Idle),
+            1 => Some(AfState::Scanning),
+            2 => Some(AfState::Focused),
+            3 => Some(AfState::Failed),
+            _ => None,
+        }
+    }
+}
+
+impl EffectiveControls {
+    pub fn from_metadata(metadata: &Bound<PyDict>) -> Self {
+        let colour_gains: Option<(f32, f32)> = Self::get(metadata, "ColourGains");
+        // Newer libcamera reports AeState instead of AeLocked
+        let ae_converged = Self::get(metadata, "AeLocked").or_else(|| {
+            Self::get::<u8>(metadata, "AeState").map(|state| state == AE_STATE_CONVERGED)
+        });
+
+        EffectiveControls {
+            exposure_time: Self::get(metadata, "ExposureTime"),
+            analogue_gain: Self::get(metadata, "AnalogueGain"),
+            digital_gain: Self::get(metadata, "DigitalGain"),
+            colour_gains: colour_gains.map(|(red, blue)| ColourGain { red, blue }),
+            colour_temperature: Self::get(metadata, "ColourTemperature"),
+            lux: Self::get(metadata, "Lux"),
+            ae_converged,
+            awb_converged: Self::get(metadata, "AwbLocked"),
+            lens_position: Self::get(metadata, "LensPosition"),
+            af_state: Self::get(metadata, "AfState").and_then(AfState::from_value),
+        }
+    }
+
+    /// Metadata value, None if missing or of a different type
+    fn get<'py, T>(metadata: &Bound<'py, PyDict>, key: &str) -> Option<T>
+    where
+        T: FromPyObject<'py>,
+    {
+        metadata
+            .get_item(key)
+            .ok()
+            .flatten()
+            .and_then(|value| value.extract().ok())
+    }
+}
+
+/// Converts json to python, arrays become tuples as libcamera expects
+fn json_to_py<'py>(py: Python<'py>, value: &Value) -> Result<Bound<'py, PyAny>, anyhow::Error> {
+    let object = match value {
+        Value::Null => py.None().into_bound(py),
+        Value::Bool(v) => v.into_bound_py_any(py)?,
+        Value::Number(v) => match v.as_i64() {
+            Some(v) => v.into_bound_py_any(py)?,
+            None => v.as_f64().into_bound_py_any(py)?,
+        },
+        Value::String(v) => v.into_bound_py_any(py)?,
+        Value::Array(values) => {
+            let items = values
+                .iter()
+                .map(|value| json_to_py(py, value))
+                .collect::<Result<Vec<_>, _>>()?;
+            PyTuple::new(py, items)?.into_any()
+        }
+        Value::Object(_) => anyhow::bail!("Control value can't be an object"),
+    };
+    Ok(object)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enum_info(name: &str, enum_values: &[(&str, i64)]) -> ControlInfo {
+        ControlInfo {
+            name: name.to_string(),
+            control_type: "Integer32".to_string(),
+            min: json!(0),
+            max: json!(enum_values.len() - 1),
+            default: json!(0),
+            enum_values: Some(
+                enum_values
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), *value))
+                    .collect(),
+            ),
+        }
+    }
+
+    fn control_info() -> Vec<ControlInfo> {
+        vec![
+            enum_info("AwbMode", &[("Auto", 0), ("Daylight", 5)]),
+            enum_info(
+                "AfPause",
+                &[("Deferred", 0), ("Immediate", 1), ("Resume", 2)],
+            ),
+        ]
+    }
+
+    fn json_object(value: Value) -> Map<String, Value> {
+        let Value::Object(fields) = value else {
+            panic!("Not a json object");
+        };
+        fields
+    }
+
+    #[test]
+    fn patch_merges_fields_and_resets_null() {
+        let controls = CameraControls {
+            exposure_time: Some(10_000),
+            analogue_gain: Some(2.0),
+            awb_enable: Some(false),
+            ..Default::default()
+        };
+        let patch = json_object(json!({ "analogueGain": 4.0, "exposureTime": null }));
+
+        let (patched, reset_fields) = controls.patch(&patch, &[]).unwrap();
+
+        assert_eq!(patched.exposure_time, None);
+        assert_eq!(patched.analogue_gain, Some(4.0));
+        assert_eq!(patched.awb_enable, Some(false));
+        assert_eq!(reset_fields, vec!["exposureTime".to_string()]);
+    }
+
+    #[test]
+    fn patch_rejects_unknown_fields() {
+        let patch = json_object(json!({ "exposureTyme": 1000 }));
+        assert!(CameraControls::default().patch(&patch, &[]).is_err());
+    }
+
+    #[test]
+    fn patch_accepts_only_known_other_controls() {
+        let patch = json_object(json!({ "AfPause": "Immediate" }));
+        let (patched, _) = CameraControls::default()
+            .patch(&patch, &control_info())
+            .unwrap();
+        assert_eq!(patched.other_controls["AfPause"], json!("Immediate"));
+
+        let patch = json_object(json!({ "NotAControl": 1 }));
+        assert!(CameraControls::default()
+            .patch(&patch, &control_info())
+            .is_err());
+    }
+
+    #[test]
+    fn enum_controls_accept_values_and_names() {
+        let controls: CameraControls =
+            serde_json::from_value(json!({ "awbMode": "Daylight", "aeMeteringMode": 1 })).unwrap();
+        assert_eq!(
+            controls.awb_mode,
+            Some(EnumControl::Name("Daylight".to_string()))
+        );
+        assert_eq!(controls.ae_metering_mode, Some(EnumControl::Value(1)));
+    }
+
+    #[test]
+    fn resolve_names_replaces_enum_names() {
+        let mut controls: CameraControls =
+            serde_json::from_value(json!({ "awbMode": "Daylight", "AfPause": "Resume" })).unwrap();
+
+        controls.resolve_names(&control_info()).unwrap();
+
+        assert_eq!(controls.awb_mode, Some(EnumControl::Value(5)));
+        assert_eq!(controls.other_controls["AfPause"], json!(2));
+    }
+
+    #[test]
+    fn resolve_names_rejects_unknown_names_and_controls() {
+        let invalid = [
+            json!({ "awbMode": "Cloudy" }),
+            json!({ "NotAControl": 1 }),
+            // Controls with a field must be set with the field
+            json!({ "AwbMode": 0 }),
+        ];
+        for controls in invalid {
+            let mut controls: CameraControls = serde_json::from_value(controls).unwrap();
+            assert!(controls.resolve_names(&control_info()).is_err());
+        }
+    }
+}
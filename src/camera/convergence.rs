@@ -0,0 +1,126 @@
+use crate::camera::{CameraService, ColourGain, EffectiveControls};
+use pyo3::Python;
+use serde::Serialize;
+use std::time::Instant;
+
+/// Consecutive frames exposure, gain and colour gains must stay within tolerance
+const STABLE_FRAMES: u32 = 5;
+/// Relative change between frames still counted as stable
+const STABLE_TOLERANCE: f32 = 0.02;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum ConvergenceMethod {
+    /// Camera reported AE and AWB as locked
+    Locked,
+    /// Exposure, gain and colour gains stopped changing
+    Stable,
+}
+
+/// Outcome of waiting for AE/AWB to settle
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Convergence {
+    pub converged: bool,
+    /// None if not converged
+    pub method: Option<ConvergenceMethod>,
+    pub frames: u32,
+    pub wait_time_millis: u64,
+    /// Controls of the last polled frame
+    pub effective_controls: Option<EffectiveControls>,
+}
+
+/// Tracks frame metadata until AE/AWB are locked or values are stable
+#[derive(Default)]
+pub struct ConvergenceTracker {
+    frames: u32,
+    stable_frames: u32,
+    /// Consecutive frames the colour gains stayed within tolerance
+    stable_colour_frames: u32,
+    last: Option<EffectiveControls>,
+}
+
+impl ConvergenceTracker {
+    pub fn update(&mut self, controls: &EffectiveControls) -> Option<ConvergenceMethod> {
+        self.frames += 1;
+        let (stable, colour_stable) = match self.last.as_ref() {
+            Some(last) => (
+                Self::is_stable(last, controls),
+                Self::colour_gains_stable(last, controls),
+            ),
+            None => (false, false),
+        };
+        self.stable_frames = if stable { self.stable_frames + 1 } else { 0 };
+        self.stable_colour_frames = if colour_stable {
+            self.stable_colour_frames + 1
+        } else {
+            0
+        };
+        self.last = Some(controls.clone());
+
+        // AE is required for a lock, if AWB is not reported the colour gains must have settled
+        let awb_locked = match controls.awb_converged {
+            Some(awb_converged) => awb_converged,
+            None => self.stable_colour_frames >= STABLE_FRAMES,
+        };
+        let locked = controls.ae_converged == Some(true) && awb_locked;
+        if locked {
+            Some(ConvergenceMethod::Locked)
+        } else if self.stable_frames >= STABLE_FRAMES {
+            Some(ConvergenceMethod::Stable)
+        } else {
+            None
+        }
+    }
+
+    fn is_stable(last: &EffectiveControls, current: &EffectiveControls) -> bool {
+        let exposure = match (last.exposure_time, current.exposure_time) {
+            (Some(a), Some(b)) => close(a as f32, b as f32),
+            _ => false,
+        };
+        let gain = match (last.analogue_gain, current.analogue_gain) {
+            (Some(a), Some(b)) => close(a, b),
+            _ => false,
+        };
+        exposure && gain && Self::colour_gains_stable(last, current)
+    }
+
+    fn colour_gains_stable(last: &EffectiveControls, current: &EffectiveControls) -> bool {
+        match (&last.colour_gains, &current.colour_gains) {
+            (Some(ColourGain { red, blue }), Some(current)) => {
+                close(*red, current.red) && close(*blue, current.blue)
+            }
+            // Not reported by every camera
+            (None, None) => true,
+            _ => false,
+        }
+    }
+}
+
+fn close(a: f32, b: f32) -> bool {
+    (a - b).abs() <= a.abs().max(b.abs()) * STABLE_TOLERANCE
+}
+
+impl CameraService {
+    /// Polls frame metadata until AE/AWB converge or the deadline passes
+    pub fn wait_for_convergence(
+        &self,
+        py: Python,
+        deadline: Instant,
+    ) -> Result<Convergence, anyhow::Error> {
+        let start = Instant::now();
+        let mut tracker = ConvergenceTracker::default();
+        let mut method = None;
+        while method.is_none() && Instant::now() < deadline {
+            let metadata = self.get_frame_metadata(py)?;
+            method = tracker.update(&EffectiveControls::from_metadata(&metadata));
+        }
+
+        Ok(Convergence {
+            converged: method.is_some(),
+            method,
+            frames: tracker.frames,
+            wait_time_millis: Instant::now().saturating_duration_since(start).as_millis() as u64,
+            effective_controls: tracker.last,
+        })
+    }
+}
@@ -0,0 +1,388 @@
+use crate::camera::{refine_corner, GrayImage, Point};
+use serde::Serialize;
+
+/// Markers are detected on a copy downscaled to about this width, corners are refined in
+/// full resolution
+const DETECTION_WIDTH: usize = 2048;
+/// Cells along a side, a black border around the data bits
+const MARKER_CELLS: usize = 7;
+const DATA_CELLS: usize = 5;
+/// Codewords of a data row, each carries 2 bits of the id. Original ArUco dictionary.
+const ROW_CODES: [[bool; DATA_CELLS]; 4] = [
+    [true, false, false, false, false],
+    [true, false, true, true, true],
+    [false, true, false, false, true],
+    [false, true, true, true, false],
+];
+/// Smallest marker side, detection pixels
+const MIN_MARKER_SIZE: usize = 14;
+/// Darker than the local mean by this much counts as black
+const THRESHOLD_OFFSET: u32 = 7;
+/// Smallest area of the fitted quadrilateral, fraction of the area of the outline
+const MIN_QUAD_FILL: f32 = 0.9;
+/// Smallest brightness difference between black and white cells
+const MIN_CELL_CONTRAST: f32 = 30.0;
+
+/// Square fiducial marker found in a frame
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Marker {
+    pub id: u32,
+    /// Frame pixels, clockwise from the top left corner of the marker
+    pub corners: [Point; 4],
+}
+
+impl Marker {
+    /// Marker in a frame of the height, after rotate_quarter_turn
+    pub fn rotate_quarter_turn(&self, height: usize) -> Marker {
+        Marker {
+            id: self.id,
+            corners: self.corners.map(|corner| Point {
+                x: (height - 1) as f32 - corner.y,
+                y: corner.x,
+            }),
+        }
+    }
+}
+
+struct Component {
+    min_x: usize,
+    max_x: usize,
+    min_y: usize,
+    max_y: usize,
+    /// Leftmost and rightmost pixel of each row from min_y
+    rows: Vec<(usize, usize)>,
+}
+
+/// Finds markers of the original ArUco dictionary: 5x5 data bits inside a one cell black border
+pub fn detect_markers(image: &GrayImage) -> Vec<Marker> {
+    let factor = image.width.div_ceil(DETECTION_WIDTH).max(1);
+    let small = image.downscaled(factor);
+    let dark = adaptive_threshold(&small);
+
+    let mut markers: Vec<Marker> = Vec::new();
+    for component in dark_components(&dark, small.width, small.height) {
+        let Some(quad) = fit_quad(&component) else {
+            continue;
+        };
+        // Detection pixel centres in frame pixels
+        let quad = quad.map(|corner| Point {
+            x: (corner.x + 0.5) * factor as f32 - 0.5,
+            y: (corner.y + 0.5) * factor as f32 - 0.5,
+        });
+        let cell_size =
+            distance(quad[0], quad[2]) / (MARKER_CELLS as f32 * std::f32::consts::SQRT_2);
+        let radius = ((cell_size / 2.0) as isize).max(2);
+        let quad = quad.map(|corner| refine_corner(image, corner, radius));
+        if let Some(marker) = decode_marker(image, quad)
+            && !markers.iter().any(|other| {
+                other.id == marker.id && distance(other.corners[0], marker.corners[0]) < cell_size
+            })
+        {
+            markers.push(marker);
+        }
+    }
+    markers.sort_by_key(|marker| marker.id);
+    markers
+}
+
+/// Pixels darker than their surroundings, from a box mean of an integral image
+fn adaptive_threshold(image: &GrayImage) -> Vec<bool> {
+    let (width, height) = (image.width, image.height);
+    let mut integral = vec![0u32; (width + 1) * (height + 1)];
+    for y in 0..height {
+        let mut row_sum = 0;
+        for x in 0..width {
+            row_sum += image.pixels[y * width + x] as u32;
+            integral[(y + 1) * (width + 1) + x + 1] = integral[y * (width + 1) + x + 1] + row_sum;
+        }
+    }
+    let half = (width / 64).max(7);
+    let mut dark = vec![false; width * height];
+    for y in 0..height {
+        let (top, bottom) = (y.saturating_sub(half), (y + half + 1).min(height));
+        for x in 0..width {
+            let (left, right) = (x.saturating_sub(half), (x + half + 1).min(width));
+            let sum = integral[bottom * (width + 1) + right] + integral[top * (width + 1) + left]
+                - integral[top * (width + 1) + right]
+                - integral[bottom * (width + 1) + left];
+            let count = ((bottom - top) * (right - left)) as u32;
+            let value = image.pixels[y * width + x] as u32;
+            dark[y * width + x] = (value + THRESHOLD_OFFSET) * count < sum;
+        }
+    }
+    dark
+}
+
+/// 4-connected dark regions that are large enough for a marker and don't touch the frame edge
+fn dark_components(dark: &[bool], width: usize, height: usize) -> Vec<Component> {
+    let mut labels = vec![0u32; dark.len()];
+    let mut components = Vec::new();
+    let mut stack = Vec::new();
+    for start in 0..dark.len() {
+        if !dark[start] || labels[start] != 0 {
+            continue;
+        }
+        let label = components.len() as u32 + 1;
+        let mut component = Component {
+            min_x: usize::MAX,
+            max_x: 0,
+            min_y: usize::MAX,
+            max_y: 0,
+            rows: Vec::new(),
+        };
+        labels[start] = label;
+        stack.push(start);
+        while let Some(index) = stack.pop() {
+            let (x, y) = (index % width, index / width);
+            component.min_x = component.min_x.min(x);
+            component.max_x = component.max_x.max(x);
+            component.min_y = component.min_y.min(y);
+            component.max_y = component.max_y.max(y);
+            let neighbours = [
+                (x > 0).then(|| index - 1),
+                (x + 1 < width).then(|| index + 1),
+                (y > 0).then(|| index - width),
+                (y + 1 < height).then(|| index + width),
+            ];
+            for neighbour in neighbours.into_iter().flatten() {
+                if dark[neighbour] && labels[neighbour] == 0 {
+                    labels[neighbour] = label;
+                    stack.push(neighbour);
+                }
+            }
+        }
+        components.push(component);
+    }
+
+    let is_candidate = |component: &Component| {
+        let size =
+            (component.max_x - component.min_x + 1).min(component.max_y - component.min_y + 1);
+        size >= MIN_MARKER_SIZE
+            && component.min_x > 0
+            && component.min_y > 0
+            && component.max_x + 1 < width
+            && component.max_y + 1 < height
+    };
+    for component in components
+        .iter_mut()
+        .filter(|component| is_candidate(component))
+    {
+        component.rows = vec![(usize::MAX, 0); component.max_y - component.min_y + 1];
+    }
+    for (index, &label) in labels.iter().enumerate() {
+        if label == 0 {
+            continue;
+        }
+        let component = &mut components[label as usize - 1];
+        if component.rows.is_empty() {
+            continue;
+        }
+        let (x, y) = (index % width, index / width);
+        let row = &mut component.rows[y - component.min_y];
+        *row = (row.0.min(x), row.1.max(x));
+    }
+    components.retain(|component| !component.rows.is_empty());
+    components
+}
+
+/// Quadrilateral of the component outline, clockwise, None if it isn't close to one
+fn fit_quad(component: &Component) -> Option<[Point; 4]> {
+    let points: Vec<Point> = component
+        .rows
+        .iter()
+        .enumerate()
+        .flat_map(|(row, &(left, right))| {
+            let y = (component.min_y + row) as f32;
+            [Point { x: left as f32, y }, Point { x: right as f32, y }]
+        })
+        .collect();
+    let hull = convex_hull(points);
+    if hull.len() < 4 {
+        return None;
+    }
+
+    // Diagonal is the farthest pair, the other corners are farthest from it on each side
+    let (a, c) = (0..hull.len())
+        .flat_map(|i| (i + 1..hull.len()).map(move |j| (i, j)))
+        .max_by(|&(a, b), &(c, d)| {
+            distance(hull[a], hull[b]).total_cmp(&distance(hull[c], hull[d]))
+        })?;
+    let (a, c) = (hull[a], hull[c]);
+    let side = |point: &Point| cross(subtract(c, a), subtract(*point, a));
+    // Clockwise with y down
+    let b = *hull.iter().min_by(|p, q| side(p).total_cmp(&side(q)))?;
+    let d = *hull.iter().max_by(|p, q| side(p).total_cmp(&side(q)))?;
+    if side(&b) >= 0.0 || side(&d) <= 0.0 {
+        return None;
+    }
+
+    let quad_area = polygon_area(&[a, b, c, d]);
+    if quad_area < MIN_QUAD_FILL * polygon_area(&hull) {
+        return None;
+    }
+    let sides = [
+        distance(a, b),
+        distance(b, c),
+        distance(c, d),
+        distance(d, a),
+    ];
+    let shortest = sides.iter().fold(f32::INFINITY, |min, &side| min.min(side));
+    let longest = sides.iter().fold(0.0f32, |max, &side| max.max(side));
+    // Too oblique to read the cells
+    if shortest < longest / 4.0 {
+        return None;
+    }
+    Some([a, b, c, d])
+}
+
+/// Reads the cells inside the quadrilateral, None if they aren't a marker
+fn decode_marker(image: &GrayImage, quad: [Point; 4]) -> Option<Marker> {
+    let cells: Vec<f32> = (0..MARKER_CELLS * MARKER_CELLS)
+        .map(|cell| {
+            let (row, column) = (cell / MARKER_CELLS, cell % MARKER_CELLS);
+            // Middle of the cell, away from blurred edges
+            let mut sum = 0.0;
+            for sy in 0..3 {
+                for sx in 0..3 {
+                    let u = (column as f32 + 0.3 + 0.2 * sx as f32) / MARKER_CELLS as f32;
+                    let v = (row as f32 + 0.3 + 0.2 * sy as f32) / MARKER_CELLS as f32;
+                    let point = quad_point(&quad, u, v);
+                    sum += image.value(point.x.round() as isize, point.y.round() as isize);
+                }
+            }
+            sum / 9.0
+        })
+        .collect();
+    let min = cells
+        .iter()
+        .fold(f32::INFINITY, |min, &value| min.min(value));
+    let max = cells.iter().fold(0.0f32, |max, &value| max.max(value));
+    if max - min < MIN_CELL_CONTRAST {
+        return None;
+    }
+    let threshold = (min + max) / 2.0;
+    let white = |row: usize, column: usize| cells[row * MARKER_CELLS + column] > threshold;
+
+    let last = MARKER_CELLS - 1;
+    let border_black = (0..MARKER_CELLS)
+        .all(|i| !white(0, i) && !white(last, i) && !white(i, 0) && !white(i, last));
+    if !border_black {
+        return None;
+    }
+
+    let mut bits = [[false; DATA_CELLS]; DATA_CELLS];
+    for (row, bits) in bits.iter_mut().enumerate() {
+        for (column, bit) in bits.iter_mut().enumerate() {
+            *bit = white(row + 1, column + 1);
+        }
+    }
+    for rotation in 0..4 {
+        if let Some(id) = marker_id(&bits) {
+            // Top left of the marker is where the sampled corner rotated to
+            let corners = [0, 1, 2, 3].map(|corner| quad[(4 - rotation + corner) % 4]);
+            return Some(Marker { id, corners });
+        }
+        bits = rotate_clockwise(&bits);
+    }
+    None
+}
+
+/// Id of the data bits, None if a row isn't a codeword
+fn marker_id(bits: &[[bool; DATA_CELLS]; DATA_CELLS]) -> Option<u32> {
+    bits.iter().try_fold(0u32, |id, row| {
+        let code = ROW_CODES.iter().position(|code| code == row)?;
+        Some((id << 2) | code as u32)
+    })
+}
+
+fn rotate_clockwise(bits: &[[bool; DATA_CELLS]; DATA_CELLS]) -> [[bool; DATA_CELLS]; DATA_CELLS] {
+    let mut rotated = [[false; DATA_CELLS]; DATA_CELLS];
+    for (row, rotated) in rotated.iter_mut().enumerate() {
+        for (column, bit) in rotated.iter_mut().enumerate() {
+            *bit = bits[DATA_CELLS - 1 - column][row];
+        }
+    }
+    rotated
+}
+
+/// Point of the quadrilateral at u, v in 0..1, projective so cells stay aligned under perspective
+fn quad_point(quad: &[Point; 4], u: f32, v: f32) -> Point {
+    // Homography from the unit square, Heckbert's square to quad mapping
+    let [p0, p1, p2, p3] = *quad;
+    let sx = p0.x - p1.x + p2.x - p3.x;
+    let sy = p0.y - p1.y + p2.y - p3.y;
+    let (dx1, dx2) = (p1.x - p2.x, p3.x - p2.x);
+    let (dy1, dy2) = (p1.y - p2.y, p3.y - p2.y);
+    let determinant = dx1 * dy2 - dx2 * dy1;
+    let (g, h) = if determinant.abs() < f32::EPSILON {
+        (0.0, 0.0)
+    } else {
+        (
+            (sx * dy2 - dx2 * sy) / determinant,
+            (dx1 * sy - sx * dy1) / determinant,
+        )
+    };
+    let a = p1.x - p0.x + g * p1.x;
+    let b = p3.x - p0.x + h * p3.x;
+    let d = p1.y - p0.y + g * p1.y;
+    let e = p3.y - p0.y + h * p3.y;
+    let w = g * u + h * v + 1.0;
+    Point {
+        x: (a * u + b * v + p0.x) / w,
+        y: (d * u + e * v + p0.y) / w,
+    }
+}
+
+/// Monotone chain, clockwise with y down
+fn convex_hull(mut points: Vec<Point>) -> Vec<Point> {
+    points.sort_by(|a, b| a.x.total_cmp(&b.x).then(a.y.total_cmp(&b.y)));
+    points.dedup();
+    if points.len() < 3 {
+        return points;
+    }
+    let mut lower = half_hull(points.iter());
+    let upper = half_hull(points.iter().rev());
+    lower.pop();
+    lower.extend(&upper[..upper.len() - 1]);
+    lower
+}
+
+fn half_hull<'a>(points: impl Iterator<Item = &'a Point>) -> Vec<Point> {
+    let mut hull: Vec<Point> = Vec::new();
+    for &point in points {
+        while hull.len() >= 2
+            && cross(
+                subtract(hull[hull.len() - 1], hull[hull.len() - 2]),
+                subtract(point, hull[hull.len() - 2]),
+            ) <= 0.0
+        {
+            hull.pop();
+        }
+        hull.push(point);
+    }
+    hull
+}
+
+fn polygon_area(points: &[Point]) -> f32 {
+    let twice_area: f32 = (0..points.len())
+        .map(|i| cross(points[i], points[(i + 1) % points.len()]))
+        .sum();
+    twice_area.abs() / 2.0
+}
+
+fn subtract(a: Point, b: Point) -> Point {
+    Point {
+        x: a.x - b.x,
+        y: a.y - b.y,
+    }
+}
+
+fn cross(a: Point, b: Point) -> f32 {
+    a.x * b.y - a.y * b.x
+}
+
+fn distance(a: Point, b: Point) -> f32 {
+    let difference = subtract(a, b);
+    (difference.x * difference.x + difference.y * difference.y).sqrt()
+}
@@ -0,0 +1,379 @@
+use crate::camera::{
+    linear_to_srgb, srgb_to_linear, CaptureConfig, Frame, Orientation, PixelFormat, SrgbTables,
+};
+use serde::{Deserialize, Serialize};
+
+/// Side of the square pixel blocks a flat field gain is stored for. Vignetting is smooth,
+/// so the gain map is interpolated between block centres.
+pub const FLAT_FIELD_BLOCK: usize = 8;
+/// Frames that can be summed without overflowing the 16 bit sums
+pub const MAX_ACCUMULATED_FRAMES: u32 = 257;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CalibrationFrameKind {
+    /// Lens capped, removes hot pixels and the black level
+    Dark,
+    /// Uniformly lit target, removes vignetting
+    Flat,
+}
+
+impl CalibrationFrameKind {
+    pub fn file_prefix(&self) -> &'static str {
+        match self {
+            CalibrationFrameKind::Dark => "dark",
+            CalibrationFrameKind::Flat => "flat",
+        }
+    }
+}
+
+/// Stored alongside calibration data, describes the frames it was made from
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CalibrationFrameInfo {
+    pub kind: CalibrationFrameKind,
+    pub width: usize,
+    pub height: usize,
+    pub format: PixelFormat,
+    /// Number of averaged frames
+    pub frames: u32,
+    /// Mean red, green and blue of the averaged frames, after dark subtraction for flat fields
+    pub mean: [f32; 3],
+    /// Flat field was captured with dark frame subtraction
+    pub dark_subtracted: bool,
+}
+
+/// Calibration data is only valid for the still configuration and transform it was captured with
+pub fn calibration_key(capture_config: &CaptureConfig, orientation: &Orientation) -> String {
+    let sensor = match &capture_config.sensor_mode {
+        Some(mode) => format!("{}x{}", mode.width, mode.height),
+        None => "auto".to_string(),
+    };
+    let (hflip, vflip) = orientation.transform_flips();
+    format!(
+        "{}x{}_{}_{}_{}{}",
+        capture_config.resolution.width,
+        capture_config.resolution.height,
+        capture_config.format.python_name(),
+        sensor,
+        hflip as u8,
+        vflip as u8
+    )
+}
+
+/// Averaged dark frame, in the byte layout of the captures
+pub struct DarkFrame {
+    pub info: CalibrationFrameInfo,
+    pub pixels: Vec<u8>,
+}
+
+/// Per channel gain of each block in linear light, row by row
+pub struct FlatField {
+    pub info: CalibrationFrameInfo,
+    pub blocks_x: usize,
+    pub blocks_y: usize,
+    pub gains: Vec<[f32; 3]>,
+}
+
+/// Dark frame and flat field stored for a calibration key, None if not captured
+pub struct CalibrationFrames {
+    pub key: String,
+    pub dark: Option<DarkFrame>,
+    pub flat: Option<FlatField>,
+}
+
+/// Sums frames of the same size and format
+pub struct FrameAccumulator {
+    width: usize,
+    height: usize,
+    format: PixelFormat,
+    frames: u32,
+    /// 16 bit to fit full resolution sums in memory
+    sums: Vec<u16>,
+}
+
+impl FrameAccumulator {
+    pub fn new(frame: &Frame) -> Self {
+        Self::with_size(frame.width, frame.height, frame.format, frame.pixels.len())
+    }
+
+    /// Accumulator for frames of `len` bytes
+    pub fn with_size(width: usize, height: usize, format: PixelFormat, len: usize) -> Self {
+        FrameAccumulator {
+            width,
+            height,
+            format,
+            frames: 0,
+            sums: vec![0; len],
+        }
+    }
+
+    pub fn add(&mut self, frame: &Frame) -> Result<(), anyhow::Error> {
+        self.add_pixels(&frame.pixels, frame.width, frame.height, frame.format)
+    }
+
+    pub fn add_pixels(
+        &mut self,
+        pixels: &[u8],
+        width: usize,
+        height: usize,
+        format: PixelFormat,
+    ) -> Result<(), anyhow::Error> {
+        if width != self.width
+            || height != self.height
+            || format != self.format
+            || pixels.len() != self.sums.len()
+        {
+            anyhow::bail!("Frame size or format changed while averaging");
+        }
+        if self.frames >= MAX_ACCUMULATED_FRAMES {
+            anyhow::bail!("At most {} frames can be averaged", MAX_ACCUMULATED_FRAMES);
+        }
+        for (sum, value) in self.sums.iter_mut().zip(pixels) {
+            *sum += *value as u16;
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    /// Mean of the byte at index
+    fn mean(&self, index: usize) -> f32 {
+        self.sums[index] as f32 / self.frames.max(1) as f32
+    }
+
+    /// Rounded mean of every byte
+    pub fn mean_pixels(&self) -> Vec<u8> {
+        (0..self.sums.len())
+            .map(|index| self.mean(index).round() as u8)
+            .collect()
+    }
+
+    fn info(
+        &self,
+        kind: CalibrationFrameKind,
+        mean: [f32; 3],
+        dark_subtracted: bool,
+    ) -> CalibrationFrameInfo {
+        CalibrationFrameInfo {
+            kind,
+            width: self.width,
+            height: self.height,
+            format: self.format,
+            frames: self.frames,
+            mean,
+            dark_subtracted,
+        }
+    }
+
+    pub fn into_dark_frame(self) -> DarkFrame {
+        let pixels = self.mean_pixels();
+        let mean = channel_means(&pixels, self.format);
+        DarkFrame {
+            info: self.info(CalibrationFrameKind::Dark, mean, false),
+            pixels,
+        }
+    }
+
+    /// Gain map that scales each block to the frame mean, after dark subtraction.
+    /// Vignetting scales light, so gains are measured in linear light, scaled to 0-255.
+    pub fn into_flat_field(self, dark: Option<&DarkFrame>) -> Result<FlatField, anyhow::Error> {
+        if let Some(dark) = dark {
+            check_matches(&dark.info, self.width, self.height, self.format)?;
+        }
+        let linear = |value: f32| srgb_to_linear(value / 255.0) * 255.0;
+        let value = |index: usize| {
+            let dark = dark.map_or(0.0, |dark| linear(dark.pixels[index] as f32));
+            (linear(self.mean(index)) - dark).max(0.0) as f64
+        };
+
+        let (offsets, channels) = self.format.rgb_offsets();
+        let blocks_x = self.width.div_ceil(FLAT_FIELD_BLOCK);
+        let blocks_y = self.height.div_ceil(FLAT_FIELD_BLOCK);
+        let mut block_sums = vec![[0.0f64; 3]; blocks_x * blocks_y];
+        let mut block_counts = vec![0u32; blocks_x * blocks_y];
+        let mut totals = [0.0f64; 3];
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let block = (y / FLAT_FIELD_BLOCK) * blocks_x + x / FLAT_FIELD_BLOCK;
+                let index = (y * self.width + x) * channels;
+                for (channel, offset) in offsets.iter().enumerate() {
+                    let value = value(index + offset);
+                    block_sums[block][channel] += value;
+                    totals[channel] += value;
+                }
+                block_counts[block] += 1;
+            }
+        }
+
+        let pixel_count = (self.width * self.height) as f64;
+        let frame_mean = totals.map(|total| total / pixel_count);
+        if frame_mean.iter().any(|&channel| channel < 1.0) {
+            anyhow::bail!("Flat field is too dark, mean {:?}", frame_mean);
+        }
+        let gains = block_sums
+            .iter()
+            .zip(&block_counts)
+            .map(|(sums, count)| {
+                let mut gain = [1.0f32; 3];
+                for channel in 0..3 {
+                    let block_mean = sums[channel] / *count as f64;
+                    // Unlit blocks are left as they are
+                    if block_mean >= 1.0 {
+                        gain[channel] = (frame_mean[channel] / block_mean) as f32;
+                    }
+                }
+                gain
+            })
+            .collect();
+
+        Ok(FlatField {
+            info: self.info(
+                CalibrationFrameKind::Flat,
+                frame_mean.map(|mean| linear_to_srgb(mean as f32 / 255.0) * 255.0),
+                dark.is_some(),
+            ),
+            blocks_x,
+            blocks_y,
+            gains,
+        })
+    }
+}
+
+impl FlatField {
+    /// Gains as little endian floats
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.gains
+            .iter()
+            .flatten()
+            .flat_map(|gain| gain.to_le_bytes())
+            .collect()
+    }
+
+    pub fn from_bytes(info: CalibrationFrameInfo, bytes: &[u8]) -> Result<Self, anyhow::Error> {
+        let blocks_x = info.width.div_ceil(FLAT_FIELD_BLOCK);
+        let blocks_y = info.height.div_ceil(FLAT_FIELD_BLOCK);
+        if bytes.len() != blocks_x * blocks_y * 3 * 4 {
+            anyhow::bail!("Flat field data does not match its size");
+        }
+        let gains = bytes
+            .chunks_exact(12)
+            .map(|gain| {
+                [0, 1, 2].map(|channel| {
+                    let start = channel * 4;
+                    f32::from_le_bytes([
+                        gain[start],
+                        gain[start + 1],
+                        gain[start + 2],
+                        gain[start + 3],
+                    ])
+                })
+            })
+            .collect();
+        Ok(FlatField {
+            info,
+            blocks_x,
+            blocks_y,
+            gains,
+        })
+    }
+
+    /// Gain of each channel, interpolated between block centres
+    fn gain(&self, x: usize, y: usize) -> [f32; 3] {
+        let position = |pixel: usize, blocks: usize| {
+            let block = (pixel as f32 + 0.5) / FLAT_FIELD_BLOCK as f32 - 0.5;
+            let block = block.clamp(0.0, (blocks - 1) as f32);
+            let first = block.floor() as usize;
+            let second = (first + 1).min(blocks - 1);
+            (first, second, block - first as f32)
+        };
+        let (x0, x1, tx) = position(x, self.blocks_x);
+        let (y0, y1, ty) = position(y, self.blocks_y);
+        let at = |bx: usize, by: usize| self.gains[by * self.blocks_x + bx];
+        let (a, b, c, d) = (at(x0, y0), at(x1, y0), at(x0, y1), at(x1, y1));
+        [0, 1, 2].map(|channel| {
+            let top = a[channel] + (b[channel] - a[channel]) * tx;
+            let bottom = c[channel] + (d[channel] - c[channel]) * tx;
+            top + (bottom - top) * ty
+        })
+    }
+}
+
+impl DarkFrame {
+    pub fn from_bytes(info: CalibrationFrameInfo, pixels: Vec<u8>) -> Result<Self, anyhow::Error> {
+        let (_, channels) = info.format.rgb_offsets();
+        if pixels.len() != info.width * info.height * channels {
+            anyhow::bail!("Dark frame data does not match its size");
+        }
+        Ok(DarkFrame { info, pixels })
+    }
+}
+
+/// Subtracts the dark frame and applies the flat field gains in place, in linear light
+pub fn apply_calibration_frames(
+    pixels: &mut [u8],
+    width: usize,
+    height: usize,
+    format: PixelFormat,
+    dark: Option<&DarkFrame>,
+    flat: Option<&FlatField>,
+) -> Result<(), anyhow::Error> {
+    if let Some(dark) = dark {
+        check_matches(&dark.info, width, height, format)?;
+    }
+    if let Some(flat) = flat {
+        check_matches(&flat.info, width, height, format)?;
+    }
+
+    let tables = SrgbTables::new();
+    let (offsets, channels) = format.rgb_offsets();
+    for (y, row) in pixels.chunks_exact_mut(width * channels).enumerate() {
+        for (x, pixel) in row.chunks_exact_mut(channels).enumerate() {
+            let gain = flat.map_or([1.0; 3], |flat| flat.gain(x, y));
+            let index = (y * width + x) * channels;
+            for (channel, offset) in offsets.iter().enumerate() {
+                let dark = dark.map_or(0.0, |dark| {
+                    tables.to_linear[dark.pixels[index + offset] as usize]
+                });
+                let value = (tables.to_linear[pixel[*offset] as usize] - dark).max(0.0);
+                pixel[*offset] = tables.encode(value * gain[channel]);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn check_matches(
+    info: &CalibrationFrameInfo,
+    width: usize,
+    height: usize,
+    format: PixelFormat,
+) -> Result<(), anyhow::Error> {
+    if info.width != width || info.height != height || info.format != format {
+        anyhow::bail!(
+            "{:?} calibration is {}x{} {:?}, frame is {}x{} {:?}",
+            info.kind,
+            info.width,
+            info.height,
+            info.format,
+            width,
+            height,
+            format
+        );
+    }
+    Ok(())
+}
+
+fn channel_means(pixels: &[u8], format: PixelFormat) -> [f32; 3] {
+    let (offsets, channels) = format.rgb_offsets();
+    let mut sums = [0u64; 3];
+    for pixel in pixels.chunks_exact(channels) {
+        for (sum, offset) in sums.iter_mut().zip(offsets) {
+            *sum += pixel[offset] as u64;
+        }
+    }
+    let count = (pixels.len() / channels).max(1) as f32;
+    sums.map(|sum| sum as f32 / count)
+}
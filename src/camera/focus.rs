@@ -0,0 +1,82 @@
+use crate::camera::Roi;
+use pyo3::types::{PyDict, PyDictMethods};
+use pyo3::{Bound, Python};
+use serde::{Deserialize, Serialize};
+
+/// Shortest time between published scores
+pub const MIN_FOCUS_PUBLISH_INTERVAL_MILLIS: u64 = 100;
+
+/// Focus assist settings, sharpness is measured on every preview frame
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FocusAssistConfig {
+    /// Part of the frame the sharpness is measured on, centre of the frame if unset
+    #[serde(default = "default_focus_roi")]
+    pub roi: Roi,
+    /// Time between published scores
+    #[serde(default = "default_publish_interval_millis")]
+    pub publish_interval_millis: u64,
+    /// Draw the region and scores into the preview stream
+    #[serde(default)]
+    pub draw: bool,
+}
+
+fn default_focus_roi() -> Roi {
+    Roi {
+        x: 0.375,
+        y: 0.375,
+        width: 0.25,
+        height: 0.25,
+    }
+}
+
+fn default_publish_interval_millis() -> u64 {
+    250
+}
+
+impl FocusAssistConfig {
+    pub fn validate(&self, width: u32, height: u32) -> Result<(), anyhow::Error> {
+        if self.publish_interval_millis < MIN_FOCUS_PUBLISH_INTERVAL_MILLIS {
+            anyhow::bail!(
+                "Publish interval must be at least {} ms",
+                MIN_FOCUS_PUBLISH_INTERVAL_MILLIS
+            );
+        }
+        let (_, _, roi_width, roi_height) = self.roi.to_pixels(width as usize, height as usize)?;
+        // Laplacian needs a border pixel on each side
+        if roi_width < 3 || roi_height < 3 {
+            anyhow::bail!("Region must be at least 3x3 pixels");
+        }
+        Ok(())
+    }
+
+    pub fn to_pydict<'py>(&self, py: Python<'py>) -> Result<Bound<'py, PyDict>, anyhow::Error> {
+        let dict = PyDict::new(py);
+        dict.set_item(
+            "roi",
+            (self.roi.x, self.roi.y, self.roi.width, self.roi.height),
+        )?;
+        dict.set_item("draw", self.draw)?;
+        Ok(dict)
+    }
+}
+
+/// Sharpness of the latest preview frame
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct FocusScore {
+    /// Variance of the Laplacian of the region's luma, higher is sharper
+    pub score: f64,
+    /// Highest score since focus assist was started
+    pub max_score: f64,
+    pub sensor_timestamp: i64,
+}
+
+/// Why focus assist was stopped
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FocusAssistStopReason {
+    /// StopFocusAssist request
+    Requested,
+    /// Preview was stopped, scores are only measured on preview frames
+    PreviewStopped,
+}
@@ -0,0 +1,571 @@
+use crate::camera::{Orientation, Point};
+use serde::{Deserialize, Serialize};
+
+/// Views needed to separate the focal length from the board pose
+pub const MIN_INTRINSICS_VIEWS: usize = 3;
+const MAX_ITERATIONS: usize = 100;
+const MAX_DAMPING: f64 = 1e12;
+/// Refinement stops once the cost improves by less than this fraction
+const MIN_IMPROVEMENT: f64 = 1e-10;
+
+type Matrix3 = [[f64; 3]; 3];
+type Vector3 = [f64; 3];
+
+/// Checkerboard corners found in one frame
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CalibrationView {
+    /// Calibration key of the still configuration and transform of the frame
+    pub key: String,
+    pub width: usize,
+    pub height: usize,
+    pub columns: usize,
+    pub rows: usize,
+    /// Inner corners in frame pixels, row by row
+    pub corners: Vec<Point>,
+}
+
+/// Pinhole camera with radial distortion, in pixels of the still configuration it was solved for
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CameraIntrinsics {
+    /// Calibration key of the still configuration and transform of the views
+    pub key: String,
+    pub width: usize,
+    pub height: usize,
+    pub focal_length_x: f64,
+    pub focal_length_y: f64,
+    pub principal_point_x: f64,
+    pub principal_point_y: f64,
+    /// k1 and k2 of the distortion factor 1 + k1 r^2 + k2 r^4, r in normalized coordinates
+    pub radial_distortion: [f64; 2],
+    /// RMS distance between detected and reprojected corners, pixels
+    pub reprojection_error: f64,
+    pub views: usize,
+}
+
+impl CameraIntrinsics {
+    /// Intrinsics in pixels of a picture taken with the still configuration of the key,
+    /// None if they were solved for a different one
+    pub fn for_picture(&self, key: &str, orientation: &Orientation) -> Option<CameraIntrinsics> {
+        if self.key != key {
+            return None;
+        }
+        if !orientation.quarter_turn() {
+            return Some(self.clone());
+        }
+        // Rotated clockwise, x' = height - 1 - y and y' = x
+        Some(CameraIntrinsics {
+            width: self.height,
+            height: self.width,
+            focal_length_x: self.focal_length_y,
+            focal_length_y: self.focal_length_x,
+            principal_point_x: self.height as f64 - 1.0 - self.principal_point_y,
+            principal_point_y: self.principal_point_x,
+            ..self.clone()
+        })
+    }
+}
+
+/// Zhang's method: poses and focal lengths from board homographies, refined together with
+/// the principal point and distortion by Levenberg-Marquardt
+pub fn solve_intrinsics(views: &[CalibrationView]) -> Result<CameraIntrinsics, anyhow::Error> {
+    if views.len() < MIN_INTRINSICS_VIEWS {
+        anyhow::bail!(
+            "{} views collected, at least {} are needed",
+            views.len(),
+            MIN_INTRINSICS_VIEWS
+        );
+    }
+    let first = &views[0];
+    let same_pattern = views.iter().all(|view| {
+        view.key == first.key
+            && view.width == first.width
+            && view.height == first.height
+            && view.columns == first.columns
+            && view.rows == first.rows
+            && view.corners.len() == first.columns * first.rows
+    });
+    if !same_pattern {
+        anyhow::bail!("Views have different patterns or still configurations");
+    }
+
+    // Board coordinates in squares, the size doesn't change the intrinsics
+    let model: Vec<[f64; 2]> = (0..first.rows)
+        .flat_map(|row| (0..first.columns).map(move |column| [column as f64, row as f64]))
+        .collect();
+    let observed: Vec<Vec<[f64; 2]>> = views
+        .iter()
+        .map(|view| {
+            view.corners
+                .iter()
+                .map(|corner| [corner.x as f64, corner.y as f64])
+                .collect()
+        })
+        .collect();
+
+    let homographies = observed
+        .iter()
+        .map(|corners| homography(&model, corners))
+        .collect::<Result<Vec<_>, _>>()?;
+    let principal_point = [
+        (first.width as f64 - 1.0) / 2.0,
+        (first.height as f64 - 1.0) / 2.0,
+    ];
+    let focal_length = initial_focal_length(&homographies, principal_point)?;
+    let mut params = vec![
+        focal_length[0],
+        focal_length[1],
+        principal_point[0],
+        principal_point[1],
+        0.0,
+        0.0,
+    ];
+    for homography in &homographies {
+        params.extend(initial_pose(homography, &params[..6]));
+    }
+
+    let params = refine(params, &model, &observed);
+    let cost = total_cost(&params, &model, &observed);
+    if params[..6].iter().any(|value| !value.is_finite()) || params[0] <= 0.0 || params[1] <= 0.0 {
+        anyhow::bail!("Intrinsics did not converge, capture views with more board tilt");
+    }
+
+    Ok(CameraIntrinsics {
+        key: first.key.clone(),
+        width: first.width,
+        height: first.height,
+        focal_length_x: params[0],
+        focal_length_y: params[1],
+        principal_point_x: params[2],
+        principal_point_y: params[3],
+        radial_distortion: [params[4], params[5]],
+        reprojection_error: (cost / (model.len() * views.len()) as f64).sqrt(),
+        views: views.len(),
+    })
+}
+
+/// Homography from board to frame coordinates, normalized DLT with h33 = 1
+fn homography(model: &[[f64; 2]], observed: &[[f64; 2]]) -> Result<Matrix3, anyhow::Error> {
+    let (model_scale, model_mean) = normalization(model);
+    let (observed_scale, observed_mean) = normalization(observed);
+
+    let mut ata = vec![vec![0.0; 8]; 8];
+    let mut atb = vec![0.0; 8];
+    for (model, observed) in model.iter().zip(observed) {
+        let x = (model[0] - model_mean[0]) * model_scale;
+        let y = (model[1] - model_mean[1]) * model_scale;
+        let u = (observed[0] - observed_mean[0]) * observed_scale;
+        let v = (observed[1] - observed_mean[1]) * observed_scale;
+        let equations = [
+            ([x, y, 1.0, 0.0, 0.0, 0.0, -x * u, -y * u], u),
+            ([0.0, 0.0, 0.0, x, y, 1.0, -x * v, -y * v], v),
+        ];
+        for (row, value) in equations {
+            for i in 0..8 {
+                atb[i] += row[i] * value;
+                for j in 0..8 {
+                    ata[i][j] += row[i] * row[j];
+                }
+            }
+        }
+    }
+    let h = solve_linear(ata, atb)
+        .ok_or_else(|| anyhow::anyhow!("Corners are degenerate, can't fit a homography"))?;
+    let normalized = [[h[0], h[1], h[2]], [h[3], h[4], h[5]], [h[6], h[7], 1.0]];
+
+    let model_transform = [
+        [model_scale, 0.0, -model_scale * model_mean[0]],
+        [0.0, model_scale, -model_scale * model_mean[1]],
+        [0.0, 0.0, 1.0],
+    ];
+    let observed_inverse = [
+        [1.0 / observed_scale, 0.0, observed_mean[0]],
+        [0.0, 1.0 / observed_scale, observed_mean[1]],
+        [0.0, 0.0, 1.0],
+    ];
+    Ok(multiply(
+        &multiply(&observed_inverse, &normalized),
+        &model_transform,
+    ))
+}
+
+/// Scale and mean that move points to the origin at an average distance of sqrt(2)
+fn normalization(points: &[[f64; 2]]) -> (f64, [f64; 2]) {
+    let count = points.len() as f64;
+    let mean = [
+        points.iter().map(|point| point[0]).sum::<f64>() / count,
+        points.iter().map(|point| point[1]).sum::<f64>() / count,
+    ];
+    let distance = points
+        .iter()
+        .map(|point| ((point[0] - mean[0]).powi(2) + (point[1] - mean[1]).powi(2)).sqrt())
+        .sum::<f64>()
+        / count;
+    (std::f64::consts::SQRT_2 / distance.max(1e-12), mean)
+}
+
+/// Focal lengths from the orthonormality of the rotation columns, principal point assumed
+fn initial_focal_length(
+    homographies: &[Matrix3],
+    principal_point: [f64; 2],
+) -> Result<[f64; 2], anyhow::Error> {
+    let centre = [
+        [1.0, 0.0, -principal_point[0]],
+        [0.0, 1.0, -principal_point[1]],
+        [0.0, 0.0, 1.0],
+    ];
+    // a = 1 / fx^2, b = 1 / fy^2 by least squares
+    let mut ata = [[0.0f64; 2]; 2];
+    let mut atb = [0.0f64; 2];
+    for homography in homographies {
+        let h = multiply(&centre, homography);
+        let norm = h
+            .iter()
+            .flatten()
+            .map(|value| value * value)
+            .sum::<f64>()
+            .sqrt();
+        let column = |index: usize| h.map(|row| row[index] / norm);
+        let (h1, h2) = (column(0), column(1));
+        let equations = [
+            ([h1[0] * h2[0], h1[1] * h2[1]], -h1[2] * h2[2]),
+            (
+                [h1[0].powi(2) - h2[0].powi(2), h1[1].powi(2) - h2[1].powi(2)],
+                h2[2].powi(2) - h1[2].powi(2),
+            ),
+        ];
+        for (row, value) in equations {
+            for i in 0..2 {
+                atb[i] += row[i] * value;
+                for j in 0..2 {
+                    ata[i][j] += row[i] * row[j];
+                }
+            }
+        }
+    }
+    let determinant = ata[0][0] * ata[1][1] - ata[0][1] * ata[1][0];
+    let a = (ata[1][1] * atb[0] - ata[0][1] * atb[1]) / determinant;
+    let b = (ata[0][0] * atb[1] - ata[1][0] * atb[0]) / determinant;
+    if !(a > 0.0 && b > 0.0) {
+        anyhow::bail!("Views don't constrain the focal length, tilt the board more between views");
+    }
+    Ok([1.0 / a.sqrt(), 1.0 / b.sqrt()])
+}
+
+/// Rotation vector and translation of the board from its homography
+fn initial_pose(homography: &Matrix3, intrinsics: &[f64]) -> [f64; 6] {
+    let unproject = |index: usize| {
+        let column = homography.map(|row| row[index]);
+        [
+            (column[0] - intrinsics[2] * column[2]) / intrinsics[0],
+            (column[1] - intrinsics[3] * column[2]) / intrinsics[1],
+            column[2],
+        ]
+    };
+    let (r1, r2, t) = (unproject(0), unproject(1), unproject(2));
+    let mut scale = 2.0 / (norm(r1) + norm(r2));
+    // Board is in front of the camera
+    if t[2] < 0.0 {
+        scale = -scale;
+    }
+    let (r1, r2, t) = (
+        scale_vector(r1, scale),
+        scale_vector(r2, scale),
+        scale_vector(t, scale),
+    );
+    // Closest rotation by Gram-Schmidt
+    let r1 = normalize(r1);
+    let r2 = normalize(subtract(r2, scale_vector(r1, dot(r1, r2))));
+    let r3 = cross(r1, r2);
+    let rotation = [
+        [r1[0], r2[0], r3[0]],
+        [r1[1], r2[1], r3[1]],
+        [r1[2], r2[2], r3[2]],
+    ];
+    let vector = rotation_to_vector(&rotation);
+    [vector[0], vector[1], vector[2], t[0], t[1], t[2]]
+}
+
+/// Frame pixels of a board point, intrinsics are fx, fy, cx, cy, k1, k2
+fn project(intrinsics: &[f64], pose: &[f64], point: [f64; 2]) -> [f64; 2] {
+    let rotation = vector_to_rotation([pose[0], pose[1], pose[2]]);
+    let camera = [0, 1, 2]
+        .map(|row| rotation[row][0] * point[0] + rotation[row][1] * point[1] + pose[3 + row]);
+    let (x, y) = (camera[0] / camera[2], camera[1] / camera[2]);
+    let r2 = x * x + y * y;
+    let distortion = 1.0 + intrinsics[4] * r2 + intrinsics[5] * r2 * r2;
+    [
+        intrinsics[0] * x * distortion + intrinsics[2],
+        intrinsics[1] * y * distortion + intrinsics[3],
+    ]
+}
+
+fn view_residuals(
+    intrinsics: &[f64],
+    pose: &[f64],
+    model: &[[f64; 2]],
+    observed: &[[f64; 2]],
+) -> Vec<f64> {
+    model
+        .iter()
+        .zip(observed)
+        .flat_map(|(&point, observed)| {
+            let projected = project(intrinsics, pose, point);
+            [projected[0] - observed[0], projected[1] - observed[1]]
+        })
+        .collect()
+}
+
+/// Sum of squared reprojection errors
+fn total_cost(params: &[f64], model: &[[f64; 2]], observed: &[Vec<[f64; 2]>]) -> f64 {
+    observed
+        .iter()
+        .enumerate()
+        .map(|(view, observed)| {
+            let pose = &params[6 + view * 6..12 + view * 6];
+            view_residuals(&params[..6], pose, model, observed)
+                .iter()
+                .map(|residual| residual * residual)
+                .sum::<f64>()
+        })
+        .sum()
+}
+
+/// Levenberg-Marquardt over the intrinsics and all poses, with numeric derivatives
+fn refine(mut params: Vec<f64>, model: &[[f64; 2]], observed: &[Vec<[f64; 2]>]) -> Vec<f64> {
+    let count = params.len();
+    let mut cost = total_cost(&params, model, observed);
+    let mut damping = 1e-3;
+    for _ in 0..MAX_ITERATIONS {
+        // Each view only depends on the intrinsics and its own pose
+        let mut jtj = vec![vec![0.0; count]; count];
+        let mut jtr = vec![0.0; count];
+        for (view, observed) in observed.iter().enumerate() {
+            let indices: Vec<usize> = (0..6).chain(6 + view * 6..12 + view * 6).collect();
+            let local: Vec<f64> = indices.iter().map(|&index| params[index]).collect();
+            let residuals = view_residuals(&local[..6], &local[6..], model, observed);
+            let derivatives: Vec<Vec<f64>> = (0..local.len())
+                .map(|param| {
+                    let step = 1e-6 * local[param].abs().max(1.0);
+                    let mut shifted = local.clone();
+                    shifted[param] += step;
+                    view_residuals(&shifted[..6], &shifted[6..], model, observed)
+                        .iter()
+                        .zip(&residuals)
+                        .map(|(shifted, residual)| (shifted - residual) / step)
+                        .collect()
+                })
+                .collect();
+            for (a, &row) in indices.iter().enumerate() {
+                jtr[row] += dot_slices(&derivatives[a], &residuals);
+                for (b, &column) in indices.iter().enumerate() {
+                    jtj[row][column] += dot_slices(&derivatives[a], &derivatives[b]);
+                }
+            }
+        }
+
+        let mut improved = None;
+        while damping < MAX_DAMPING {
+            let mut damped = jtj.clone();
+            for (index, row) in damped.iter_mut().enumerate() {
+                row[index] += damping * jtj[index][index].max(1e-12);
+            }
+            let step = solve_linear(damped, jtr.iter().map(|value| -value).collect());
+            if let Some(step) = step {
+                let candidate: Vec<f64> = params.iter().zip(&step).map(|(a, b)| a + b).collect();
+                let candidate_cost = total_cost(&candidate, model, observed);
+                if candidate_cost < cost {
+                    improved = Some((cost - candidate_cost) / cost.max(1e-300));
+                    params = candidate;
+                    cost = candidate_cost;
+                    damping /= 10.0;
+                    break;
+                }
+            }
+            damping *= 10.0;
+        }
+        if improved.is_none_or(|improvement| improvement < MIN_IMPROVEMENT) {
+            break;
+        }
+    }
+    params
+}
+
+/// Gaussian elimination with partial pivoting, None if singular
+fn solve_linear(mut a: Vec<Vec<f64>>, mut b: Vec<f64>) -> Option<Vec<f64>> {
+    let count = b.len();
+    for column in 0..count {
+        let pivot =
+            (column..count).max_by(|&i, &j| a[i][column].abs().total_cmp(&a[j][column].abs()))?;
+        if a[pivot][column].abs() < 1e-300 {
+            return None;
+        }
+        a.swap(column, pivot);
+        b.swap(column, pivot);
+        for row in column + 1..count {
+            let factor = a[row][column] / a[column][column];
+            if factor == 0.0 {
+                continue;
+            }
+            let (pivot_rows, rows) = a.split_at_mut(row);
+            for (value, pivot) in rows[0][column..]
+                .iter_mut()
+                .zip(&pivot_rows[column][column..])
+            {
+                *value -= factor * pivot;
+            }
+            b[row] -= factor * b[column];
+        }
+    }
+    let mut x = vec![0.0; count];
+    for row in (0..count).rev() {
+        let sum: f64 = (row + 1..count).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (b[row] - sum) / a[row][row];
+    }
+    Some(x)
+}
+
+/// Rodrigues' formula
+fn vector_to_rotation(vector: Vector3) -> Matrix3 {
+    let angle = norm(vector);
+    if angle < 1e-12 {
+        return [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+    }
+    let [x, y, z] = vector.map(|value| value / angle);
+    let (sin, cos) = angle.sin_cos();
+    let c = 1.0 - cos;
+    [
+        [cos + x * x * c, x * y * c - z * sin, x * z * c + y * sin],
+        [y * x * c + z * sin, cos + y * y * c, y * z * c - x * sin],
+        [z * x * c - y * sin, z * y * c + x * sin, cos + z * z * c],
+    ]
+}
+
+fn rotation_to_vector(rotation: &Matrix3) -> Vector3 {
+    let trace = rotation[0][0] + rotation[1][1] + rotation[2][2];
+    let angle = ((trace - 1.0) / 2.0).clamp(-1.0, 1.0).acos();
+    if angle < 1e-9 {
+        return [0.0; 3];
+    }
+    if std::f64::consts::PI - angle < 1e-6 {
+        // Near a half turn the axis comes from the symmetric part
+        let i = (0..3)
+            .max_by(|&a, &b| rotation[a][a].total_cmp(&rotation[b][b]))
+            .unwrap_or(0);
+        let axis_i = ((rotation[i][i] + 1.0) / 2.0).max(0.0).sqrt();
+        let axis = [0, 1, 2].map(|j| {
+            if j == i {
+                axis_i
+            } else {
+                (rotation[i][j] + rotation[j][i]) / (4.0 * axis_i)
+            }
+        });
+        return scale_vector(normalize(axis), angle);
+    }
+    let axis = [
+        rotation[2][1] - rotation[1][2],
+        rotation[0][2] - rotation[2][0],
+        rotation[1][0] - rotation[0][1],
+    ];
+    scale_vector(axis, angle / (2.0 * angle.sin()))
+}
+
+fn multiply(a: &Matrix3, b: &Matrix3) -> Matrix3 {
+    [0, 1, 2].map(|row| [0, 1, 2].map(|column| (0..3).map(|k| a[row][k] * b[k][column]).sum()))
+}
+
+fn dot(a: Vector3, b: Vector3) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn dot_slices(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+fn cross(a: Vector3, b: Vector3) -> Vector3 {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn norm(a: Vector3) -> f64 {
+    dot(a, a).sqrt()
+}
+
+fn normalize(a: Vector3) -> Vector3 {
+    scale_vector(a, 1.0 / norm(a).max(1e-300))
+}
+
+fn scale_vector(a: Vector3, scale: f64) -> Vector3 {
+    a.map(|value| value * scale)
+}
+
+fn subtract(a: Vector3, b: Vector3) -> Vector3 {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COLUMNS: usize = 9;
+    const ROWS: usize = 6;
+
+    /// Corners of a board seen by a known camera
+    fn project_view(intrinsics: &[f64], pose: &[f64]) -> CalibrationView {
+        let corners = (0..ROWS)
+            .flat_map(|row| (0..COLUMNS).map(move |column| [column as f64, row as f64]))
+            .map(|point| {
+                let [x, y] = project(intrinsics, pose, point);
+                Point {
+                    x: x as f32,
+                    y: y as f32,
+                }
+            })
+            .collect();
+        CalibrationView {
+            key: "test".to_string(),
+            width: 1280,
+            height: 960,
+            columns: COLUMNS,
+            rows: ROWS,
+            corners,
+        }
+    }
+
+    #[test]
+    fn recovers_intrinsics_of_projected_views() {
+        let intrinsics = [1000.0, 1010.0, 650.0, 470.0, -0.05, 0.01];
+        let poses = [
+            [0.3, 0.1, 0.05, -4.0, -2.5, 18.0],
+            [-0.2, 0.35, -0.1, -3.5, -3.0, 16.0],
+            [0.1, -0.3, 0.2, -4.5, -2.0, 20.0],
+            [0.4, -0.2, 0.0, -4.0, -3.5, 17.0],
+        ];
+        let views: Vec<CalibrationView> = poses
+            .iter()
+            .map(|pose| project_view(&intrinsics, pose))
+            .collect();
+
+        let solved = solve_intrinsics(&views).unwrap();
+
+        assert!((solved.focal_length_x - intrinsics[0]).abs() < 1.0);
+        assert!((solved.focal_length_y - intrinsics[1]).abs() < 1.0);
+        assert!((solved.principal_point_x - intrinsics[2]).abs() < 1.0);
+        assert!((solved.principal_point_y - intrinsics[3]).abs() < 1.0);
+        assert!((solved.radial_distortion[0] - intrinsics[4]).abs() < 0.01);
+        // Corners are rounded to f32, so the error is not exactly zero
+        assert!(solved.reprojection_error < 0.01);
+        assert_eq!(solved.views, poses.len());
+    }
+
+    #[test]
+    fn rejects_too_few_views() {
+        let intrinsics = [1000.0, 1000.0, 640.0, 480.0, 0.0, 0.0];
+        let view = project_view(&intrinsics, &[0.2, 0.1, 0.0, -4.0, -2.5, 18.0]);
+        assert!(solve_intrinsics(&[view.clone(), view]).is_err());
+    }
+}
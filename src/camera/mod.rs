@@ -1,5 +1,47 @@
 mod python_camera;
+mod capture_config;
+mod checkerboard;
+mod clip;
+mod colour_checker;
+mod colour_correction;
+mod control_info;
 mod controls;
+mod convergence;
+mod fiducial;
+mod flat_field;
+mod focus;
+mod intrinsics;
+mod motion;
+mod orientation;
+mod overlay;
+mod preset;
+mod preview;
+mod roi;
+mod sensor_mode;
+mod stacking;
+mod statistics;
+mod white_balance;
 
 pub use python_camera::*;
-pub use controls::*;
\ No newline at end of file
+pub use capture_config::*;
+pub use checkerboard::*;
+pub use clip::*;
+pub use colour_checker::*;
+pub use colour_correction::*;
+pub use control_info::*;
+pub use controls::*;
+pub use convergence::*;
+pub use fiducial::*;
+pub use flat_field::*;
+pub use focus::*;
+pub use intrinsics::*;
+pub use motion::*;
+pub use orientation::*;
+pub use overlay::*;
+pub use preset::*;
+pub use preview::*;
+pub use roi::*;
+pub use sensor_mode::*;
+pub use stacking::*;
+pub use statistics::*;
+pub use white_balance::*;
\ No newline at end of file
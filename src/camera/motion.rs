@@ -0,0 +1,144 @@
+use crate::camera::{GrayImage, Roi};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+/// Shortest time between analysed frames
+pub const MIN_MOTION_INTERVAL_MILLIS: u64 = 50;
+
+/// Motion triggered capture settings
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct MotionConfig {
+    /// Part of the frame checked for motion, whole frame if unset
+    #[serde(default)]
+    pub roi: Roi,
+    /// Fraction of region pixels that must change to take a picture
+    pub threshold: f32,
+    /// Brightness change for a pixel to count as changed
+    #[serde(default = "default_pixel_threshold")]
+    pub pixel_threshold: u8,
+    /// Time between analysed frames
+    #[serde(default = "default_interval_millis")]
+    pub interval_millis: u64,
+    /// Shortest time between pictures
+    #[serde(default = "default_cooldown_millis")]
+    pub cooldown_millis: u64,
+    /// Frames are subsampled to about this width for analysis
+    #[serde(default = "default_analysis_width")]
+    pub analysis_width: usize,
+}
+
+fn default_pixel_threshold() -> u8 {
+    25
+}
+
+fn default_interval_millis() -> u64 {
+    200
+}
+
+fn default_cooldown_millis() -> u64 {
+    2000
+}
+
+fn default_analysis_width() -> usize {
+    320
+}
+
+impl MotionConfig {
+    pub fn validate(&self) -> Result<(), anyhow::Error> {
+        if !(self.threshold > 0.0 && self.threshold <= 1.0) {
+            anyhow::bail!("Threshold must be above 0 and at most 1");
+        }
+        if self.interval_millis < MIN_MOTION_INTERVAL_MILLIS {
+            anyhow::bail!(
+                "Interval must be at least {} ms",
+                MIN_MOTION_INTERVAL_MILLIS
+            );
+        }
+        if self.analysis_width < 16 {
+            anyhow::bail!("Analysis width must be at least 16");
+        }
+        // Checks the region is within the frame
+        self.roi
+            .to_pixels(self.analysis_width, self.analysis_width)?;
+        Ok(())
+    }
+}
+
+/// Why motion triggered capture was stopped
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MotionStopReason {
+    /// StopMotionCapture request
+    Requested,
+    /// Cancel topic
+    Cancelled,
+    /// Preview was started, frames are only analysed in still mode
+    CameraModeChanged,
+}
+
+/// Running motion triggered capture
+#[derive(Debug, Clone)]
+pub struct MotionSession {
+    pub config: MotionConfig,
+    pub started: Instant,
+    pub captures: u32,
+    last_capture: Option<Instant>,
+    /// Region of the previous frame
+    previous: Option<Vec<u8>>,
+}
+
+impl MotionSession {
+    pub fn start(config: MotionConfig) -> Self {
+        MotionSession {
+            config,
+            started: Instant::now(),
+            captures: 0,
+            last_capture: None,
+            previous: None,
+        }
+    }
+
+    /// Fraction of region pixels that changed since the previous frame,
+    /// None for the first frame or if the frame size changed
+    pub fn changed_fraction(&mut self, image: &GrayImage) -> Result<Option<f32>, anyhow::Error> {
+        let (x, y, width, height) = self.config.roi.to_pixels(image.width, image.height)?;
+        let region: Vec<u8> = image
+            .pixels
+            .chunks_exact(image.width)
+            .skip(y)
+            .take(height)
+            .flat_map(|row| &row[x..x + width])
+            .copied()
+            .collect();
+
+        let previous = self.previous.replace(region);
+        let (Some(previous), Some(current)) = (previous, &self.previous) else {
+            return Ok(None);
+        };
+        if previous.len() != current.len() {
+            return Ok(None);
+        }
+        let changed = previous
+            .iter()
+            .zip(current)
+            .filter(|(previous, current)| {
+                previous.abs_diff(**current) > self.config.pixel_threshold
+            })
+            .count();
+        Ok(Some(changed as f32 / current.len() as f32))
+    }
+
+    /// Whether the change is above the threshold and the cooldown has passed
+    pub fn should_capture(&self, changed_fraction: f32) -> bool {
+        let cooldown = Duration::from_millis(self.config.cooldown_millis);
+        changed_fraction >= self.config.threshold
+            && self
+                .last_capture
+                .is_none_or(|last_capture| last_capture.elapsed() >= cooldown)
+    }
+
+    pub fn record_capture(&mut self) {
+        self.last_capture = Some(Instant::now());
+        self.captures += 1;
+    }
+}
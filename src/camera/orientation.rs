@@ -0,0 +1,80 @@
+use serde::{Deserialize, Serialize};
+
+/// Clockwise rotation of the image, in degrees when serialized
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(try_from = "u16", into = "u16")]
+pub enum Rotation {
+    #[default]
+    Rotate0,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+}
+
+impl TryFrom<u16> for Rotation {
+    type Error = String;
+
+    fn try_from(degrees: u16) -> Result<Self, Self::Error> {
+        match degrees {
+            0 => Ok(Rotation::Rotate0),
+            90 => Ok(Rotation::Rotate90),
+            180 => Ok(Rotation::Rotate180),
+            270 => Ok(Rotation::Rotate270),
+            _ => Err(format!(
+                "Invalid rotation {}, must be 0, 90, 180 or 270",
+                degrees
+            )),
+        }
+    }
+}
+
+impl From<Rotation> for u16 {
+    fn from(rotation: Rotation) -> Self {
+        match rotation {
+            Rotation::Rotate0 => 0,
+            Rotation::Rotate90 => 90,
+            Rotation::Rotate180 => 180,
+            Rotation::Rotate270 => 270,
+        }
+    }
+}
+
+/// Mounting orientation of the camera. Flips are applied first, then rotation.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Orientation {
+    #[serde(default)]
+    pub hflip: bool,
+    #[serde(default)]
+    pub vflip: bool,
+    #[serde(default)]
+    pub rotation: Rotation,
+}
+
+impl Orientation {
+    /// Horizontal and vertical flip done by libcamera, half turns are done as both flips
+    pub fn transform_flips(&self) -> (bool, bool) {
+        let half_turn = matches!(self.rotation, Rotation::Rotate180 | Rotation::Rotate270);
+        (self.hflip ^ half_turn, self.vflip ^ half_turn)
+    }
+
+    /// Whether a quarter turn is left after libcamera's transform.
+    /// libcamera can't transpose, so it is done when encoding pictures.
+    pub fn quarter_turn(&self) -> bool {
+        matches!(self.rotation, Rotation::Rotate90 | Rotation::Rotate270)
+    }
+}
+
+/// Rotates interleaved pixels a quarter turn clockwise.
+/// Returns rotated pixels, width and height.
+pub fn rotate_quarter_turn(pixels: &[u8], width: usize, height: usize) -> (Vec<u8>, usize, usize) {
+    let channels = pixels.len() / (width * height);
+    let mut rotated = vec![0; pixels.len()];
+    for (y, row) in pixels.chunks_exact(width * channels).enumerate() {
+        for (x, pixel) in row.chunks_exact(channels).enumerate() {
+            let index = (x * height + (height - 1 - y)) * channels;
+            rotated[index..index + channels].copy_from_slice(pixel);
+        }
+    }
+    (rotated, height, width)
+}
@@ -0,0 +1,57 @@
+use pyo3::types::{PyDict, PyDictMethods};
+use pyo3::{Bound, Python};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type")]
+pub enum OverlayGrid {
+    RuleOfThirds,
+    Custom { rows: u32, columns: u32 },
+}
+
+impl OverlayGrid {
+    /// Rows and columns the frame is split into
+    pub fn cells(&self) -> (u32, u32) {
+        match self {
+            OverlayGrid::RuleOfThirds => (3, 3),
+            OverlayGrid::Custom { rows, columns } => (*rows, *columns),
+        }
+    }
+}
+
+/// Overlay burnt into preview frames. Still captures are never affected.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct OverlayConfig {
+    pub enabled: bool,
+    #[serde(default)]
+    pub grid: Option<OverlayGrid>,
+    /// Crosshair in the centre of the frame
+    #[serde(default)]
+    pub crosshair: bool,
+    /// Pi Zero id
+    #[serde(default)]
+    pub label: bool,
+    /// Exposure time and gains from frame metadata
+    #[serde(default)]
+    pub exposure: bool,
+    /// Wall-clock time
+    #[serde(default)]
+    pub timestamp: bool,
+}
+
+impl OverlayConfig {
+    pub fn to_pydict<'py>(
+        &self,
+        py: Python<'py>,
+        pi_zero_id: &str,
+    ) -> Result<Bound<'py, PyDict>, anyhow::Error> {
+        let dict = PyDict::new(py);
+        dict.set_item("grid", self.grid.as_ref().map(OverlayGrid::cells))?;
+        dict.set_item("crosshair", self.crosshair)?;
+        dict.set_item("label", self.label.then_some(pi_zero_id))?;
+        dict.set_item("exposure", self.exposure)?;
+        dict.set_item("timestamp", self.timestamp)?;
+        Ok(dict)
+    }
+}
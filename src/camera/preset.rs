@@ -0,0 +1,10 @@
+use crate::camera::CameraControls;
+use serde::{Deserialize, Serialize};
+
+/// Named controls of both camera modes. Unset modes are left unchanged, when applied.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ControlPreset {
+    pub still_controls: Option<CameraControls>,
+    pub video_controls: Option<CameraControls>,
+}
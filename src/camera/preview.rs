@@ -0,0 +1,194 @@
+use crate::camera::SensorMode;
+use pyo3::types::{PyDict, PyDictMethods};
+use pyo3::{Bound, Python};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_PREVIEW_WIDTH: u32 = 1640;
+pub const DEFAULT_PREVIEW_HEIGHT: u32 = 1232;
+pub const DEFAULT_PREVIEW_PORT: u16 = 8000;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Resolution {
+    pub width: u32,
+    pub height: u32,
+}
+
+/// MJPEG encoder quality, maps to Picamera2's `Quality` enum
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EncoderQuality {
+    VeryLow,
+    Low,
+    #[default]
+    Medium,
+    High,
+    VeryHigh,
+}
+
+impl EncoderQuality {
+    /// Name of the matching Picamera2 `Quality` member
+    pub fn python_name(&self) -> &'static str {
+        match self {
+            EncoderQuality::VeryLow => "VERY_LOW",
+            EncoderQuality::Low => "LOW",
+            EncoderQuality::Medium => "MEDIUM",
+            EncoderQuality::High => "HIGH",
+            EncoderQuality::VeryHigh => "VERY_HIGH",
+        }
+    }
+}
+
+/// Where a preview frame came from
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameSource {
+    /// Latest frame of the running preview stream
+    Preview,
+    /// One-off low resolution still, taken when not in video mode
+    Still,
+}
+
+/// Jpeg encoded preview frame
+#[derive(Debug)]
+pub struct PreviewFrame {
+    pub jpeg: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+    pub source: FrameSource,
+}
+
+/// Effective preview stream configuration, after validation against the sensor modes
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PreviewConfig {
+    pub resolution: Resolution,
+    /// Frame rate limit, None if limited only by the sensor mode
+    pub frame_rate: Option<f32>,
+    pub quality: EncoderQuality,
+    pub port: u16,
+    pub sensor_mode: SensorMode,
+    /// Preview is stopped after this many minutes, None if disabled
+    pub timeout_minutes: Option<u64>,
+    /// Preview is stopped after this many minutes without stream clients, None if disabled
+    pub idle_timeout_minutes: Option<u64>,
+}
+
+impl PreviewConfig {
+    /// Fills in defaults and validates requested values against available sensor modes
+    pub fn new(
+        resolution: Option<Resolution>,
+        frame_rate: Option<f32>,
+        quality: Option<EncoderQuality>,
+        port: Option<u16>,
+        sensor_modes: &[SensorMode],
+    ) -> Result<Self, anyhow::Error> {
+        let resolution = resolution.unwrap_or(Resolution {
+            width: DEFAULT_PREVIEW_WIDTH,
+            height: DEFAULT_PREVIEW_HEIGHT,
+        });
+        if resolution.width == 0 || resolution.height == 0 {
+            anyhow::bail!(
+                "Invalid resolution {}x{}",
+                resolution.width,
+                resolution.height
+            );
+        }
+        if let Some(frame_rate) = frame_rate
+            && (!frame_rate.is_finite() || frame_rate <= 0.0)
+        {
+            anyhow::bail!("Invalid frame rate {}", frame_rate);
+        }
+        let port = port.unwrap_or(DEFAULT_PREVIEW_PORT);
+        if port == 0 {
+            anyhow::bail!("Invalid port {}", port);
+        }
+
+        let sensor_mode = SensorMode::find_best(
+            sensor_modes,
+            resolution.width,
+            resolution.height,
+            frame_rate,
+        )
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "No sensor mode supports {}x{}{}",
+                resolution.width,
+                resolution.height,
+                frame_rate
+                    .map(|fps| format!(" at {} fps", fps))
+                    .unwrap_or_default()
+            )
+        })?
+        .clone();
+
+        Ok(PreviewConfig {
+            resolution,
+            frame_rate,
+            quality: quality.unwrap_or_default(),
+            port,
+            sensor_mode,
+            timeout_minutes: None,
+            idle_timeout_minutes: None,
+        })
+    }
+
+    pub fn to_pydict<'py>(&self, py: Python<'py>) -> Result<Bound<'py, PyDict>, anyhow::Error> {
+        let dict = PyDict::new(py);
+        dict.set_item("size", (self.resolution.width, self.resolution.height))?;
+        dict.set_item("frame_rate", self.frame_rate)?;
+        dict.set_item("quality", self.quality.python_name())?;
+        dict.set_item("port", self.port)?;
+        dict.set_item(
+            "sensor_size",
+            (self.sensor_mode.width, self.sensor_mode.height),
+        )?;
+        dict.set_item("bit_depth", self.sensor_mode.bit_depth)?;
+        Ok(dict)
+    }
+}
+
+/// Why the preview was stopped
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PreviewStopReason {
+    /// StopPreview request
+    Requested,
+    /// Session ran longer than the timeout
+    Timeout,
+    /// No stream clients connected for longer than the idle timeout
+    NoClients,
+}
+
+/// Timing of the running preview, for the automatic timeout
+#[derive(Debug, Clone)]
+pub struct PreviewSession {
+    pub started: Instant,
+    /// Last time a stream client was seen, or the start of the preview
+    pub last_client: Instant,
+}
+
+impl PreviewSession {
+    pub fn start() -> Self {
+        let now = Instant::now();
+        PreviewSession {
+            started: now,
+            last_client: now,
+        }
+    }
+
+    /// Checks if the preview should be stopped, None if it should keep running
+    pub fn expired(&self, preview_config: &PreviewConfig) -> Option<PreviewStopReason> {
+        let minutes = |m: u64| Duration::from_secs(m * 60);
+
+        if let Some(timeout) = preview_config.timeout_minutes
+            && self.started.elapsed() >= minutes(timeout)
+        {
+            return Some(PreviewStopReason::Timeout);
+        }
+        if let Some(idle_timeout) = preview_config.idle_timeout_minutes
+            && self.last_client.elapsed() >= minutes(idle_timeout)
+        {
+            return Some(PreviewStopReason::NoClients);
+        }
+        None
+    }
+}
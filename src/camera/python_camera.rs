@@ -1,11 +1,28 @@
-use crate::camera::CameraControls;
+use crate::camera::{
+    libcamera_control_name, CalibrationFrames, CameraControls, CameraIntrinsics, CaptureConfig,
+    ClipConfig, ColourCorrection, ControlInfo, EffectiveControls, FocusAssistConfig, FocusScore,
+    FrameSource, GrayImage, IspStatistics, MotionSession, Orientation, OverlayConfig, PixelFormat,
+    PreviewConfig, PreviewFrame, PreviewSession, SensorMode, BCM2835_STATS_METADATA_KEY,
+    ISP_STATISTICS_METADATA_KEY,
+};
 use numpy::{PyArrayMethods, PyReadonlyArray1};
 use pyo3::ffi::c_str;
 use pyo3::prelude::{PyAnyMethods, PyDictMethods, PyModule};
 use pyo3::types::{PyDict, PyTuple};
 use pyo3::{Bound, IntoPyObjectExt, Py, PyAny, PyResult, Python};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::collections::HashMap;
+use std::time::Duration;
+
+/// Uncompressed frame with the controls it was taken with
+pub struct Frame {
+    pub pixels: Vec<u8>,
+    pub width: usize,
+    pub height: usize,
+    pub format: PixelFormat,
+    pub effective_controls: EffectiveControls,
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
 pub enum CameraMode {
@@ -18,6 +35,36 @@ pub struct CameraService {
     pub camera_mode: CameraMode,
     pub still_controls: Option<CameraControls>,
     pub video_controls: Option<CameraControls>,
+    pub still_capture_config: CaptureConfig,
+    pub video_capture_config: CaptureConfig,
+    /// Mounting orientation of the camera
+    pub orientation: Orientation,
+    /// Name of the last applied control preset, None if controls were changed since
+    pub active_preset: Option<String>,
+    /// Colour correction matrix of this camera, None if not calibrated
+    pub colour_correction: Option<ColourCorrection>,
+    pub intrinsics: Option<CameraIntrinsics>,
+    /// Calibration frames of the last used calibration key, loaded when a picture needs them
+    pub calibration_frames: Option<CalibrationFrames>,
+    /// Sensor modes, read once on startup
+    pub sensor_modes: Vec<SensorMode>,
+    /// Camera controls with types and enum value names, read once on startup
+    pub control_info: Vec<ControlInfo>,
+    /// Configuration of the running preview, None in still mode
+    pub preview_config: Option<PreviewConfig>,
+    /// Timing of the running preview, None in still mode
+    pub preview_session: Option<PreviewSession>,
+    /// Video capture config the running preview was started with, None in still mode
+    pub preview_capture_config: Option<CaptureConfig>,
+    /// Overlay drawn on preview frames, None if disabled
+    pub overlay_config: Option<OverlayConfig>,
+    /// Running motion triggered capture, None if not running
+    pub motion_session: Option<MotionSession>,
+    /// Focus assist measured on preview frames, None if disabled
+    pub focus_assist: Option<FocusAssistConfig>,
+    /// Json names of controls reset while their camera mode was not active,
+    /// reset in the camera when the mode is entered
+    pub pending_control_resets: Vec<(CameraMode, String)>,
 }
 
 impl CameraService {
@@ -25,8 +72,10 @@ impl CameraService {
         py: Python,
         still_controls: &Option<CameraControls>,
         video_controls: &Option<CameraControls>,
-        still_controls_pydict: Option<Bound<PyDict>>,
-    ) -> PyResult<Self> {
+        still_capture_config: Option<CaptureConfig>,
+        video_capture_config: Option<CaptureConfig>,
+        orientation: Option<Orientation>,
+    ) -> Result<Self, anyhow::Error> {
         println!("Rust - CameraService new");
         // Your Python code as string
         let py_code = c_str!(include_str!(concat!(
@@ -40,23 +89,82 @@ impl CameraService {
         // Get the class
         let class = module.getattr("CameraService")?;
 
-        let still_controls_py = match still_controls_pydict {
-            Some(v) => v.into_py_any(py)?,
-            None => py.None(),
-        };
-
         // Instantiate the class
-        let instance = class.call1((still_controls_py,))?.into_py_any(py)?;
+        let instance = class.call0()?.into_py_any(py)?;
+
+        // Capture configs default to the sensor modes, which are read by the instance
+        let sensor_modes = Self::read_sensor_modes(py, &instance)?;
+        let control_info = Self::read_control_info(py, &instance)?;
+        let still_capture_config =
+            Self::stored_capture_config(&CameraMode::Still, still_capture_config, &sensor_modes);
+        let video_capture_config =
+            Self::stored_capture_config(&CameraMode::Video, video_capture_config, &sensor_modes);
 
         let still_controls = still_controls.clone();
         let video_controls = video_controls.clone();
 
-        Ok(CameraService {
+        let mut camera_service = CameraService {
             instance,
             camera_mode: CameraMode::Still,
             still_controls,
             video_controls,
-        })
+            still_capture_config,
+            video_capture_config,
+            orientation: orientation.unwrap_or_default(),
+            active_preset: None,
+            colour_correction: None,
+            intrinsics: None,
+            calibration_frames: None,
+            sensor_modes,
+            control_info,
+            preview_config: None,
+            preview_session: None,
+            preview_capture_config: None,
+            overlay_config: None,
+            motion_session: None,
+            focus_assist: None,
+            pending_control_resets: Vec::new(),
+        };
+        if let Err(e) = camera_service.configure_still(py) {
+            // Stored configs can pass validation and still be rejected by libcamera
+            println!(
+                "Failed to start camera with stored capture configs and orientation, using defaults: {:?}",
+                e
+            );
+            camera_service.still_capture_config =
+                CaptureConfig::default_for(&CameraMode::Still, &camera_service.sensor_modes);
+            camera_service.video_capture_config =
+                CaptureConfig::default_for(&CameraMode::Video, &camera_service.sensor_modes);
+            camera_service.orientation = Orientation::default();
+            camera_service.configure_still(py)?;
+        }
+
+        Ok(camera_service)
+    }
+
+    /// Stored capture config of the camera mode, default if not stored or invalid
+    fn stored_capture_config(
+        camera_mode: &CameraMode,
+        capture_config: Option<CaptureConfig>,
+        sensor_modes: &[SensorMode],
+    ) -> CaptureConfig {
+        if let Some(capture_config) = capture_config {
+            match capture_config.validate(sensor_modes) {
+                Ok(()) => return capture_config,
+                Err(e) => println!(
+                    "Stored {:?} capture config is invalid, using default: {:?}",
+                    camera_mode, e
+                ),
+            }
+        }
+        CaptureConfig::default_for(camera_mode, sensor_modes)
+    }
+
+    /// Sends capture configs and transform, then starts the camera in still mode
+    fn configure_still(&self, py: Python) -> Result<(), anyhow::Error> {
+        self.send_capture_configs(py)?;
+        self.send_transform(py)?;
+        self.start_still(py)
     }
 
     pub fn capture(&self, py: Python, time: u64) -> PyResult<(Vec<u8>, u16, u16, HashMap<String, String>)> {
@@ -87,6 +195,19 @@ impl CameraService {
                     Ok(dict) => {
                         for (key, value) in dict.iter() {
                             let key_str: Option<String> = key.extract().ok();
+                            // Raw statistics are decoded, instead of stored as a list of bytes
+                            if key_str.as_deref() == Some(BCM2835_STATS_METADATA_KEY) {
+                                match Self::decode_statistics(&value) {
+                                    Ok(statistics) => {
+                                        metadata.insert(
+                                            ISP_STATISTICS_METADATA_KEY.to_string(),
+                                            statistics,
+                                        );
+                                    }
+                                    Err(e) => println!("Statistics could not be decoded: {:?}", e),
+                                }
+                                continue;
+                            }
                             if let Some(key_str) = key_str {
                                 let value_str = value.str().ok();
                                 if let Some(value_str) = value_str {
@@ -113,6 +234,37 @@ impl CameraService {
         Ok((jpeg_bytes, width, height, metadata))
     }
 
+    /// Statistics json of the raw `Bcm2835StatsOutput` metadata
+    fn decode_statistics(value: &Bound<PyAny>) -> Result<String, anyhow::Error> {
+        let bytes: Vec<u8> = value.extract()?;
+        let statistics = IspStatistics::from_bcm2835(&bytes)?;
+        Ok(serde_json::to_string(&statistics)?)
+    }
+
+    pub fn get_preview_frame(&self, py: Python) -> PyResult<PreviewFrame> {
+        let result = self.instance.call_method0(py, "get_preview_frame")?;
+        // Returned tuple with jpeg bytes, size and whether it is from the preview stream
+        let (jpeg, width, height, from_preview): (Vec<u8>, u32, u32, bool) = result.extract(py)?;
+        let source = if from_preview {
+            FrameSource::Preview
+        } else {
+            FrameSource::Still
+        };
+
+        Ok(PreviewFrame {
+            jpeg,
+            width,
+            height,
+            source,
+        })
+    }
+
+    /// Number of clients connected to the preview stream
+    pub fn get_stream_clients(&self, py: Python) -> PyResult<u32> {
+        let result = self.instance.call_method0(py, "get_stream_clients")?;
+        result.extract(py)
+    }
+
     pub fn get_sync_status(&self, py: Python) -> PyResult<(bool, i64)> {
         let result = self.instance.call_method0(py, "get_sync_status")?;
         // Returned tuple with array and metadata
@@ -126,12 +278,212 @@ impl CameraService {
         Ok((sync_ready, sync_timer))
     }
 
+    fn read_sensor_modes(py: Python, instance: &Py<PyAny>) -> PyResult<Vec<SensorMode>> {
+        let result = instance.call_method0(py, "get_sensor_modes")?;
+        // List of (format, bit depth, width, height, fps) tuples
+        let modes: Vec<(String, u8, u32, u32, f32)> = result.extract(py)?;
+
+        Ok(modes
+            .into_iter()
+            .map(|(format, bit_depth, width, height, fps)| SensorMode {
+                format,
+                bit_depth,
+                width,
+                height,
+                fps,
+            })
+            .collect())
+    }
+
+    fn read_control_info(
+        py: Python,
+        instance: &Py<PyAny>,
+    ) -> Result<Vec<ControlInfo>, anyhow::Error> {
+        let result = instance.call_method0(py, "get_control_info")?;
+        let json: String = result.extract(py)?;
+        Ok(serde_json::from_str(&json)?)
+    }
+
+    /// Sends both capture configs to python, they are used on the next configure
+    fn send_capture_configs(&self, py: Python) -> Result<(), anyhow::Error> {
+        let still_capture_config_py = self.still_capture_config.to_pydict(py)?;
+        let video_capture_config_py = self.video_capture_config.to_pydict(py)?;
+        self.instance.call_method1(
+            py,
+            "set_capture_config",
+            (still_capture_config_py, video_capture_config_py),
+        )?;
+        Ok(())
+    }
+
+    /// Sends libcamera transform of the orientation to python, used on the next configure
+    fn send_transform(&self, py: Python) -> Result<(), anyhow::Error> {
+        let (hflip, vflip) = self.orientation.transform_flips();
+        self.instance
+            .call_method1(py, "set_transform", (hflip, vflip))?;
+        Ok(())
+    }
+
+    /// Sets orientation of the camera. Camera is reconfigured, if it is in still mode,
+    /// otherwise orientation is used the next time the camera is configured.
+    /// Returns whether the orientation is in use already.
+    pub fn set_orientation(
+        &mut self,
+        py: Python,
+        orientation: &Orientation,
+    ) -> Result<bool, anyhow::Error> {
+        let previous = std::mem::replace(&mut self.orientation, *orientation);
+
+        let applied = self.camera_mode == CameraMode::Still;
+        let result = self.send_transform(py).and_then(|_| {
+            if applied {
+                self.start_still(py)?;
+            }
+            Ok(())
+        });
+
+        if let Err(e) = result {
+            // Restore previous orientation, so camera keeps working
+            self.orientation = previous;
+            self.send_transform(py)?;
+            if applied {
+                self.start_still(py)?;
+            }
+            return Err(e);
+        }
+
+        Ok(applied)
+    }
+
+    /// Configures the camera with still capture config and controls and starts it
+    fn start_still(&self, py: Python) -> Result<(), anyhow::Error> {
+        let still_controls_py = match &self.still_controls {
+            Some(v) => v.to_pydict(py)?.into_py_any(py)?,
+            None => py.None(),
+        };
+        self.instance
+            .call_method1(py, "start_still", (still_controls_py,))?;
+        Ok(())
+    }
+
+    /// Sets capture config of the camera mode. Camera is reconfigured, if it is in
+    /// still mode and still config is set, otherwise config is used the next time the
+    /// mode is configured. Returns whether the config is in use already.
+    pub fn set_capture_config(
+        &mut self,
+        py: Python,
+        camera_mode: &CameraMode,
+        capture_config: &CaptureConfig,
+    ) -> Result<bool, anyhow::Error> {
+        let capture_config_field = match camera_mode {
+            CameraMode::Still => &mut self.still_capture_config,
+            CameraMode::Video => &mut self.video_capture_config,
+        };
+        let previous = std::mem::replace(capture_config_field, capture_config.clone());
+
+        // Still mode is reconfigured right away, a running preview keeps its config
+        let restart = *camera_mode == CameraMode::Still && self.camera_mode == CameraMode::Still;
+        let result = self.send_capture_configs(py).and_then(|_| {
+            if restart {
+                self.start_still(py)?;
+            }
+            Ok(())
+        });
+
+        if let Err(e) = result {
+            // Restore previous config, so camera keeps working
+            match camera_mode {
+                CameraMode::Still => self.still_capture_config = previous,
+                CameraMode::Video => self.video_capture_config = previous,
+            }
+            self.send_capture_configs(py)?;
+            if restart {
+                self.start_still(py)?;
+            }
+            return Err(e);
+        }
+
+        Ok(self.capture_config_applied(camera_mode))
+    }
+
+    /// Whether the camera is running with the stored capture config of the mode
+    pub fn capture_config_applied(&self, camera_mode: &CameraMode) -> bool {
+        match camera_mode {
+            CameraMode::Still => self.camera_mode == CameraMode::Still,
+            CameraMode::Video => {
+                self.preview_capture_config.as_ref() == Some(&self.video_capture_config)
+            }
+        }
+    }
+
     pub fn set_controls(&self, py: Python, controls: Bound<PyDict>) -> PyResult<()> {
         self.instance
             .call_method1(py, "set_controls", (controls,))?;
         Ok(())
     }
 
+    /// Next frame in the current camera mode
+    pub fn capture_frame(&self, py: Python) -> Result<Frame, anyhow::Error> {
+        let result = self.instance.call_method0(py, "capture_frame")?;
+        let (pixels, width, height, metadata): (PyReadonlyArray1<u8>, usize, usize, Bound<PyDict>) =
+            result.extract(py)?;
+        let format = match self.camera_mode {
+            CameraMode::Still => self.still_capture_config.format,
+            CameraMode::Video => self.video_capture_config.format,
+        };
+
+        Ok(Frame {
+            pixels: pixels.to_vec()?,
+            width,
+            height,
+            format,
+            effective_controls: EffectiveControls::from_metadata(&metadata),
+        })
+    }
+
+    /// Luma of the next frame, subsampled to about the width
+    pub fn capture_motion_frame(
+        &self,
+        py: Python,
+        width: usize,
+    ) -> Result<GrayImage, anyhow::Error> {
+        let result = self
+            .instance
+            .call_method1(py, "capture_motion_frame", (width,))?;
+        let (pixels, width, height): (PyReadonlyArray1<u8>, usize, usize) = result.extract(py)?;
+        let format = match self.camera_mode {
+            CameraMode::Still => self.still_capture_config.format,
+            CameraMode::Video => self.video_capture_config.format,
+        };
+        Ok(GrayImage::new(pixels.as_slice()?, width, height, format))
+    }
+
+    /// Metadata of the next completed frame
+    pub fn get_frame_metadata<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let result = self.instance.call_method0(py, "get_frame_metadata")?;
+        let dict = result.downcast_bound::<PyDict>(py)?;
+        Ok(dict.clone())
+    }
+
+    /// Runs an autofocus cycle, returns controls of the frame it finished on
+    pub fn auto_focus(
+        &self,
+        py: Python,
+        timeout: Duration,
+    ) -> Result<EffectiveControls, anyhow::Error> {
+        let result = self
+            .instance
+            .call_method1(py, "auto_focus", (timeout.as_secs_f64(),))?;
+        let metadata: Bound<PyDict> = result.extract(py)?;
+        Ok(EffectiveControls::from_metadata(&metadata))
+    }
+
+    /// Sets controls to camera defaults, by libcamera name
+    pub fn reset_controls(&self, py: Python, names: Vec<String>) -> PyResult<()> {
+        self.instance.call_method1(py, "reset_controls", (names,))?;
+        Ok(())
+    }
+
     pub fn get_controls_limits<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
         let result = self.instance.call_method0(py, "get_controls")?;
         let dict = result.downcast_bound::<PyDict>(py)?;
@@ -142,22 +494,27 @@ impl CameraService {
         &mut self,
         py: Python,
         video_controls_pydict: Option<Bound<PyDict>>,
-    ) -> PyResult<()> {
+        preview_config: &PreviewConfig,
+    ) -> Result<(), anyhow::Error> {
         let video_controls_py = match video_controls_pydict {
             Some(v) => v.into_py_any(py)?,
             None => py.None(),
         };
+        let preview_config_py = preview_config.to_pydict(py)?;
         self.instance
-            .call_method1(py, "start_preview", (video_controls_py,))?;
+            .call_method1(py, "start_preview", (video_controls_py, preview_config_py))?;
         self.camera_mode = CameraMode::Video;
-        Ok(())
+        self.preview_config = Some(preview_config.clone());
+        self.preview_session = Some(PreviewSession::start());
+        self.preview_capture_config = Some(self.video_capture_config.clone());
+        self.apply_pending_control_resets(py)
     }
 
     pub fn stop_preview(
         &mut self,
         py: Python,
         still_controls_pydict: Option<Bound<PyDict>>,
-    ) -> PyResult<()> {
+    ) -> Result<(), anyhow::Error> {
         let still_controls_py = match still_controls_pydict {
             Some(v) => v.into_py_any(py)?,
             None => py.None(),
@@ -165,9 +522,121 @@ impl CameraService {
         self.instance
             .call_method1(py, "stop_preview", (still_controls_py,))?;
         self.camera_mode = CameraMode::Still;
+        self.preview_config = None;
+        self.preview_session = None;
+        self.preview_capture_config = None;
+        self.apply_pending_control_resets(py)
+    }
+
+    /// Resets controls that were reset while the current camera mode was not active.
+    /// Controls stored again since then are left as they are.
+    fn apply_pending_control_resets(&mut self, py: Python) -> Result<(), anyhow::Error> {
+        let (pending, other_modes) = std::mem::take(&mut self.pending_control_resets)
+            .into_iter()
+            .partition::<Vec<_>, _>(|(camera_mode, _)| *camera_mode == self.camera_mode);
+        self.pending_control_resets = other_modes;
+
+        let stored_controls = match self.camera_mode {
+            CameraMode::Still => &self.still_controls,
+            CameraMode::Video => &self.video_controls,
+        };
+        let stored_controls = stored_controls.clone().unwrap_or_default();
+        let Value::Object(fields) = serde_json::to_value(stored_controls)? else {
+            anyhow::bail!("Controls are not a json object");
+        };
+        let names: Vec<String> = pending
+            .iter()
+            .map(|(_, field)| field)
+            .filter(|field| fields.get(*field).is_none_or(Value::is_null))
+            .map(|field| libcamera_control_name(field))
+            .collect();
+        if !names.is_empty() {
+            self.reset_controls(py, names)?;
+        }
+        Ok(())
+    }
+
+    pub fn set_overlay(
+        &mut self,
+        py: Python,
+        overlay_config: &OverlayConfig,
+        pi_zero_id: &str,
+    ) -> Result<(), anyhow::Error> {
+        let overlay_config = overlay_config.enabled.then(|| overlay_config.clone());
+        let overlay_py = match &overlay_config {
+            Some(v) => v.to_pydict(py, pi_zero_id)?.into_py_any(py)?,
+            None => py.None(),
+        };
+        self.instance
+            .call_method1(py, "set_overlay", (overlay_py,))?;
+        self.overlay_config = overlay_config;
+        Ok(())
+    }
+
+    /// Starts measuring sharpness on preview frames, None stops it. Resets the running max.
+    pub fn set_focus_assist(
+        &mut self,
+        py: Python,
+        focus_assist: Option<&FocusAssistConfig>,
+    ) -> Result<(), anyhow::Error> {
+        let focus_assist_py = match focus_assist {
+            Some(v) => v.to_pydict(py)?.into_py_any(py)?,
+            None => py.None(),
+        };
+        self.instance
+            .call_method1(py, "set_focus_assist", (focus_assist_py,))?;
+        self.focus_assist = focus_assist.cloned();
         Ok(())
     }
 
+    /// Sharpness of the latest preview frame, None if no frame was measured yet
+    pub fn get_focus_score(&self, py: Python) -> PyResult<Option<FocusScore>> {
+        let result = self.instance.call_method0(py, "get_focus_score")?;
+        let Some((score, max_score, sensor_timestamp)) = result.extract(py)? else {
+            return Ok(None);
+        };
+        Ok(Some(FocusScore {
+            score,
+            max_score,
+            sensor_timestamp,
+        }))
+    }
+
+    /// Records clip between start and end (monotonic nanoseconds) to file,
+    /// using video controls. Returns sensor timestamps of recorded frames.
+    pub fn record_clip(
+        &self,
+        py: Python,
+        clip_config: &ClipConfig,
+        start: u64,
+        end: u64,
+        path: &str,
+    ) -> Result<Vec<i64>, anyhow::Error> {
+        let video_controls_py = match &self.video_controls {
+            Some(v) => v.to_pydict(py)?.into_py_any(py)?,
+            None => py.None(),
+        };
+        // Still configuration is restored after recording
+        let still_controls_py = match &self.still_controls {
+            Some(v) => v.to_pydict(py)?.into_py_any(py)?,
+            None => py.None(),
+        };
+        let clip_config_py = clip_config.to_pydict(py)?;
+        let result = self.instance.call_method1(
+            py,
+            "record_clip",
+            (
+                video_controls_py,
+                still_controls_py,
+                clip_config_py,
+                start,
+                end,
+                path,
+            ),
+        )?;
+        Ok(result.extract(py)?)
+    }
+
     pub fn stop(&self, py: Python) -> PyResult<()> {
         self.instance.call_method0(py, "stop")?;
         Ok(())
@@ -0,0 +1,82 @@
+use crate::camera::PixelFormat;
+use serde::{Deserialize, Serialize};
+
+/// Region of a frame, normalized to 0..1 of the frame size
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Roi {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Default for Roi {
+    fn default() -> Self {
+        Roi {
+            x: 0.0,
+            y: 0.0,
+            width: 1.0,
+            height: 1.0,
+        }
+    }
+}
+
+impl Roi {
+    /// Pixel rectangle (x, y, width, height) of the region, at least one pixel
+    pub fn to_pixels(
+        self,
+        width: usize,
+        height: usize,
+    ) -> Result<(usize, usize, usize, usize), anyhow::Error> {
+        let valid = |start: f32, size: f32| {
+            (0.0..1.0).contains(&start) && size > 0.0 && start + size <= 1.0
+        };
+        if !valid(self.x, self.width) || !valid(self.y, self.height) {
+            anyhow::bail!("Region must be within 0..1 of the frame: {:?}", self);
+        }
+
+        let x = (self.x * width as f32) as usize;
+        let y = (self.y * height as f32) as usize;
+        let roi_width = ((self.width * width as f32) as usize).clamp(1, width - x);
+        let roi_height = ((self.height * height as f32) as usize).clamp(1, height - y);
+        Ok((x, y, roi_width, roi_height))
+    }
+}
+
+/// Mean red, green and blue of the region
+pub fn mean_rgb(
+    pixels: &[u8],
+    width: usize,
+    height: usize,
+    format: PixelFormat,
+    roi: &Roi,
+) -> Result<[f32; 3], anyhow::Error> {
+    let (offsets, channels) = format.rgb_offsets();
+    if pixels.len() != width * height * channels {
+        anyhow::bail!(
+            "Frame is {} bytes, expected {}x{}x{}",
+            pixels.len(),
+            width,
+            height,
+            channels
+        );
+    }
+    let (x, y, roi_width, roi_height) = roi.to_pixels(width, height)?;
+
+    let mut sums = [0u64; 3];
+    for row in pixels
+        .chunks_exact(width * channels)
+        .skip(y)
+        .take(roi_height)
+    {
+        for pixel in row[x * channels..(x + roi_width) * channels].chunks_exact(channels) {
+            for (sum, offset) in sums.iter_mut().zip(offsets) {
+                *sum += pixel[offset] as u64;
+            }
+        }
+    }
+
+    let count = (roi_width * roi_height) as f32;
+    Ok(sums.map(|sum| sum as f32 / count))
+}
@@ -0,0 +1,34 @@
+use serde::{Deserialize, Serialize};
+
+/// Sensor readout mode, as reported by Picamera2
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SensorMode {
+    pub format: String,
+    pub bit_depth: u8,
+    pub width: u32,
+    pub height: u32,
+    /// Maximum frame rate of the mode
+    pub fps: f32,
+}
+
+impl SensorMode {
+    /// Finds the smallest sensor mode that covers the resolution and can reach the frame rate
+    pub fn find_best(
+        sensor_modes: &[SensorMode],
+        width: u32,
+        height: u32,
+        frame_rate: Option<f32>,
+    ) -> Option<&SensorMode> {
+        sensor_modes
+            .iter()
+            .filter(|mode| mode.width >= width && mode.height >= height)
+            .filter(|mode| frame_rate.is_none_or(|fps| mode.fps >= fps))
+            .min_by_key(|mode| {
+                (
+                    mode.width as u64 * mode.height as u64,
+                    u8::MAX - mode.bit_depth,
+                )
+            })
+    }
+}
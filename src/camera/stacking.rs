@@ -0,0 +1,261 @@
+use crate::camera::{CameraService, FrameAccumulator, PixelFormat};
+use pyo3::Python;
+use serde::Serialize;
+use std::collections::HashMap;
+
+pub const MAX_STACK_FRAMES: u32 = 16;
+/// Fewer values don't give a meaningful standard deviation
+const MIN_SIGMA_CLIP_FRAMES: usize = 3;
+/// Sigma clipping keeps every frame in memory, this much is left for Python and encoding
+const SIGMA_CLIP_MEMORY_HEADROOM: u64 = 96 * 1024 * 1024;
+
+/// Describes the frames averaged into a picture
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct StackInfo {
+    pub requested_frames: u32,
+    /// Frames actually averaged, capturing stops at the first failed frame
+    pub frames: u32,
+    /// Sensor timestamp of the first and last frame, nanoseconds
+    pub first_timestamp: Option<u64>,
+    pub last_timestamp: Option<u64>,
+    pub time_span_nanos: Option<u64>,
+    pub sigma_clip: Option<f32>,
+    /// Pixel values left out by sigma clipping
+    pub clipped_values: u64,
+}
+
+/// Averaged picture with the metadata of its first frame
+pub struct StackedPicture {
+    pub pixels: Vec<u8>,
+    pub width: u16,
+    pub height: u16,
+    pub metadata: HashMap<String, String>,
+    pub info: StackInfo,
+}
+
+impl CameraService {
+    /// Captures consecutive frames from the scheduled time and averages them
+    pub fn capture_stack(
+        &self,
+        py: Python,
+        time: u64,
+        frames: u32,
+        sigma_clip: Option<f32>,
+    ) -> Result<StackedPicture, anyhow::Error> {
+        if !(1..=MAX_STACK_FRAMES).contains(&frames) {
+            anyhow::bail!("Stack frames must be between 1 and {}", MAX_STACK_FRAMES);
+        }
+        if sigma_clip.is_some_and(|sigma| sigma <= 0.0) {
+            anyhow::bail!("Sigma clip must be positive");
+        }
+
+        let (first, width, height, metadata) = self.capture(py, time)?;
+        if sigma_clip.is_some() {
+            check_stack_memory(first.len(), frames)?;
+        }
+        let format = self.still_capture_config.format;
+        let first_timestamp = sensor_timestamp(&metadata);
+        let mut last_timestamp = first_timestamp;
+        // Without sigma clipping frames are only summed, so a stack takes the memory of two frames
+        let mut stack = match sigma_clip {
+            Some(_) => FrameStack::Kept(vec![first]),
+            None => {
+                let mut accumulator = FrameAccumulator::with_size(
+                    width as usize,
+                    height as usize,
+                    format,
+                    first.len(),
+                );
+                accumulator.add_pixels(&first, width as usize, height as usize, format)?;
+                FrameStack::Summed(accumulator)
+            }
+        };
+        while stack.len() < frames {
+            // Frames after the last one, so none is stacked twice
+            let next_time = last_timestamp.map_or(time, |timestamp| timestamp + 1);
+            let (pixels, frame_width, frame_height, frame_metadata) =
+                match self.capture(py, next_time) {
+                    Ok(frame) => frame,
+                    Err(e) => {
+                        println!("Failed to capture frame to stack: {:?}", e);
+                        break;
+                    }
+                };
+            if frame_width != width || frame_height != height {
+                println!("Frame size changed while stacking");
+                break;
+            }
+            if let Err(e) = stack.add(pixels, width as usize, height as usize, format) {
+                println!("Failed to stack frame: {:?}", e);
+                break;
+            }
+            last_timestamp = sensor_timestamp(&frame_metadata);
+        }
+
+        let stacked_frames = stack.len();
+        let (pixels, clipped_values) = match stack {
+            FrameStack::Summed(accumulator) => (accumulator.mean_pixels(), 0),
+            FrameStack::Kept(frames) => stack_frames(&frames, sigma_clip),
+        };
+        let info = StackInfo {
+            requested_frames: frames,
+            frames: stacked_frames,
+            first_timestamp,
+            last_timestamp,
+            time_span_nanos: first_timestamp
+                .zip(last_timestamp)
+                .map(|(first, last)| last.saturating_sub(first)),
+            sigma_clip,
+            clipped_values,
+        };
+        Ok(StackedPicture {
+            pixels,
+            width,
+            height,
+            metadata,
+            info,
+        })
+    }
+}
+
+/// Frames being stacked
+enum FrameStack {
+    /// Running sum, enough for a plain average
+    Summed(FrameAccumulator),
+    /// Every frame, needed for the per pixel standard deviation of sigma clipping
+    Kept(Vec<Vec<u8>>),
+}
+
+impl FrameStack {
+    fn len(&self) -> u32 {
+        match self {
+            FrameStack::Summed(accumulator) => accumulator.frames(),
+            FrameStack::Kept(frames) => frames.len() as u32,
+        }
+    }
+
+    fn add(
+        &mut self,
+        pixels: Vec<u8>,
+        width: usize,
+        height: usize,
+        format: PixelFormat,
+    ) -> Result<(), anyhow::Error> {
+        match self {
+            FrameStack::Summed(accumulator) => {
+                accumulator.add_pixels(&pixels, width, height, format)
+            }
+            FrameStack::Kept(frames) => {
+                if pixels.len() != frames[0].len() {
+                    anyhow::bail!("Frame size changed while stacking");
+                }
+                frames.push(pixels);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Checks there is memory to keep every frame of a sigma clipped stack
+fn check_stack_memory(frame_bytes: usize, frames: u32) -> Result<(), anyhow::Error> {
+    let required = frame_bytes as u64 * frames as u64 + SIGMA_CLIP_MEMORY_HEADROOM;
+    let available = available_memory()?;
+    if required > available {
+        anyhow::bail!(
+            "Sigma clipping {} frames needs {} MB, {} MB available, use fewer frames",
+            frames,
+            required / (1024 * 1024),
+            available / (1024 * 1024)
+        );
+    }
+    Ok(())
+}
+
+/// MemAvailable from /proc/meminfo, bytes
+fn available_memory() -> Result<u64, anyhow::Error> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo")?;
+    meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemAvailable:"))
+        .and_then(|value| {
+            value
+                .trim()
+                .trim_end_matches("kB")
+                .trim()
+                .parse::<u64>()
+                .ok()
+        })
+        .map(|kilobytes| kilobytes * 1024)
+        .ok_or_else(|| anyhow::anyhow!("MemAvailable missing from /proc/meminfo"))
+}
+
+/// Averages frames of the same size. With sigma clipping, values further than sigma
+/// standard deviations from the mean are left out. Returns the number of clipped values.
+pub fn stack_frames(frames: &[Vec<u8>], sigma_clip: Option<f32>) -> (Vec<u8>, u64) {
+    let Some(first) = frames.first() else {
+        return (Vec::new(), 0);
+    };
+    let count = frames.len() as f32;
+    let sigma_clip = sigma_clip.filter(|_| frames.len() >= MIN_SIGMA_CLIP_FRAMES);
+    let mut clipped_values = 0u64;
+    let pixels = (0..first.len())
+        .map(|index| {
+            let sum: u32 = frames.iter().map(|frame| frame[index] as u32).sum();
+            let mean = sum as f32 / count;
+            let Some(sigma) = sigma_clip else {
+                return mean.round() as u8;
+            };
+            let variance = frames
+                .iter()
+                .map(|frame| (frame[index] as f32 - mean).powi(2))
+                .sum::<f32>()
+                / count;
+            let limit = sigma * variance.sqrt();
+            let (kept_sum, kept) = frames
+                .iter()
+                .map(|frame| frame[index] as f32)
+                .filter(|value| (value - mean).abs() <= limit)
+                .fold((0.0, 0u32), |(sum, kept), value| (sum + value, kept + 1));
+            clipped_values += frames.len() as u64 - kept as u64;
+            if kept == 0 {
+                mean.round() as u8
+            } else {
+                (kept_sum / kept as f32).round() as u8
+            }
+        })
+        .collect();
+    (pixels, clipped_values)
+}
+
+fn sensor_timestamp(metadata: &HashMap<String, String>) -> Option<u64> {
+    metadata
+        .get("SensorTimestamp")
+        .and_then(|timestamp| timestamp.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sigma_clipping_rejects_outlier() {
+        // A hot pixel in one frame, noise free elsewhere
+        let mut frames = vec![vec![100, 50, 200]; 6];
+        frames[2][0] = 250;
+
+        let (averaged, clipped) = stack_frames(&frames, None);
+        assert_eq!(averaged, vec![125, 50, 200]);
+        assert_eq!(clipped, 0);
+
+        let (stacked, clipped) = stack_frames(&frames, Some(2.0));
+        assert_eq!(stacked, vec![100, 50, 200]);
+        assert_eq!(clipped, 1);
+    }
+
+    #[test]
+    fn too_few_frames_are_averaged() {
+        let frames = vec![vec![10], vec![20]];
+        assert_eq!(stack_frames(&frames, Some(1.0)), (vec![15], 0));
+    }
+}
@@ -0,0 +1,184 @@
+use serde::{Deserialize, Serialize};
+
+/// Frame metadata key of the raw VC4 ISP statistics, present if StatsOutputEnable is set
+pub const BCM2835_STATS_METADATA_KEY: &str = "Bcm2835StatsOutput";
+/// Picture metadata key the decoded statistics are stored under until the picture is saved
+pub const ISP_STATISTICS_METADATA_KEY: &str = "IspStatistics";
+
+pub const HISTOGRAM_BINS: usize = 128;
+pub const AWB_REGIONS_X: usize = 16;
+pub const AWB_REGIONS_Y: usize = 12;
+pub const AGC_REGIONS: usize = 16;
+
+// Layout of `struct bcm2835_isp_stats` from the bcm2835-isp uapi header
+const HISTOGRAMS: usize = 2;
+const FLOATING_REGIONS: usize = 16;
+const FOCUS_REGIONS: usize = 12;
+const HEADER_SIZE: usize = 8;
+const HISTOGRAM_SIZE: usize = 3 * HISTOGRAM_BINS * 4;
+const REGION_SIZE: usize = 32;
+const FOCUS_REGION_SIZE: usize = 48;
+const AWB_OFFSET: usize = HEADER_SIZE + HISTOGRAMS * HISTOGRAM_SIZE;
+const AGC_OFFSET: usize =
+    AWB_OFFSET + (AWB_REGIONS_X * AWB_REGIONS_Y + FLOATING_REGIONS) * REGION_SIZE;
+const STATS_SIZE: usize =
+    AGC_OFFSET + AGC_REGIONS * REGION_SIZE + FOCUS_REGIONS * FOCUS_REGION_SIZE;
+
+/// Per channel histogram of a frame
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Histogram {
+    pub r: Vec<u32>,
+    pub g: Vec<u32>,
+    pub b: Vec<u32>,
+}
+
+/// Pixel sums of a statistics region
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct RegionStatistics {
+    /// Pixels within the ISP's thresholds, the sums only include these
+    pub counted: u32,
+    pub not_counted: u32,
+    pub r_sum: u64,
+    pub g_sum: u64,
+    pub b_sum: u64,
+}
+
+/// AGC, AWB and histogram statistics the ISP produced for a frame
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct IspStatistics {
+    pub version: u32,
+    /// Histogram the AGC uses, with 128 bins per channel
+    pub histogram: Histogram,
+    pub awb_columns: usize,
+    pub awb_rows: usize,
+    /// AWB regions, row by row
+    pub awb_regions: Vec<RegionStatistics>,
+    pub agc_regions: Vec<RegionStatistics>,
+}
+
+impl IspStatistics {
+    /// Decodes the raw `Bcm2835StatsOutput` metadata.
+    /// Floating and focus regions are not exported.
+    pub fn from_bcm2835(bytes: &[u8]) -> Result<Self, anyhow::Error> {
+        if bytes.len() < STATS_SIZE {
+            anyhow::bail!(
+                "Statistics are {} bytes, expected at least {}",
+                bytes.len(),
+                STATS_SIZE
+            );
+        }
+
+        let version = read_u32(bytes, 0);
+        let histogram = Histogram {
+            r: read_u32s(bytes, HEADER_SIZE, HISTOGRAM_BINS),
+            g: read_u32s(bytes, HEADER_SIZE + HISTOGRAM_BINS * 4, HISTOGRAM_BINS),
+            b: read_u32s(bytes, HEADER_SIZE + 2 * HISTOGRAM_BINS * 4, HISTOGRAM_BINS),
+        };
+
+        Ok(IspStatistics {
+            version,
+            histogram,
+            awb_columns: AWB_REGIONS_X,
+            awb_rows: AWB_REGIONS_Y,
+            awb_regions: read_regions(bytes, AWB_OFFSET, AWB_REGIONS_X * AWB_REGIONS_Y),
+            agc_regions: read_regions(bytes, AGC_OFFSET, AGC_REGIONS),
+        })
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+fn read_u32s(bytes: &[u8], offset: usize, count: usize) -> Vec<u32> {
+    (0..count)
+        .map(|i| read_u32(bytes, offset + i * 4))
+        .collect()
+}
+
+fn read_regions(bytes: &[u8], offset: usize, count: usize) -> Vec<RegionStatistics> {
+    (0..count)
+        .map(|i| {
+            let offset = offset + i * REGION_SIZE;
+            RegionStatistics {
+                counted: read_u32(bytes, offset),
+                not_counted: read_u32(bytes, offset + 4),
+                r_sum: read_u64(bytes, offset + 8),
+                g_sum: read_u64(bytes, offset + 16),
+                b_sum: read_u64(bytes, offset + 24),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_u32(bytes: &mut [u8], offset: usize, value: u32) {
+        bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn write_u64(bytes: &mut [u8], offset: usize, value: u64) {
+        bytes[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn write_region(bytes: &mut [u8], offset: usize, region: [u64; 5]) {
+        write_u32(bytes, offset, region[0] as u32);
+        write_u32(bytes, offset + 4, region[1] as u32);
+        write_u64(bytes, offset + 8, region[2]);
+        write_u64(bytes, offset + 16, region[3]);
+        write_u64(bytes, offset + 24, region[4]);
+    }
+
+    #[test]
+    fn decodes_bcm2835_statistics() {
+        let mut bytes = vec![0u8; STATS_SIZE];
+        write_u32(&mut bytes, 0, 1);
+        write_u32(&mut bytes, HEADER_SIZE, 11);
+        write_u32(&mut bytes, HEADER_SIZE + (HISTOGRAM_BINS - 1) * 4, 12);
+        write_u32(&mut bytes, HEADER_SIZE + HISTOGRAM_BINS * 4 + 4, 21);
+        write_u32(&mut bytes, HEADER_SIZE + 2 * HISTOGRAM_BINS * 4 + 8, 31);
+        // Second histogram is not exported
+        write_u32(&mut bytes, HEADER_SIZE + HISTOGRAM_SIZE, 99);
+        write_region(&mut bytes, AWB_OFFSET, [100, 2, 1000, 2000, 3000]);
+        let last_awb = AWB_OFFSET + (AWB_REGIONS_X * AWB_REGIONS_Y - 1) * REGION_SIZE;
+        write_region(&mut bytes, last_awb, [200, 4, 5000, 6000, 1 << 40]);
+        write_region(&mut bytes, AGC_OFFSET + REGION_SIZE, [300, 6, 7, 8, 9]);
+
+        let statistics = IspStatistics::from_bcm2835(&bytes).unwrap();
+
+        assert_eq!(statistics.version, 1);
+        assert_eq!(statistics.histogram.r.len(), HISTOGRAM_BINS);
+        assert_eq!(statistics.histogram.r[0], 11);
+        assert_eq!(statistics.histogram.r[HISTOGRAM_BINS - 1], 12);
+        assert_eq!(statistics.histogram.g[1], 21);
+        assert_eq!(statistics.histogram.b[2], 31);
+        assert!(!statistics.histogram.b.contains(&99));
+
+        assert_eq!(statistics.awb_regions.len(), AWB_REGIONS_X * AWB_REGIONS_Y);
+        let first = statistics.awb_regions[0];
+        assert_eq!((first.counted, first.not_counted), (100, 2));
+        assert_eq!((first.r_sum, first.g_sum, first.b_sum), (1000, 2000, 3000));
+        let last = statistics.awb_regions[AWB_REGIONS_X * AWB_REGIONS_Y - 1];
+        assert_eq!((last.counted, last.b_sum), (200, 1 << 40));
+
+        assert_eq!(statistics.agc_regions.len(), AGC_REGIONS);
+        let agc = statistics.agc_regions[1];
+        assert_eq!((agc.counted, agc.not_counted), (300, 6));
+        assert_eq!((agc.r_sum, agc.g_sum, agc.b_sum), (7, 8, 9));
+        assert_eq!(statistics.agc_regions[0].counted, 0);
+    }
+
+    #[test]
+    fn rejects_short_statistics() {
+        assert!(IspStatistics::from_bcm2835(&vec![0u8; STATS_SIZE - 1]).is_err());
+    }
+}
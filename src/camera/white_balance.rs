@@ -0,0 +1,43 @@
+use crate::camera::ColourGain;
+
+/// Mean channel value below which a frame is too dark to balance
+const MIN_CHANNEL_MEAN: f32 = 4.0;
+
+/// Colour gains that make the mean colour of a frame, taken with the given gains, neutral.
+/// Gains act in linear light, so the 8 bit sRGB mean is linearized first.
+pub fn neutral_colour_gains(
+    mean: [f32; 3],
+    gains: &ColourGain,
+) -> Result<ColourGain, anyhow::Error> {
+    if mean.iter().any(|&channel| channel < MIN_CHANNEL_MEAN) {
+        anyhow::bail!("Region is too dark to balance, mean colour {:?}", mean);
+    }
+    let [red, green, blue] = linearize(mean);
+    Ok(ColourGain {
+        red: gains.red * green / red,
+        blue: gains.blue * green / blue,
+    })
+}
+
+/// Largest relative difference of red and blue from green in linear light,
+/// 0 for a neutral colour
+pub fn white_balance_residual(mean: [f32; 3]) -> f32 {
+    let [red, green, blue] = linearize(mean);
+    if green <= 0.0 {
+        return f32::INFINITY;
+    }
+    (red / green - 1.0).abs().max((blue / green - 1.0).abs())
+}
+
+fn linearize(mean: [f32; 3]) -> [f32; 3] {
+    mean.map(|channel| srgb_to_linear(channel / 255.0))
+}
+
+/// Linear light value of an sRGB encoded value between 0 and 1
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
@@ -6,3 +6,11 @@ pub fn get_download_update_url(base_url: &str) -> String {
 pub fn get_upload_image_url(base_url: &str) -> String {
     format!("{}/uploadimage", base_url)
 }
+
+pub fn get_upload_clip_url(base_url: &str) -> String {
+    format!("{}/uploadclip", base_url)
+}
+
+pub fn get_upload_preview_url(base_url: &str) -> String {
+    format!("{}/uploadpreview", base_url)
+}
@@ -0,0 +1,23 @@
+use crate::camera::CameraService;
+use std::sync::Arc;
+use tokio::sync::{Mutex, OwnedMutexGuard};
+
+/// Locks the camera and runs blocking (Python) work on a blocking thread,
+/// so background loops do not stall the MQTT event loop.
+/// Returns the guard, so the caller can keep the camera locked afterwards.
+pub async fn run_blocking<T, F>(
+    camera_service: &Arc<Mutex<CameraService>>,
+    f: F,
+) -> Result<(OwnedMutexGuard<CameraService>, T), anyhow::Error>
+where
+    T: Send + 'static,
+    F: FnOnce(&mut CameraService) -> T + Send + 'static,
+{
+    let mut camera_guard = Arc::clone(camera_service).lock_owned().await;
+    let result = tokio::task::spawn_blocking(move || {
+        let result = f(&mut camera_guard);
+        (camera_guard, result)
+    })
+    .await?;
+    Ok(result)
+}
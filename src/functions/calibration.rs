@@ -0,0 +1,507 @@
+use crate::camera::{
+    apply_calibration_frames, calibration_key, colour_checker_from_corners, detect_checkerboard,
+    detect_colour_checker, mean_rgb, neutral_colour_gains, solve_intrinsics,
+    white_balance_residual, CalibrationFrameInfo, CalibrationFrameKind, CalibrationFrames,
+    CalibrationView, CameraMode, CameraService, ColourCorrection, ColourFit, ColourGain, DarkFrame,
+    FlatField, Frame, FrameAccumulator, GrayImage, RgbImage, MAX_ACCUMULATED_FRAMES,
+};
+use crate::functions::camera::apply_controls;
+use crate::functions::requests::{
+    CalibrateColour, CalibrateWhiteBalance, CaptureCalibrationFrames, DetectCalibrationPattern,
+    SetColourCorrection, SetControls,
+};
+use crate::functions::responses::{
+    CalibrationFramesResponse, CalibrationPatternResponse, CameraResponse,
+    ColourCorrectionResponse, IntrinsicsResponse, WhiteBalanceResponse,
+};
+use crate::settings::{BaseSettings, Settings};
+use crate::utils::{AsyncClientExt, SuccessWrapper};
+use pyo3::Python;
+use rumqttc::v5::AsyncClient;
+use serde::Serialize;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::fs;
+
+pub const COLOUR_CORRECTION_FILENAME: &str = "colour_correction.json";
+pub const CALIBRATION_FRAMES_PATH: &str = "calibration_frames";
+pub const INTRINSICS_FILENAME: &str = "intrinsics.json";
+const CALIBRATION_VIEWS_FILENAME: &str = "calibration_views.json";
+const DEFAULT_CALIBRATION_FRAMES: u32 = 16;
+
+/// Time for new colour gains to take effect before measuring the residual
+const WHITE_BALANCE_SETTLE_TIME: Duration = Duration::from_secs(1);
+/// Relative difference between reported and applied colour gains still counted as applied
+const COLOUR_GAIN_TOLERANCE: f32 = 0.01;
+
+pub async fn calibrate_white_balance(
+    base_settings: &BaseSettings,
+    settings: &Settings,
+    mqtt_client: &AsyncClient,
+    camera_service: &mut CameraService,
+    request: &CalibrateWhiteBalance,
+) -> Result<(), anyhow::Error> {
+    let success_wrapper = match run_calibrate_white_balance(camera_service, request).await {
+        Ok(calibrated) => SuccessWrapper::success(calibrated),
+        Err(e) => SuccessWrapper::failure(WhiteBalanceResponse::Failed {
+            message: e.to_string(),
+        }),
+    };
+    let response = CameraResponse::WhiteBalance {
+        response: success_wrapper,
+    };
+
+    mqtt_client
+        .publish_individual(
+            &settings.camera_topic,
+            &base_settings.pi_zero_id,
+            response.into_bytes()?,
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Neutralizes the mean colour of the region with manual colour gains, stored in still controls
+async fn run_calibrate_white_balance(
+    camera_service: &mut CameraService,
+    request: &CalibrateWhiteBalance,
+) -> Result<WhiteBalanceResponse, anyhow::Error> {
+    // Gains are stored in still controls, so they are measured with the still pipeline
+    if camera_service.camera_mode != CameraMode::Still {
+        anyhow::bail!("White balance is calibrated in still mode, stop the preview first");
+    }
+    let roi = request.roi.unwrap_or_default();
+
+    let frame = Python::attach(|py| camera_service.capture_frame(py))?;
+    let Some(gains) = &frame.effective_controls.colour_gains else {
+        anyhow::bail!("Camera did not report colour gains");
+    };
+    let mean = mean_rgb(&frame.pixels, frame.width, frame.height, frame.format, &roi)?;
+    let colour_gains = neutral_colour_gains(mean, gains)?;
+
+    let mut camera_controls = camera_service.still_controls.clone().unwrap_or_default();
+    camera_controls.awb_enable = Some(false);
+    camera_controls.colour_gains = Some(colour_gains.clone());
+    let controls = SetControls {
+        camera_mode: CameraMode::Still,
+        camera_controls,
+        clamp: true,
+    };
+    let (camera_controls, clamped) = apply_controls(camera_service, &controls).await?;
+
+    // Residual is measured on the first frame the new gains are reported for
+    let applied_gains = camera_controls.colour_gains.clone().unwrap_or(colour_gains);
+    let deadline = Instant::now() + WHITE_BALANCE_SETTLE_TIME;
+    let frame = Python::attach(|py| -> Result<Frame, anyhow::Error> {
+        loop {
+            let frame = camera_service.capture_frame(py)?;
+            if frame
+                .effective_controls
+                .colour_gains
+                .as_ref()
+                .is_some_and(|gains| colour_gains_match(gains, &applied_gains))
+            {
+                return Ok(frame);
+            }
+            if Instant::now() >= deadline {
+                anyhow::bail!("Camera did not apply the colour gains in time");
+            }
+        }
+    })?;
+    let mean_after = mean_rgb(&frame.pixels, frame.width, frame.height, frame.format, &roi)?;
+
+    Ok(WhiteBalanceResponse::WhiteBalanceCalibrated {
+        roi,
+        mean_before: mean,
+        mean_after,
+        residual: white_balance_residual(mean_after),
+        camera_controls: Box::new(camera_controls),
+        clamped,
+    })
+}
+
+fn colour_gains_match(reported: &ColourGain, applied: &ColourGain) -> bool {
+    let close = |a: f32, b: f32| (a - b).abs() <= a.abs().max(b.abs()) * COLOUR_GAIN_TOLERANCE;
+    close(reported.red, applied.red) && close(reported.blue, applied.blue)
+}
+
+pub async fn calibrate_colour(
+    base_settings: &BaseSettings,
+    settings: &Settings,
+    mqtt_client: &AsyncClient,
+    camera_service: &mut CameraService,
+    request: &CalibrateColour,
+) -> Result<(), anyhow::Error> {
+    let result = run_calibrate_colour(camera_service, request).await;
+    publish_colour_correction_response(base_settings, settings, mqtt_client, result).await
+}
+
+/// Fits a colour correction matrix to a colour checker in the next still frame
+async fn run_calibrate_colour(
+    camera_service: &mut CameraService,
+    request: &CalibrateColour,
+) -> Result<ColourCorrectionResponse, anyhow::Error> {
+    // The matrix is applied to still captures, so it is fitted to the still pipeline
+    if camera_service.camera_mode != CameraMode::Still {
+        anyhow::bail!("Colour is calibrated in still mode, stop the preview first");
+    }
+
+    let frame = Python::attach(|py| camera_service.capture_frame(py))?;
+    let image = RgbImage::new(&frame.pixels, frame.width, frame.height, frame.format);
+    let checker = match &request.corners {
+        Some(corners) => colour_checker_from_corners(&image, corners)?,
+        None => detect_colour_checker(&image)?,
+    };
+    let fit = ColourFit::new(&checker.colours)?;
+
+    let colour_correction = ColourCorrection {
+        matrix: fit.matrix,
+        enabled: request.enable.unwrap_or(true),
+        mean_error: fit.mean_error,
+        max_error: fit.max_error,
+    };
+    save_colour_correction(camera_service, colour_correction.clone()).await?;
+
+    Ok(ColourCorrectionResponse::ColourCalibrated {
+        colour_correction,
+        checker,
+        patch_errors: fit.patch_errors,
+    })
+}
+
+pub async fn set_colour_correction(
+    base_settings: &BaseSettings,
+    settings: &Settings,
+    mqtt_client: &AsyncClient,
+    camera_service: &mut CameraService,
+    request: &SetColourCorrection,
+) -> Result<(), anyhow::Error> {
+    let result = async {
+        let Some(colour_correction) = camera_service.colour_correction.clone() else {
+            anyhow::bail!("Colour is not calibrated");
+        };
+        let colour_correction = ColourCorrection {
+            enabled: request.enabled,
+            ..colour_correction
+        };
+        save_colour_correction(camera_service, colour_correction.clone()).await?;
+        Ok(ColourCorrectionResponse::ColourCorrection { colour_correction })
+    }
+    .await;
+    publish_colour_correction_response(base_settings, settings, mqtt_client, result).await
+}
+
+async fn save_colour_correction(
+    camera_service: &mut CameraService,
+    colour_correction: ColourCorrection,
+) -> Result<(), anyhow::Error> {
+    fs::write(
+        COLOUR_CORRECTION_FILENAME,
+        serde_json::to_string(&colour_correction)?,
+    )
+    .await?;
+    camera_service.colour_correction = Some(colour_correction);
+    Ok(())
+}
+
+async fn publish_colour_correction_response(
+    base_settings: &BaseSettings,
+    settings: &Settings,
+    mqtt_client: &AsyncClient,
+    result: Result<ColourCorrectionResponse, anyhow::Error>,
+) -> Result<(), anyhow::Error> {
+    let success_wrapper = match result {
+        Ok(response) => SuccessWrapper::success(response),
+        Err(e) => SuccessWrapper::failure(ColourCorrectionResponse::Failed {
+            message: e.to_string(),
+        }),
+    };
+    let response = CameraResponse::ColourCorrection {
+        response: success_wrapper,
+    };
+
+    mqtt_client
+        .publish_individual(
+            &settings.camera_topic,
+            &base_settings.pi_zero_id,
+            response.into_bytes()?,
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub async fn capture_calibration_frames(
+    base_settings: &BaseSettings,
+    settings: &Settings,
+    mqtt_client: &AsyncClient,
+    camera_service: &mut CameraService,
+    request: &CaptureCalibrationFrames,
+) -> Result<(), anyhow::Error> {
+    let success_wrapper = match run_capture_calibration_frames(camera_service, request).await {
+        Ok(captured) => SuccessWrapper::success(captured),
+        Err(e) => SuccessWrapper::failure(CalibrationFramesResponse::Failed {
+            kind: request.kind,
+            message: e.to_string(),
+        }),
+    };
+    let response = CameraResponse::CalibrationFrames {
+        response: success_wrapper,
+    };
+
+    mqtt_client
+        .publish_individual(
+            &settings.camera_topic,
+            &base_settings.pi_zero_id,
+            response.into_bytes()?,
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Averages still frames and stores them as calibration data of the still configuration
+async fn run_capture_calibration_frames(
+    camera_service: &mut CameraService,
+    request: &CaptureCalibrationFrames,
+) -> Result<CalibrationFramesResponse, anyhow::Error> {
+    if camera_service.camera_mode != CameraMode::Still {
+        anyhow::bail!("Calibration frames are captured in still mode, stop the preview first");
+    }
+    let frames = request.frames.unwrap_or(DEFAULT_CALIBRATION_FRAMES);
+    if !(1..=MAX_ACCUMULATED_FRAMES).contains(&frames) {
+        anyhow::bail!("Frames must be between 1 and {}", MAX_ACCUMULATED_FRAMES);
+    }
+    let key = calibration_key(
+        &camera_service.still_capture_config,
+        &camera_service.orientation,
+    );
+
+    let accumulator = Python::attach(|py| -> Result<FrameAccumulator, anyhow::Error> {
+        let frame = camera_service.capture_frame(py)?;
+        let mut accumulator = FrameAccumulator::new(&frame);
+        accumulator.add(&frame)?;
+        for _ in 1..frames {
+            accumulator.add(&camera_service.capture_frame(py)?)?;
+        }
+        Ok(accumulator)
+    })?;
+
+    fs::create_dir_all(CALIBRATION_FRAMES_PATH).await?;
+    let (info, bytes) = match request.kind {
+        CalibrationFrameKind::Dark => {
+            let dark = accumulator.into_dark_frame();
+            (dark.info, dark.pixels)
+        }
+        CalibrationFrameKind::Flat => {
+            let dark = load_dark_frame(&key).await?;
+            let flat = accumulator.into_flat_field(dark.as_ref())?;
+            let bytes = flat.to_bytes();
+            (flat.info, bytes)
+        }
+    };
+    let path = calibration_frames_path(request.kind, &key);
+    fs::write(path.with_extension("bin"), bytes).await?;
+    fs::write(path.with_extension("json"), serde_json::to_string(&info)?).await?;
+    // Reloaded by the next picture that uses them
+    camera_service.calibration_frames = None;
+
+    Ok(CalibrationFramesResponse::CalibrationFramesCaptured { key, info })
+}
+
+/// Calibration data applied to a picture
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AppliedCalibration {
+    key: String,
+    dark_frame: bool,
+    flat_field: bool,
+}
+
+/// Applies the stored dark frame and flat field of the still configuration, if there are any.
+/// They are read once per calibration key and kept in the camera service.
+pub async fn apply_stored_calibration(
+    camera_service: &mut CameraService,
+    pixels: &mut [u8],
+    width: u16,
+    height: u16,
+) -> Result<AppliedCalibration, anyhow::Error> {
+    let key = calibration_key(
+        &camera_service.still_capture_config,
+        &camera_service.orientation,
+    );
+    let frames = match camera_service.calibration_frames.take() {
+        Some(frames) if frames.key == key => frames,
+        _ => CalibrationFrames {
+            dark: load_dark_frame(&key).await?,
+            flat: load_flat_field(&key).await?,
+            key: key.clone(),
+        },
+    };
+    let result = apply_calibration_frames(
+        pixels,
+        width as usize,
+        height as usize,
+        camera_service.still_capture_config.format,
+        frames.dark.as_ref(),
+        frames.flat.as_ref(),
+    );
+    let applied = AppliedCalibration {
+        key,
+        dark_frame: frames.dark.is_some(),
+        flat_field: frames.flat.is_some(),
+    };
+    camera_service.calibration_frames = Some(frames);
+    result?;
+
+    Ok(applied)
+}
+
+fn calibration_frames_path(kind: CalibrationFrameKind, key: &str) -> PathBuf {
+    Path::new(CALIBRATION_FRAMES_PATH).join(format!("{}_{}", kind.file_prefix(), key))
+}
+
+/// Info and data of stored calibration frames, None if not captured
+async fn read_calibration_frames(
+    kind: CalibrationFrameKind,
+    key: &str,
+) -> Result<Option<(CalibrationFrameInfo, Vec<u8>)>, anyhow::Error> {
+    let path = calibration_frames_path(kind, key);
+    let info = match fs::read_to_string(path.with_extension("json")).await {
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        result => result?,
+    };
+    let info = serde_json::from_str(&info)?;
+    let bytes = fs::read(path.with_extension("bin")).await?;
+    Ok(Some((info, bytes)))
+}
+
+async fn load_dark_frame(key: &str) -> Result<Option<DarkFrame>, anyhow::Error> {
+    read_calibration_frames(CalibrationFrameKind::Dark, key)
+        .await?
+        .map(|(info, bytes)| DarkFrame::from_bytes(info, bytes))
+        .transpose()
+}
+
+async fn load_flat_field(key: &str) -> Result<Option<FlatField>, anyhow::Error> {
+    read_calibration_frames(CalibrationFrameKind::Flat, key)
+        .await?
+        .map(|(info, bytes)| FlatField::from_bytes(info, &bytes))
+        .transpose()
+}
+
+pub async fn detect_calibration_pattern(
+    base_settings: &BaseSettings,
+    settings: &Settings,
+    mqtt_client: &AsyncClient,
+    camera_service: &CameraService,
+    request: &DetectCalibrationPattern,
+) -> Result<(), anyhow::Error> {
+    let success_wrapper = match run_detect_calibration_pattern(camera_service, request).await {
+        Ok(detected) => SuccessWrapper::success(detected),
+        Err(e) => SuccessWrapper::failure(CalibrationPatternResponse::Failed {
+            message: e.to_string(),
+        }),
+    };
+    let response = CameraResponse::CalibrationPattern {
+        response: success_wrapper,
+    };
+
+    mqtt_client
+        .publish_individual(
+            &settings.camera_topic,
+            &base_settings.pi_zero_id,
+            response.into_bytes()?,
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Finds checkerboard corners in the next still frame and adds them to the collected views
+async fn run_detect_calibration_pattern(
+    camera_service: &CameraService,
+    request: &DetectCalibrationPattern,
+) -> Result<CalibrationPatternResponse, anyhow::Error> {
+    // Intrinsics are used for still captures, so views are of the still pipeline
+    if camera_service.camera_mode != CameraMode::Still {
+        anyhow::bail!("Calibration patterns are detected in still mode, stop the preview first");
+    }
+
+    let frame = Python::attach(|py| camera_service.capture_frame(py))?;
+    let image = GrayImage::new(&frame.pixels, frame.width, frame.height, frame.format);
+    let corners = detect_checkerboard(&image, request.columns, request.rows)?;
+    let view = CalibrationView {
+        key: calibration_key(
+            &camera_service.still_capture_config,
+            &camera_service.orientation,
+        ),
+        width: frame.width,
+        height: frame.height,
+        columns: request.columns,
+        rows: request.rows,
+        corners,
+    };
+
+    let mut views = if request.reset {
+        Vec::new()
+    } else {
+        load_calibration_views().await?
+    };
+    views.retain(|other| {
+        other.key == view.key && other.columns == view.columns && other.rows == view.rows
+    });
+    views.push(view.clone());
+    fs::write(CALIBRATION_VIEWS_FILENAME, serde_json::to_string(&views)?).await?;
+
+    Ok(CalibrationPatternResponse::PatternDetected {
+        view,
+        views: views.len(),
+    })
+}
+
+pub async fn solve_camera_intrinsics(
+    base_settings: &BaseSettings,
+    settings: &Settings,
+    mqtt_client: &AsyncClient,
+    camera_service: &mut CameraService,
+) -> Result<(), anyhow::Error> {
+    let result = async {
+        let views = load_calibration_views().await?;
+        let intrinsics = solve_intrinsics(&views)?;
+        fs::write(INTRINSICS_FILENAME, serde_json::to_string(&intrinsics)?).await?;
+        camera_service.intrinsics = Some(intrinsics.clone());
+        Ok::<_, anyhow::Error>(IntrinsicsResponse::IntrinsicsSolved { intrinsics })
+    }
+    .await;
+    let success_wrapper = match result {
+        Ok(solved) => SuccessWrapper::success(solved),
+        Err(e) => SuccessWrapper::failure(IntrinsicsResponse::Failed {
+            message: e.to_string(),
+        }),
+    };
+    let response = CameraResponse::Intrinsics {
+        response: success_wrapper,
+    };
+
+    mqtt_client
+        .publish_individual(
+            &settings.camera_topic,
+            &base_settings.pi_zero_id,
+            response.into_bytes()?,
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Views collected by DetectCalibrationPattern, empty if there are none
+async fn load_calibration_views() -> Result<Vec<CalibrationView>, anyhow::Error> {
+    match fs::read_to_string(CALIBRATION_VIEWS_FILENAME).await {
+        Ok(views) => Ok(serde_json::from_str(&views)?),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
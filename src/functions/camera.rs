@@ -1,12 +1,36 @@
-use crate::camera::{CameraControlsLimit, CameraMode, CameraService};
-use crate::endpoints::get_upload_image_url;
-use crate::functions::requests::{CameraRequest, SendPicture, SetControls, TakePicture};
+use crate::camera::{
+    calibration_key, detect_markers, libcamera_control_name, rotate_quarter_turn, AfState,
+    CameraControls, CameraControlsLimit, CameraMode, CameraService, CaptureConfig, ControlInfo,
+    ControlLimitError, ControlLimitViolation, Convergence, EffectiveControls, EnumControl,
+    FocusAssistStopReason, GrayImage, IspStatistics, MotionStopReason, Orientation, OverlayConfig,
+    PreviewConfig, PreviewFrame, PreviewStopReason, StackedPicture, ISP_STATISTICS_METADATA_KEY,
+};
+use crate::endpoints::{get_upload_image_url, get_upload_preview_url};
+use crate::functions::calibration::{
+    apply_stored_calibration, calibrate_colour, calibrate_white_balance,
+    capture_calibration_frames, detect_calibration_pattern, set_colour_correction,
+    solve_camera_intrinsics,
+};
+use crate::functions::clip::{record_clip, send_clip};
+use crate::functions::focus::{start_focus_assist, stop_focus_assist};
+use crate::functions::motion::{start_motion_capture, stop_motion_capture};
+use crate::functions::preset::{
+    apply_preset, clear_active_preset, delete_preset, list_presets, save_preset,
+};
+use crate::functions::requests::{
+    AutoFocus, CameraRequest, GetPreviewFrame, MeasureExposure, PatchControls, SendPicture,
+    SetCaptureConfig, SetControls, StartPreview, TakePicture,
+};
 use crate::functions::responses::{
-    CameraResponse, SendPictureResponse, SyncStatusResponse, TakePictureResponse,
+    AutoFocusResponse, CameraResponse, CaptureConfigResponse, EffectiveControlsResponse,
+    MarkersResponse, MeasureExposureResponse, OrientationResponse, PatchControlsResponse,
+    PreviewFrameHeader, PreviewFrameResponse, PreviewOverlayResponse, RecordClipResponse,
+    SendClipResponse, SendPictureResponse, SetControlsResponse, StartPreviewResponse,
+    StatisticsResponse, StopPreviewResponse, SyncStatusResponse, TakePictureResponse,
 };
 use crate::settings::{BaseSettings, Settings};
 use crate::utils::{AsyncClientExt, SuccessWrapper};
-use jpeg_encoder::{ColorType, Encoder};
+use jpeg_encoder::Encoder;
 use nix::sys::time::TimeValLike;
 use pyo3::{PyResult, Python};
 use reqwest::{multipart, Client};
@@ -14,6 +38,7 @@ use rumqttc::v5::mqttbytes::v5::Publish;
 use rumqttc::v5::AsyncClient;
 use serde::Serialize;
 use std::collections::HashMap;
+use std::time::{Duration, Instant};
 use tokio::fs;
 use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
@@ -21,6 +46,13 @@ use uuid::Uuid;
 
 pub const STILL_CAMERA_CONTROLS_FILENAME: &'static str = "controls_still.json";
 pub const VIDEO_CAMERA_CONTROLS_FILENAME: &'static str = "controls_video.json";
+pub const STILL_CAPTURE_CONFIG_FILENAME: &str = "capture_config_still.json";
+pub const VIDEO_CAPTURE_CONFIG_FILENAME: &str = "capture_config_video.json";
+pub const ORIENTATION_FILENAME: &str = "orientation.json";
+const DEFAULT_AUTO_FOCUS_TIMEOUT_MILLIS: u64 = 5000;
+const DEFAULT_MEASURE_EXPOSURE_TIMEOUT_MILLIS: u64 = 3000;
+/// Convergence wait stops this long before the picture time
+const CONVERGENCE_CAPTURE_MARGIN: Duration = Duration::from_millis(100);
 
 #[derive(Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
@@ -28,6 +60,8 @@ struct CameraControlsResponse {
     min: CameraControlsLimit,
     max: CameraControlsLimit,
     default: CameraControlsLimit,
+    /// All camera controls, including those without a field
+    controls: Vec<ControlInfo>,
 }
 
 pub async fn handle_picture(
@@ -99,27 +133,318 @@ pub async fn handle_picture(
                     .unwrap_or_default();
             }
         }
+        CameraRequest::RecordClip(request) => {
+            let res = record_clip(
+                base_settings,
+                settings,
+                mqtt_client,
+                camera_service,
+                &request,
+                wall_nanoseconds,
+            )
+            .await;
+
+            if let Err(err) = res {
+                println!("Error while recording clip: {:?}", err);
+                let err = RecordClipResponse::Failed {
+                    uuid: request.uuid,
+                    message: err.to_string(),
+                };
+                let success_wrapper = SuccessWrapper::failure(err);
+                let response = CameraResponse::RecordClip {
+                    response: success_wrapper,
+                };
+
+                mqtt_client
+                    .publish_individual(
+                        &settings.camera_topic,
+                        &base_settings.pi_zero_id,
+                        response.into_bytes()?,
+                    )
+                    .await
+                    .unwrap_or_default();
+            }
+        }
+        CameraRequest::SendClip(request) => {
+            let res = send_clip(base_settings, settings, mqtt_client, http_client, &request).await;
+
+            if let Err(err) = res {
+                println!("Error while sending clip: {:?}", err);
+                let err = SendClipResponse::Failed {
+                    uuid: request.uuid,
+                    message: err.to_string(),
+                };
+                let success_wrapper = SuccessWrapper::failure(err);
+                let response = CameraResponse::SendClip {
+                    response: success_wrapper,
+                };
+
+                mqtt_client
+                    .publish_individual(
+                        &settings.camera_topic,
+                        &base_settings.pi_zero_id,
+                        response.into_bytes()?,
+                    )
+                    .await
+                    .unwrap_or_default();
+            }
+        }
         CameraRequest::SetControls(controls) => {
             set_controls(
                 base_settings,
                 settings,
                 mqtt_client,
                 camera_service,
-                controls,
+                *controls,
+            )
+            .await?;
+        }
+        CameraRequest::PatchControls(request) => {
+            patch_controls(
+                base_settings,
+                settings,
+                mqtt_client,
+                camera_service,
+                &request,
             )
             .await?;
         }
         CameraRequest::GetControlLimits => {
             get_control_limits(base_settings, settings, mqtt_client, camera_service).await?;
         }
-        CameraRequest::StartPreview => {
-            start_preview(camera_service).await?;
+        CameraRequest::StartPreview(request) => {
+            start_preview(
+                base_settings,
+                settings,
+                mqtt_client,
+                camera_service,
+                &request,
+            )
+            .await?;
         }
         CameraRequest::StopPreview => {
-            stop_preview(camera_service).await?;
+            stop_preview(
+                base_settings,
+                settings,
+                mqtt_client,
+                camera_service,
+                PreviewStopReason::Requested,
+            )
+            .await?;
+        }
+        CameraRequest::GetPreviewFrame(request) => {
+            get_preview_frame(
+                base_settings,
+                settings,
+                mqtt_client,
+                http_client,
+                camera_service,
+                &request,
+            )
+            .await?;
+        }
+        CameraRequest::SetPreviewOverlay(overlay_config) => {
+            set_preview_overlay(
+                base_settings,
+                settings,
+                mqtt_client,
+                camera_service,
+                &overlay_config,
+            )
+            .await?;
+        }
+        CameraRequest::SetCaptureConfig(request) => {
+            set_capture_config(
+                base_settings,
+                settings,
+                mqtt_client,
+                camera_service,
+                &request,
+            )
+            .await?;
+        }
+        CameraRequest::GetCaptureConfig(camera_mode) => {
+            get_capture_config(
+                base_settings,
+                settings,
+                mqtt_client,
+                camera_service,
+                camera_mode,
+            )
+            .await?;
+        }
+        CameraRequest::SetOrientation(orientation) => {
+            set_orientation(
+                base_settings,
+                settings,
+                mqtt_client,
+                camera_service,
+                &orientation,
+            )
+            .await?;
+        }
+        CameraRequest::SavePreset(request) => {
+            save_preset(
+                base_settings,
+                settings,
+                mqtt_client,
+                camera_service,
+                &request,
+            )
+            .await?;
+        }
+        CameraRequest::ApplyPreset(request) => {
+            apply_preset(
+                base_settings,
+                settings,
+                mqtt_client,
+                camera_service,
+                &request,
+            )
+            .await?;
+        }
+        CameraRequest::ListPresets => {
+            list_presets(base_settings, settings, mqtt_client, camera_service).await?;
+        }
+        CameraRequest::DeletePreset(request) => {
+            delete_preset(
+                base_settings,
+                settings,
+                mqtt_client,
+                camera_service,
+                &request,
+            )
+            .await?;
+        }
+        CameraRequest::ListSensorModes => {
+            list_sensor_modes(base_settings, settings, mqtt_client, camera_service).await?;
+        }
+        CameraRequest::GetControls(camera_mode) => {
+            get_controls(
+                base_settings,
+                settings,
+                mqtt_client,
+                camera_service,
+                &camera_mode,
+            )
+            .await?;
+        }
+        CameraRequest::GetEffectiveControls(camera_mode) => {
+            get_effective_controls(
+                base_settings,
+                settings,
+                mqtt_client,
+                camera_service,
+                camera_mode,
+            )
+            .await?;
+        }
+        CameraRequest::AutoFocus(request) => {
+            auto_focus(
+                base_settings,
+                settings,
+                mqtt_client,
+                camera_service,
+                request,
+            )
+            .await?;
+        }
+        CameraRequest::MeasureExposure(request) => {
+            measure_exposure(
+                base_settings,
+                settings,
+                mqtt_client,
+                camera_service,
+                request,
+            )
+            .await?;
+        }
+        CameraRequest::CalibrateWhiteBalance(request) => {
+            calibrate_white_balance(
+                base_settings,
+                settings,
+                mqtt_client,
+                camera_service,
+                &request,
+            )
+            .await?;
+        }
+        CameraRequest::CalibrateColour(request) => {
+            calibrate_colour(
+                base_settings,
+                settings,
+                mqtt_client,
+                camera_service,
+                &request,
+            )
+            .await?;
+        }
+        CameraRequest::SetColourCorrection(request) => {
+            set_colour_correction(
+                base_settings,
+                settings,
+                mqtt_client,
+                camera_service,
+                &request,
+            )
+            .await?;
+        }
+        CameraRequest::CaptureCalibrationFrames(request) => {
+            capture_calibration_frames(
+                base_settings,
+                settings,
+                mqtt_client,
+                camera_service,
+                &request,
+            )
+            .await?;
+        }
+        CameraRequest::DetectCalibrationPattern(request) => {
+            detect_calibration_pattern(
+                base_settings,
+                settings,
+                mqtt_client,
+                camera_service,
+                &request,
+            )
+            .await?;
+        }
+        CameraRequest::SolveIntrinsics => {
+            solve_camera_intrinsics(base_settings, settings, mqtt_client, camera_service).await?;
+        }
+        CameraRequest::DetectMarkers => {
+            detect_frame_markers(base_settings, settings, mqtt_client, camera_service).await?;
+        }
+        CameraRequest::StartMotionCapture(config) => {
+            start_motion_capture(base_settings, settings, mqtt_client, camera_service, config)
+                .await?;
+        }
+        CameraRequest::StopMotionCapture => {
+            stop_motion_capture(
+                base_settings,
+                settings,
+                mqtt_client,
+                camera_service,
+                MotionStopReason::Requested,
+            )
+            .await?;
+        }
+        CameraRequest::StartFocusAssist(config) => {
+            start_focus_assist(base_settings, settings, mqtt_client, camera_service, config)
+                .await?;
         }
-        CameraRequest::GetControls(_) => {
-            get_controls(base_settings, settings, mqtt_client, camera_service).await?;
+        CameraRequest::StopFocusAssist => {
+            stop_focus_assist(
+                base_settings,
+                settings,
+                mqtt_client,
+                camera_service,
+                FocusAssistStopReason::Requested,
+            )
+            .await?;
+        }
+        CameraRequest::GetStatistics(request) => {
+            get_statistics(base_settings, settings, mqtt_client, &request).await?;
         }
         CameraRequest::GetSyncStatus => {
             get_sync_status(base_settings, settings, mqtt_client, camera_service).await?;
@@ -129,20 +454,20 @@ pub async fn handle_picture(
     Ok(())
 }
 
-async fn take_picture(
+pub async fn take_picture(
     base_settings: &BaseSettings,
     settings: &Settings,
     mqtt_client: &AsyncClient,
-    camera_service: &CameraService,
+    camera_service: &mut CameraService,
     request: &TakePicture,
     message_received_nanos: Option<i64>,
 ) -> Result<(), anyhow::Error> {
     // todo: proper error
-    // calculate time between current time and picture time
-    let wall_time = nix::time::clock_gettime(nix::time::ClockId::CLOCK_REALTIME)?;
-    let monotonic_time = nix::time::clock_gettime(nix::time::ClockId::CLOCK_MONOTONIC)?;
-    let wall_nanoseconds = wall_time.num_nanoseconds();
-    let wait_time = request.picture_epoch as i64 * 1000000 - wall_nanoseconds;
+    let ScheduledTime {
+        wall_nanoseconds,
+        wait_time,
+        monotonic_nanoseconds: monotonic_nanoseconds_future,
+    } = epoch_to_monotonic(request.picture_epoch)?;
     // return error, if wait time is negative
     if wait_time < 0 {
         let err = TakePictureResponse::PictureFailedToSchedule {
@@ -170,12 +495,39 @@ async fn take_picture(
         // Return ok, as error handled in this function
         return Ok(());
     }
-    // add wait time to monotonic time
-    let monotonic_nanoseconds = monotonic_time.num_nanoseconds();
-    let monotonic_nanoseconds_future = monotonic_nanoseconds + wait_time;
+    let convergence = if request.wait_for_convergence {
+        let until_capture =
+            Duration::from_nanos(wait_time as u64).saturating_sub(CONVERGENCE_CAPTURE_MARGIN);
+        let until_deadline = match request.convergence_timeout_millis {
+            Some(timeout) => until_capture.min(Duration::from_millis(timeout)),
+            None => until_capture,
+        };
+        let deadline = Instant::now() + until_deadline;
+        // The picture is still taken if polling fails
+        match Python::attach(|py| camera_service.wait_for_convergence(py, deadline)) {
+            Ok(convergence) => Some(convergence),
+            Err(e) => {
+                println!("Failed to wait for convergence: {:?}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
 
-    let pic = take_picture_take(camera_service, monotonic_nanoseconds_future as u64).await;
-    let (bytes, width, height, metadata) = match pic {
+    let pic = match request.stack_frames {
+        Some(frames) => {
+            take_picture_stack(
+                camera_service,
+                monotonic_nanoseconds_future as u64,
+                frames,
+                request.stack_sigma_clip,
+            )
+            .await
+        }
+        None => take_picture_take(camera_service, monotonic_nanoseconds_future as u64).await,
+    };
+    let (bytes, width, height, mut metadata) = match pic {
         Ok(pic) => {
             // Send that taken successfully
             let picture_taken = TakePictureResponse::PictureTaken {
@@ -183,6 +535,7 @@ async fn take_picture(
                 monotonic_time: monotonic_nanoseconds_future,
                 message_received_nanos,
                 wait_time_nanos: wait_time,
+                convergence: convergence.clone(),
             };
             // It's ok if it fails, we will still try to save/send
             let success_wrapper = SuccessWrapper::success(picture_taken);
@@ -228,14 +581,92 @@ async fn take_picture(
         }
     };
 
+    let mut bytes = bytes;
+    if request.calibration_correction {
+        // Pictures are still saved without correction, metadata records what was applied
+        match apply_stored_calibration(camera_service, &mut bytes, width, height).await {
+            Ok(applied) => {
+                metadata.insert(
+                    "CalibrationFrames".to_string(),
+                    serde_json::to_string(&applied)?,
+                );
+            }
+            Err(e) => {
+                println!("Failed to apply calibration frames: {:?}", e);
+                metadata.insert(
+                    "CalibrationFramesError".to_string(),
+                    serde_json::to_string(&e.to_string())?,
+                );
+            }
+        }
+    }
+
+    // Flips and half turns are done by libcamera, quarter turns here
+    let orientation = camera_service.orientation;
+    let key = calibration_key(&camera_service.still_capture_config, &orientation);
+    if let Some(intrinsics) = camera_service
+        .intrinsics
+        .as_ref()
+        .and_then(|intrinsics| intrinsics.for_picture(&key, &orientation))
+    {
+        metadata.insert(
+            "Intrinsics".to_string(),
+            serde_json::to_string(&intrinsics)?,
+        );
+    }
+    let (mut bytes, width, height) = if orientation.quarter_turn() {
+        let (bytes, width, height) = rotate_quarter_turn(&bytes, width as usize, height as usize);
+        (bytes, width as u16, height as u16)
+    } else {
+        (bytes, width, height)
+    };
+    metadata.insert(
+        "Orientation".to_string(),
+        serde_json::to_string(&orientation)?,
+    );
+    if let Some(convergence) = &convergence {
+        metadata.insert(
+            "Convergence".to_string(),
+            serde_json::to_string(convergence)?,
+        );
+    }
+
+    let format = camera_service.still_capture_config.format;
+    if request.detect_markers {
+        let image = GrayImage::new(&bytes, width as usize, height as usize, format);
+        metadata.insert(
+            "Markers".to_string(),
+            serde_json::to_string(&detect_markers(&image))?,
+        );
+    }
+    if let Some(colour_correction) = &camera_service.colour_correction
+        && colour_correction.enabled
+    {
+        colour_correction.apply(&mut bytes, format);
+        metadata.insert(
+            "ColourCorrection".to_string(),
+            serde_json::to_string(colour_correction)?,
+        );
+    }
+
     let mut jpeg_buf = Vec::new();
     let encoder = Encoder::new(&mut jpeg_buf, 95);
-    encoder.encode(&bytes, width, height, ColorType::Rgb)?;
-
-    let save_result = take_picture_save(&base_settings, &request, &jpeg_buf, &metadata).await;
+    let color_type = format.color_type();
+    encoder.encode(&bytes, width, height, color_type)?;
 
-    match save_result {
-        Ok(res) => res,
+    // Statistics are too large for the metadata, they are saved next to it
+    let statistics = metadata.remove(ISP_STATISTICS_METADATA_KEY);
+    let save_result = take_picture_save(
+        &base_settings,
+        &request,
+        &jpeg_buf,
+        &metadata,
+        statistics.as_deref(),
+    )
+    .await;
+
+    match save_result {
+        Ok(res) => res,
         Err(e) => {
             let err = TakePictureResponse::PictureFailedToSave {
                 uuid: request.uuid,
@@ -381,6 +812,79 @@ async fn send_picture(
     Ok(())
 }
 
+/// Publishes ISP statistics saved with a picture
+async fn get_statistics(
+    base_settings: &BaseSettings,
+    settings: &Settings,
+    mqtt_client: &AsyncClient,
+    request: &SendPicture,
+) -> Result<(), anyhow::Error> {
+    let filename = get_statistics_filename(&request.uuid, &base_settings.pi_zero_id);
+    let read_result = async {
+        let statistics_json = fs::read_to_string(get_photos_path(&filename))
+            .await
+            .map_err(|e| {
+                anyhow::anyhow!(
+                    "No statistics saved, StatsOutputEnable may not have been set: {}",
+                    e
+                )
+            })?;
+        Ok::<IspStatistics, anyhow::Error>(serde_json::from_str(&statistics_json)?)
+    }
+    .await;
+
+    let success_wrapper = match read_result {
+        Ok(statistics) => SuccessWrapper::success(StatisticsResponse::Statistics {
+            uuid: request.uuid,
+            statistics,
+        }),
+        Err(e) => SuccessWrapper::failure(StatisticsResponse::StatisticsFailedToRead {
+            uuid: request.uuid,
+            message: e.to_string(),
+        }),
+    };
+    let response = CameraResponse::Statistics {
+        response: success_wrapper,
+    };
+
+    mqtt_client
+        .publish_individual(
+            &settings.camera_topic,
+            &base_settings.pi_zero_id,
+            response.into_bytes()?,
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Wall clock epoch converted to monotonic time, which camera timestamps use
+pub struct ScheduledTime {
+    /// Current wall clock time, nanoseconds
+    pub wall_nanoseconds: i64,
+    /// Time until the epoch, negative if it has passed, nanoseconds
+    pub wait_time: i64,
+    /// Monotonic time of the epoch, nanoseconds
+    pub monotonic_nanoseconds: i64,
+}
+
+/// Converts wall clock epoch (milliseconds) to monotonic time
+pub fn epoch_to_monotonic(epoch_millis: u64) -> Result<ScheduledTime, anyhow::Error> {
+    // calculate time between current time and epoch
+    let wall_time = nix::time::clock_gettime(nix::time::ClockId::CLOCK_REALTIME)?;
+    let monotonic_time = nix::time::clock_gettime(nix::time::ClockId::CLOCK_MONOTONIC)?;
+    let wall_nanoseconds = wall_time.num_nanoseconds();
+    let wait_time = epoch_millis as i64 * 1000000 - wall_nanoseconds;
+    // add wait time to monotonic time
+    let monotonic_nanoseconds = monotonic_time.num_nanoseconds() + wait_time;
+
+    Ok(ScheduledTime {
+        wall_nanoseconds,
+        wait_time,
+        monotonic_nanoseconds,
+    })
+}
+
 /// Take picture - 1. take pic
 async fn take_picture_take(
     camera_service: &CameraService,
@@ -395,6 +899,25 @@ async fn take_picture_take(
     Ok(picture_result)
 }
 
+/// Take picture - 1. take pic, averaging consecutive frames
+async fn take_picture_stack(
+    camera_service: &CameraService,
+    time: u64,
+    frames: u32,
+    sigma_clip: Option<f32>,
+) -> Result<(Vec<u8>, u16, u16, HashMap<String, String>), anyhow::Error> {
+    let StackedPicture {
+        pixels,
+        width,
+        height,
+        mut metadata,
+        info,
+    } = Python::attach(|py| camera_service.capture_stack(py, time, frames, sigma_clip))?;
+    metadata.insert("Stacking".to_string(), serde_json::to_string(&info)?);
+
+    Ok((pixels, width, height, metadata))
+}
+
 /// Take picture - 2. save pic
 /// Returns filename and metadata json (can be empty, if fails)
 /// Returns error only if saving file fails
@@ -403,6 +926,7 @@ async fn take_picture_save(
     request: &TakePicture,
     bytes: &Vec<u8>,
     metadata: &HashMap<String, String>,
+    statistics: Option<&str>,
 ) -> Result<(String, String), anyhow::Error> {
     let filename = format!("{}_{}.jpg", &request.uuid, &base_settings.pi_zero_id);
     let filename_with_path = format!("photos/{}", filename);
@@ -430,6 +954,16 @@ async fn take_picture_save(
         }
     }
 
+    if let Some(statistics) = statistics {
+        let filename_statistics = get_photos_path(&get_statistics_filename(
+            &request.uuid,
+            &base_settings.pi_zero_id,
+        ));
+        if let Err(e) = fs::write(&filename_statistics, statistics).await {
+            println!("Failed to create statistics file: {:?}", e)
+        }
+    }
+
     Ok((filename, metadata_json))
 }
 
@@ -437,11 +971,15 @@ fn get_filename(uuid: &Uuid, pi_zero_id: &str) -> String {
     format!("{}_{}.jpg", &uuid, &pi_zero_id)
 }
 
-fn get_metadata_filename(uuid: &Uuid, pi_zero_id: &str) -> String {
+pub fn get_metadata_filename(uuid: &Uuid, pi_zero_id: &str) -> String {
     format!("{}_{}_metadata.json", &uuid, &pi_zero_id)
 }
 
-fn get_photos_path(filename: &str) -> String {
+pub fn get_statistics_filename(uuid: &Uuid, pi_zero_id: &str) -> String {
+    format!("{}_{}_statistics.json", &uuid, &pi_zero_id)
+}
+
+pub fn get_photos_path(filename: &str) -> String {
     format!("photos/{}", &filename)
 }
 
@@ -536,27 +1074,260 @@ async fn get_sync_status(
     Ok(())
 }
 
-async fn start_preview(camera_service: &mut CameraService) -> Result<(), anyhow::Error> {
-    Python::attach(|py| -> Result<(), anyhow::Error> {
-        let video_controls_pydict = match &camera_service.video_controls {
-            Some(v) => Some(v.to_pydict(py)?),
-            None => None,
-        };
-        camera_service.start_preview(py, video_controls_pydict)?;
+async fn start_preview(
+    base_settings: &BaseSettings,
+    settings: &Settings,
+    mqtt_client: &AsyncClient,
+    camera_service: &mut CameraService,
+    request: &StartPreview,
+) -> Result<(), anyhow::Error> {
+    let start_result = PreviewConfig::new(
+        // Video capture config resolution is the default
+        request
+            .resolution
+            .or(Some(camera_service.video_capture_config.resolution)),
+        request.frame_rate,
+        request.quality,
+        request.port,
+        &camera_service.sensor_modes,
+    )
+    .and_then(|mut preview_config| {
+        // Request overrides settings, 0 disables the timeout
+        preview_config.timeout_minutes = Some(
+            request
+                .timeout_minutes
+                .unwrap_or(settings.preview_timeout_minutes),
+        )
+        .filter(|minutes| *minutes > 0);
+        preview_config.idle_timeout_minutes = Some(
+            request
+                .idle_timeout_minutes
+                .unwrap_or(settings.preview_idle_timeout_minutes),
+        )
+        .filter(|minutes| *minutes > 0);
+
+        Python::attach(|py| -> Result<(), anyhow::Error> {
+            let video_controls_pydict = match &camera_service.video_controls {
+                Some(v) => Some(v.to_pydict(py)?),
+                None => None,
+            };
+            camera_service.start_preview(py, video_controls_pydict, &preview_config)?;
+            Ok(())
+        })?;
+        Ok(preview_config)
+    });
+
+    let success_wrapper = match start_result {
+        Ok(preview_config) => {
+            SuccessWrapper::success(StartPreviewResponse::PreviewStarted { preview_config })
+        }
+        Err(e) => SuccessWrapper::failure(StartPreviewResponse::Failed {
+            message: e.to_string(),
+        }),
+    };
+    let response = CameraResponse::StartPreview {
+        response: success_wrapper,
+    };
+
+    mqtt_client
+        .publish_individual(
+            &settings.camera_topic,
+            &base_settings.pi_zero_id,
+            response.into_bytes()?,
+        )
+        .await?;
+
+    Ok(())
+}
+
+async fn get_preview_frame(
+    base_settings: &BaseSettings,
+    settings: &Settings,
+    mqtt_client: &AsyncClient,
+    http_client: &Client,
+    camera_service: &CameraService,
+    request: &GetPreviewFrame,
+) -> Result<(), anyhow::Error> {
+    let frame = Python::attach(|py| camera_service.get_preview_frame(py));
+    let frame = match frame {
+        Ok(frame) => frame,
+        Err(e) => {
+            let err = PreviewFrameResponse::Failed {
+                message: e.to_string(),
+            };
+            let success_wrapper = SuccessWrapper::failure(err);
+            let response = CameraResponse::PreviewFrame {
+                response: success_wrapper,
+            };
+
+            mqtt_client
+                .publish_individual(
+                    &settings.camera_topic,
+                    &base_settings.pi_zero_id,
+                    response.into_bytes()?,
+                )
+                .await?;
+
+            // Return ok, as error handled in this function
+            return Ok(());
+        }
+    };
+
+    let header = PreviewFrameHeader {
+        width: frame.width,
+        height: frame.height,
+        source: frame.source,
+        size: frame.jpeg.len(),
+    };
+
+    if !request.upload {
+        let mut payload = serde_json::to_vec(&header)?;
+        payload.push(b'\n');
+        payload.extend_from_slice(&frame.jpeg);
+
+        mqtt_client
+            .publish_individual(&settings.camera_topic, &base_settings.pi_zero_id, payload)
+            .await?;
+        return Ok(());
+    }
+
+    let (width, height, source) = (frame.width, frame.height, frame.source);
+    let send_result = preview_frame_send(base_settings, http_client, frame, &header).await;
+    let success_wrapper = match send_result {
+        Ok(_) => SuccessWrapper::success(PreviewFrameResponse::FrameUploaded {
+            width,
+            height,
+            source,
+        }),
+        Err(e) => SuccessWrapper::failure(PreviewFrameResponse::Failed {
+            message: e.to_string(),
+        }),
+    };
+    let response = CameraResponse::PreviewFrame {
+        response: success_wrapper,
+    };
+
+    mqtt_client
+        .publish_individual(
+            &settings.camera_topic,
+            &base_settings.pi_zero_id,
+            response.into_bytes()?,
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Uploads preview frame to the server, with header as metadata
+async fn preview_frame_send(
+    base_settings: &BaseSettings,
+    http_client: &Client,
+    frame: PreviewFrame,
+    header: &PreviewFrameHeader,
+) -> Result<(), anyhow::Error> {
+    let filename = format!("preview_{}.jpg", &base_settings.pi_zero_id);
+    let form = multipart::Form::new()
+        .part(
+            "image",
+            multipart::Part::bytes(frame.jpeg)
+                .file_name(filename)
+                .mime_str("image/jpeg")?,
+        )
+        .text("metadata", serde_json::to_string(header)?)
+        .text("piZeroId", base_settings.pi_zero_id.clone());
+
+    let response = http_client
+        .post(get_upload_preview_url(&base_settings.server_url))
+        .multipart(form)
+        .send()
+        .await?;
+
+    let status = response.status();
+
+    if status.is_success() {
         Ok(())
-    })?;
+    } else {
+        Err(anyhow::Error::msg(status.to_string()))
+    }
+}
+
+async fn set_preview_overlay(
+    base_settings: &BaseSettings,
+    settings: &Settings,
+    mqtt_client: &AsyncClient,
+    camera_service: &mut CameraService,
+    overlay_config: &OverlayConfig,
+) -> Result<(), anyhow::Error> {
+    let result = Python::attach(|py| {
+        camera_service.set_overlay(py, overlay_config, &base_settings.pi_zero_id)
+    });
+
+    let success_wrapper = match result {
+        Ok(()) => SuccessWrapper::success(PreviewOverlayResponse::PreviewOverlay {
+            overlay_config: camera_service.overlay_config.clone(),
+        }),
+        Err(e) => SuccessWrapper::failure(PreviewOverlayResponse::Failed {
+            message: e.to_string(),
+        }),
+    };
+    let response = CameraResponse::PreviewOverlay {
+        response: success_wrapper,
+    };
+
+    mqtt_client
+        .publish_individual(
+            &settings.camera_topic,
+            &base_settings.pi_zero_id,
+            response.into_bytes()?,
+        )
+        .await?;
+
     Ok(())
 }
 
-async fn stop_preview(camera_service: &mut CameraService) -> Result<(), anyhow::Error> {
-    Python::attach(|py| -> Result<(), anyhow::Error> {
+pub async fn stop_preview(
+    base_settings: &BaseSettings,
+    settings: &Settings,
+    mqtt_client: &AsyncClient,
+    camera_service: &mut CameraService,
+    reason: PreviewStopReason,
+) -> Result<(), anyhow::Error> {
+    let duration_seconds = camera_service
+        .preview_session
+        .as_ref()
+        .map(|session| session.started.elapsed().as_secs());
+
+    let stop_result = Python::attach(|py| -> Result<(), anyhow::Error> {
         let still_controls_pydict = match &camera_service.still_controls {
             Some(v) => Some(v.to_pydict(py)?),
             None => None,
         };
         camera_service.stop_preview(py, still_controls_pydict)?;
         Ok(())
-    })?;
+    });
+
+    let success_wrapper = match stop_result {
+        Ok(_) => SuccessWrapper::success(StopPreviewResponse::PreviewStopped {
+            reason,
+            duration_seconds,
+        }),
+        Err(e) => SuccessWrapper::failure(StopPreviewResponse::Failed {
+            reason,
+            message: e.to_string(),
+        }),
+    };
+    let response = CameraResponse::StopPreview {
+        response: success_wrapper,
+    };
+
+    mqtt_client
+        .publish_individual(
+            &settings.camera_topic,
+            &base_settings.pi_zero_id,
+            response.into_bytes()?,
+        )
+        .await?;
+
     Ok(())
 }
 
@@ -566,43 +1337,202 @@ async fn set_controls(
     mqtt_client: &AsyncClient,
     camera_service: &mut CameraService,
     controls: SetControls,
+) -> Result<(), anyhow::Error> {
+    let apply_result = apply_controls(camera_service, &controls).await;
+    let response = CameraResponse::SetControls {
+        response: set_controls_response(&controls.camera_mode, apply_result),
+    };
+
+    mqtt_client
+        .publish_individual(
+            &settings.camera_topic,
+            &base_settings.pi_zero_id,
+            response.into_bytes()?,
+        )
+        .await?;
+
+    Ok(())
+}
+
+async fn patch_controls(
+    base_settings: &BaseSettings,
+    settings: &Settings,
+    mqtt_client: &AsyncClient,
+    camera_service: &mut CameraService,
+    request: &PatchControls,
+) -> Result<(), anyhow::Error> {
+    let apply_result: Result<_, anyhow::Error> = async {
+        let stored_controls = match request.camera_mode {
+            CameraMode::Still => &camera_service.still_controls,
+            CameraMode::Video => &camera_service.video_controls,
+        };
+        let (camera_controls, reset_fields) = stored_controls
+            .clone()
+            .unwrap_or_default()
+            .patch(&request.camera_controls, &camera_service.control_info)?;
+
+        let controls = SetControls {
+            camera_mode: request.camera_mode.clone(),
+            camera_controls,
+            clamp: request.clamp,
+        };
+        let (stored_controls, clamped) = apply_controls(camera_service, &controls).await?;
+        if request.camera_mode != camera_service.camera_mode {
+            // Reset when the mode is entered, unless set again before
+            for field in reset_fields {
+                let reset = (request.camera_mode.clone(), field);
+                if !camera_service.pending_control_resets.contains(&reset) {
+                    camera_service.pending_control_resets.push(reset);
+                }
+            }
+            return Ok((stored_controls, None, clamped));
+        }
+
+        // Unset controls are not sent to the camera, so it keeps the previous value
+        let effective_controls = Python::attach(|py| -> Result<_, anyhow::Error> {
+            if !reset_fields.is_empty() {
+                let names = reset_fields
+                    .iter()
+                    .map(|field| libcamera_control_name(field))
+                    .collect();
+                camera_service.reset_controls(py, names)?;
+            }
+            let metadata = camera_service.get_frame_metadata(py)?;
+            Ok(EffectiveControls::from_metadata(&metadata))
+        })?;
+
+        Ok((stored_controls, Some(effective_controls), clamped))
+    }
+    .await;
+
+    let camera_mode = request.camera_mode.clone();
+    let success_wrapper = match apply_result {
+        Ok((stored_controls, effective_controls, clamped)) => {
+            SuccessWrapper::success(PatchControlsResponse::ControlsPatched {
+                camera_mode,
+                stored_controls: Box::new(stored_controls),
+                effective_controls,
+                clamped,
+            })
+        }
+        Err(e) => match e.downcast::<ControlLimitError>() {
+            Ok(limit_error) => SuccessWrapper::failure(PatchControlsResponse::InvalidControls {
+                camera_mode,
+                violations: limit_error.violations,
+            }),
+            Err(e) => SuccessWrapper::failure(PatchControlsResponse::Failed {
+                camera_mode,
+                message: e.to_string(),
+            }),
+        },
+    };
+    let response = CameraResponse::PatchControls {
+        response: success_wrapper,
+    };
+
+    mqtt_client
+        .publish_individual(
+            &settings.camera_topic,
+            &base_settings.pi_zero_id,
+            response.into_bytes()?,
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Response to the result of apply_controls
+pub fn set_controls_response(
+    camera_mode: &CameraMode,
+    apply_result: Result<(CameraControls, Vec<ControlLimitViolation>), anyhow::Error>,
+) -> SuccessWrapper<SetControlsResponse> {
+    match apply_result {
+        Ok((camera_controls, clamped)) => {
+            SuccessWrapper::success(SetControlsResponse::ControlsSet {
+                camera_mode: camera_mode.clone(),
+                camera_controls: Box::new(camera_controls),
+                clamped,
+            })
+        }
+        Err(e) => match e.downcast::<ControlLimitError>() {
+            Ok(limit_error) => SuccessWrapper::failure(SetControlsResponse::InvalidControls {
+                camera_mode: camera_mode.clone(),
+                violations: limit_error.violations,
+            }),
+            Err(e) => SuccessWrapper::failure(SetControlsResponse::Failed {
+                camera_mode: camera_mode.clone(),
+                message: e.to_string(),
+            }),
+        },
+    }
+}
+
+/// Validates controls against camera limits, saves them to file and sets them in the camera,
+/// if the camera mode matches. Nothing is saved, if controls are out of range and not clamped.
+/// Returns the stored controls and clamped values.
+pub async fn apply_controls(
+    camera_service: &mut CameraService,
+    controls: &SetControls,
+) -> Result<(CameraControls, Vec<ControlLimitViolation>), anyhow::Error> {
+    let (camera_controls, violations) = validate_controls(camera_service, controls)?;
+    store_controls(camera_service, &controls.camera_mode, &camera_controls).await?;
+    Ok((camera_controls, violations))
+}
+
+/// Resolves names and checks limits without applying anything.
+/// Returns the controls, clamped if requested, and the limit violations.
+pub fn validate_controls(
+    camera_service: &CameraService,
+    controls: &SetControls,
+) -> Result<(CameraControls, Vec<ControlLimitViolation>), anyhow::Error> {
+    let mut camera_controls = controls.camera_controls.clone();
+    let control_info = &camera_service.control_info;
+    camera_controls.resolve_names(control_info)?;
+    let violations = Python::attach(|py| -> Result<Vec<ControlLimitViolation>, anyhow::Error> {
+        let pydict = camera_service.get_controls_limits(py)?;
+        let (min, max, _) = CameraControlsLimit::from_control_triplets(pydict)?;
+        Ok(camera_controls.validate(&min, &max, control_info, controls.clamp))
+    })?;
+    if !controls.clamp && !violations.is_empty() {
+        return Err(ControlLimitError { violations }.into());
+    }
+    Ok((camera_controls, violations))
+}
+
+/// Saves validated controls of the camera mode and sets them if the mode is running
+pub async fn store_controls(
+    camera_service: &mut CameraService,
+    camera_mode: &CameraMode,
+    camera_controls: &CameraControls,
 ) -> Result<(), anyhow::Error> {
     println!("Writing file");
     // Save controls
-    let filename = match controls.camera_mode {
+    let filename = match camera_mode {
         CameraMode::Still => STILL_CAMERA_CONTROLS_FILENAME,
         CameraMode::Video => VIDEO_CAMERA_CONTROLS_FILENAME,
     };
     let mut file = File::create(&filename).await?;
-    let bytes = serde_json::to_string(&controls.camera_controls)?.into_bytes();
+    let bytes = serde_json::to_string(camera_controls)?.into_bytes();
     file.write_all(&bytes).await?;
 
     println!("Settings controls in camera service");
     // Set controls
-    match controls.camera_mode {
-        CameraMode::Still => camera_service.still_controls = Some(controls.camera_controls.clone()),
-        CameraMode::Video => camera_service.video_controls = Some(controls.camera_controls.clone()),
+    match camera_mode {
+        CameraMode::Still => camera_service.still_controls = Some(camera_controls.clone()),
+        CameraMode::Video => camera_service.video_controls = Some(camera_controls.clone()),
     }
 
     // Only set config if config matches
-    if controls.camera_mode == camera_service.camera_mode {
+    if *camera_mode == camera_service.camera_mode {
         println!("Settings controls in python");
         Python::attach(|py| -> Result<(), anyhow::Error> {
-            camera_service.set_controls(py, controls.camera_controls.to_pydict(py)?)?;
+            camera_service.set_controls(py, camera_controls.to_pydict(py)?)?;
             Ok(())
         })?;
     }
 
-    let success_wrapper = SuccessWrapper::success("");
-
-    // Picture taken
-    mqtt_client
-        .publish_individual(
-            &settings.camera_topic,
-            &base_settings.pi_zero_id,
-            success_wrapper.into_bytes()?,
-        )
-        .await?;
+    // Controls no longer match the preset
+    clear_active_preset(camera_service).await?;
 
     Ok(())
 }
@@ -613,21 +1543,22 @@ async fn get_control_limits(
     mqtt_client: &AsyncClient,
     camera_service: &CameraService,
 ) -> Result<(), anyhow::Error> {
-    let (min, max, default) = Python::attach(
-        |py| -> Result<
-            (
-                CameraControlsLimit,
-                CameraControlsLimit,
-                CameraControlsLimit,
-            ),
-            anyhow::Error,
-        > {
+    let controls_response =
+        Python::attach(|py| -> Result<CameraControlsResponse, anyhow::Error> {
             let pydict = camera_service.get_controls_limits(py)?;
-            let controls = CameraControlsLimit::from_control_triplets(pydict)?;
-            Ok(controls)
-        },
-    )?;
-    let controls_response = CameraControlsResponse { min, max, default };
+            let (mut min, mut max, mut default) =
+                CameraControlsLimit::from_control_triplets(pydict)?;
+            let controls = camera_service.control_info.clone();
+            for limit in [&mut min, &mut max, &mut default] {
+                limit.name_enum_values(&controls);
+            }
+            Ok(CameraControlsResponse {
+                min,
+                max,
+                default,
+                controls,
+            })
+        })?;
     let success_wrapper = SuccessWrapper::success(controls_response);
 
     // Picture taken
@@ -646,8 +1577,9 @@ async fn get_controls(
     settings: &Settings,
     mqtt_client: &AsyncClient,
     camera_service: &CameraService,
+    camera_mode: &CameraMode,
 ) -> Result<(), anyhow::Error> {
-    let controls = match camera_service.camera_mode {
+    let controls = match camera_mode {
         CameraMode::Still => &camera_service.still_controls,
         CameraMode::Video => &camera_service.video_controls,
     };
@@ -664,3 +1596,507 @@ async fn get_controls(
         .await?;
     Ok(())
 }
+
+async fn set_capture_config(
+    base_settings: &BaseSettings,
+    settings: &Settings,
+    mqtt_client: &AsyncClient,
+    camera_service: &mut CameraService,
+    request: &SetCaptureConfig,
+) -> Result<(), anyhow::Error> {
+    let set_result = async {
+        let capture_config = CaptureConfig::new(
+            &request.camera_mode,
+            request.resolution,
+            request.format,
+            request.sensor_mode,
+            request.buffer_count,
+            request.colour_space,
+            &camera_service.sensor_modes,
+        )?;
+        let applied = Python::attach(|py| {
+            camera_service.set_capture_config(py, &request.camera_mode, &capture_config)
+        })?;
+
+        // Save capture config, so it is used on startup
+        let filename = match request.camera_mode {
+            CameraMode::Still => STILL_CAPTURE_CONFIG_FILENAME,
+            CameraMode::Video => VIDEO_CAPTURE_CONFIG_FILENAME,
+        };
+        fs::write(filename, serde_json::to_string(&capture_config)?).await?;
+
+        Ok::<(CaptureConfig, bool), anyhow::Error>((capture_config, applied))
+    }
+    .await;
+
+    let success_wrapper = match set_result {
+        Ok((capture_config, applied)) => {
+            SuccessWrapper::success(CaptureConfigResponse::CaptureConfig {
+                camera_mode: request.camera_mode.clone(),
+                capture_config,
+                applied,
+            })
+        }
+        Err(e) => SuccessWrapper::failure(CaptureConfigResponse::Failed {
+            camera_mode: request.camera_mode.clone(),
+            message: e.to_string(),
+        }),
+    };
+    let response = CameraResponse::CaptureConfig {
+        response: success_wrapper,
+    };
+
+    mqtt_client
+        .publish_individual(
+            &settings.camera_topic,
+            &base_settings.pi_zero_id,
+            response.into_bytes()?,
+        )
+        .await?;
+
+    Ok(())
+}
+
+async fn get_capture_config(
+    base_settings: &BaseSettings,
+    settings: &Settings,
+    mqtt_client: &AsyncClient,
+    camera_service: &CameraService,
+    camera_mode: CameraMode,
+) -> Result<(), anyhow::Error> {
+    let capture_config = match camera_mode {
+        CameraMode::Still => camera_service.still_capture_config.clone(),
+        CameraMode::Video => camera_service.video_capture_config.clone(),
+    };
+    let applied = camera_service.capture_config_applied(&camera_mode);
+
+    let success_wrapper = SuccessWrapper::success(CaptureConfigResponse::CaptureConfig {
+        camera_mode,
+        capture_config,
+        applied,
+    });
+    let response = CameraResponse::CaptureConfig {
+        response: success_wrapper,
+    };
+
+    mqtt_client
+        .publish_individual(
+            &settings.camera_topic,
+            &base_settings.pi_zero_id,
+            response.into_bytes()?,
+        )
+        .await?;
+
+    Ok(())
+}
+
+async fn list_sensor_modes(
+    base_settings: &BaseSettings,
+    settings: &Settings,
+    mqtt_client: &AsyncClient,
+    camera_service: &CameraService,
+) -> Result<(), anyhow::Error> {
+    let success_wrapper = SuccessWrapper::success(camera_service.sensor_modes.clone());
+    let response = CameraResponse::SensorModes {
+        response: success_wrapper,
+    };
+
+    mqtt_client
+        .publish_individual(
+            &settings.camera_topic,
+            &base_settings.pi_zero_id,
+            response.into_bytes()?,
+        )
+        .await?;
+
+    Ok(())
+}
+
+async fn set_orientation(
+    base_settings: &BaseSettings,
+    settings: &Settings,
+    mqtt_client: &AsyncClient,
+    camera_service: &mut CameraService,
+    orientation: &Orientation,
+) -> Result<(), anyhow::Error> {
+    let set_result = async {
+        let applied = Python::attach(|py| camera_service.set_orientation(py, orientation))?;
+
+        // Save orientation, so it is used on startup
+        fs::write(ORIENTATION_FILENAME, serde_json::to_string(orientation)?).await?;
+
+        Ok::<bool, anyhow::Error>(applied)
+    }
+    .await;
+
+    let success_wrapper = match set_result {
+        Ok(applied) => SuccessWrapper::success(OrientationResponse::Orientation {
+            orientation: *orientation,
+            applied,
+        }),
+        Err(e) => SuccessWrapper::failure(OrientationResponse::Failed {
+            message: e.to_string(),
+        }),
+    };
+    let response = CameraResponse::Orientation {
+        response: success_wrapper,
+    };
+
+    mqtt_client
+        .publish_individual(
+            &settings.camera_topic,
+            &base_settings.pi_zero_id,
+            response.into_bytes()?,
+        )
+        .await?;
+
+    Ok(())
+}
+
+async fn get_effective_controls(
+    base_settings: &BaseSettings,
+    settings: &Settings,
+    mqtt_client: &AsyncClient,
+    camera_service: &CameraService,
+    camera_mode: CameraMode,
+) -> Result<(), anyhow::Error> {
+    let stored_controls = match camera_mode {
+        CameraMode::Still => camera_service.still_controls.clone(),
+        CameraMode::Video => camera_service.video_controls.clone(),
+    };
+
+    // Metadata only reflects the mode the camera is in
+    let effective_result = if camera_mode == camera_service.camera_mode {
+        Python::attach(|py| -> PyResult<Option<EffectiveControls>> {
+            let metadata = camera_service.get_frame_metadata(py)?;
+            Ok(Some(EffectiveControls::from_metadata(&metadata)))
+        })
+    } else {
+        Ok(None)
+    };
+
+    let success_wrapper = match effective_result {
+        Ok(effective_controls) => {
+            SuccessWrapper::success(EffectiveControlsResponse::EffectiveControls {
+                camera_mode,
+                stored_controls: stored_controls.map(Box::new),
+                effective_controls,
+            })
+        }
+        Err(e) => SuccessWrapper::failure(EffectiveControlsResponse::Failed {
+            camera_mode,
+            message: e.to_string(),
+        }),
+    };
+    let response = CameraResponse::EffectiveControls {
+        response: success_wrapper,
+    };
+
+    mqtt_client
+        .publish_individual(
+            &settings.camera_topic,
+            &base_settings.pi_zero_id,
+            response.into_bytes()?,
+        )
+        .await?;
+
+    Ok(())
+}
+
+async fn auto_focus(
+    base_settings: &BaseSettings,
+    settings: &Settings,
+    mqtt_client: &AsyncClient,
+    camera_service: &mut CameraService,
+    request: AutoFocus,
+) -> Result<(), anyhow::Error> {
+    let result = run_auto_focus(camera_service, &request).await;
+
+    let success_wrapper = match result {
+        Ok((effective_controls, locked)) => SuccessWrapper::success(AutoFocusResponse::AutoFocus {
+            af_state: effective_controls.af_state,
+            lens_position: effective_controls.lens_position,
+            locked,
+        }),
+        Err(e) => SuccessWrapper::failure(AutoFocusResponse::Failed {
+            message: e.to_string(),
+        }),
+    };
+    let response = CameraResponse::AutoFocus {
+        response: success_wrapper,
+    };
+
+    mqtt_client
+        .publish_individual(
+            &settings.camera_topic,
+            &base_settings.pi_zero_id,
+            response.into_bytes()?,
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Runs an autofocus cycle, locks the lens position in controls of the current camera mode
+/// if requested. Returns controls of the final frame and whether the position was locked.
+async fn run_auto_focus(
+    camera_service: &mut CameraService,
+    request: &AutoFocus,
+) -> Result<(EffectiveControls, bool), anyhow::Error> {
+    let timeout = Duration::from_millis(
+        request
+            .timeout_millis
+            .unwrap_or(DEFAULT_AUTO_FOCUS_TIMEOUT_MILLIS),
+    );
+    let camera_mode = camera_service.camera_mode.clone();
+    let stored_controls = match camera_mode {
+        CameraMode::Still => camera_service.still_controls.clone(),
+        CameraMode::Video => camera_service.video_controls.clone(),
+    }
+    .unwrap_or_default();
+
+    let result = async {
+        let effective_controls = Python::attach(|py| camera_service.auto_focus(py, timeout))?;
+        if !request.lock {
+            return Ok((effective_controls, false));
+        }
+
+        let lens_position = match (
+            effective_controls.af_state,
+            effective_controls.lens_position,
+        ) {
+            (Some(AfState::Focused), Some(lens_position)) => lens_position,
+            (af_state, _) => anyhow::bail!("Autofocus did not focus, state {:?}", af_state),
+        };
+        let mut camera_controls = stored_controls.clone();
+        camera_controls.af_mode = Some(EnumControl::Name("Manual".to_string()));
+        camera_controls.af_trigger = None;
+        camera_controls.lens_position = Some(lens_position);
+        let controls = SetControls {
+            camera_mode,
+            camera_controls,
+            clamp: true,
+        };
+        apply_controls(camera_service, &controls).await?;
+        Ok((effective_controls, true))
+    }
+    .await;
+
+    // The cycle switches to AfMode Auto, unless the position was locked the stored mode is restored
+    if !matches!(result, Ok((_, true))) {
+        let restored = Python::attach(|py| -> Result<(), anyhow::Error> {
+            match &stored_controls.af_mode {
+                Some(af_mode) => {
+                    let camera_controls = CameraControls {
+                        af_mode: Some(af_mode.clone()),
+                        ..Default::default()
+                    };
+                    camera_service.set_controls(py, camera_controls.to_pydict(py)?)?;
+                }
+                None => camera_service.reset_controls(py, vec!["AfMode".to_string()])?,
+            }
+            Ok(())
+        });
+        match (&result, restored) {
+            (Ok(_), Err(e)) => return Err(e),
+            (Err(_), Err(e)) => println!("Failed to restore AfMode: {:?}", e),
+            _ => {}
+        }
+    }
+    result
+}
+
+/// Detects fiducial markers in the next frame of the current camera mode
+async fn detect_frame_markers(
+    base_settings: &BaseSettings,
+    settings: &Settings,
+    mqtt_client: &AsyncClient,
+    camera_service: &CameraService,
+) -> Result<(), anyhow::Error> {
+    let camera_mode = camera_service.camera_mode.clone();
+    let orientation = camera_service.orientation;
+    let result = Python::attach(|py| camera_service.capture_frame(py));
+
+    let success_wrapper = match result {
+        Ok(frame) => {
+            let image = GrayImage::new(&frame.pixels, frame.width, frame.height, frame.format);
+            let markers = detect_markers(&image);
+            // Same frame as TakePicture markers, which are detected after the quarter turn
+            let (markers, width, height) = if orientation.quarter_turn() {
+                let markers = markers
+                    .iter()
+                    .map(|marker| marker.rotate_quarter_turn(frame.height))
+                    .collect();
+                (markers, frame.height, frame.width)
+            } else {
+                (markers, frame.width, frame.height)
+            };
+            SuccessWrapper::success(MarkersResponse::MarkersDetected {
+                camera_mode,
+                orientation,
+                width,
+                height,
+                markers,
+            })
+        }
+        Err(e) => SuccessWrapper::failure(MarkersResponse::Failed {
+            camera_mode,
+            message: e.to_string(),
+        }),
+    };
+    let response = CameraResponse::Markers {
+        response: success_wrapper,
+    };
+
+    mqtt_client
+        .publish_individual(
+            &settings.camera_topic,
+            &base_settings.pi_zero_id,
+            response.into_bytes()?,
+        )
+        .await?;
+
+    Ok(())
+}
+
+async fn measure_exposure(
+    base_settings: &BaseSettings,
+    settings: &Settings,
+    mqtt_client: &AsyncClient,
+    camera_service: &mut CameraService,
+    request: MeasureExposure,
+) -> Result<(), anyhow::Error> {
+    let camera_mode = camera_service.camera_mode.clone();
+    let result = run_measure_exposure(camera_service, &request).await;
+
+    let success_wrapper = match result {
+        Ok((convergence, locked)) => {
+            let effective_controls = convergence.effective_controls.unwrap_or_default();
+            SuccessWrapper::success(MeasureExposureResponse::ExposureMeasured {
+                camera_mode,
+                exposure_time: effective_controls.exposure_time,
+                analogue_gain: effective_controls.analogue_gain,
+                colour_gains: effective_controls.colour_gains,
+                lux: effective_controls.lux,
+                converged: convergence.converged,
+                locked,
+            })
+        }
+        Err(e) => SuccessWrapper::failure(MeasureExposureResponse::Failed {
+            camera_mode,
+            message: e.to_string(),
+        }),
+    };
+    let response = CameraResponse::MeasureExposure {
+        response: success_wrapper,
+    };
+
+    mqtt_client
+        .publish_individual(
+            &settings.camera_topic,
+            &base_settings.pi_zero_id,
+            response.into_bytes()?,
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Runs AE/AWB until converged, then stores the values as manual controls if requested,
+/// otherwise restores the stored controls. Returns the convergence and whether it was locked.
+async fn run_measure_exposure(
+    camera_service: &mut CameraService,
+    request: &MeasureExposure,
+) -> Result<(Convergence, bool), anyhow::Error> {
+    let timeout = Duration::from_millis(
+        request
+            .timeout_millis
+            .unwrap_or(DEFAULT_MEASURE_EXPOSURE_TIMEOUT_MILLIS),
+    );
+    let camera_mode = camera_service.camera_mode.clone();
+    let stored_controls = match camera_mode {
+        CameraMode::Still => camera_service.still_controls.clone(),
+        CameraMode::Video => camera_service.video_controls.clone(),
+    }
+    .unwrap_or_default();
+
+    let result = async {
+        let convergence = Python::attach(|py| -> Result<Convergence, anyhow::Error> {
+            let auto_controls = CameraControls {
+                ae_enable: Some(true),
+                awb_enable: Some(true),
+                ..Default::default()
+            };
+            camera_service.set_controls(py, auto_controls.to_pydict(py)?)?;
+            camera_service.wait_for_convergence(py, Instant::now() + timeout)
+        })?;
+        if !request.lock {
+            return Ok((convergence, false));
+        }
+
+        let controls = SetControls {
+            camera_mode,
+            camera_controls: manual_exposure_controls(&stored_controls, &convergence)?,
+            clamp: true,
+        };
+        apply_controls(camera_service, &controls).await?;
+        Ok((convergence, true))
+    }
+    .await;
+
+    // Locked controls replace auto exposure, on any other exit the stored controls are restored
+    if !matches!(result, Ok((_, true))) {
+        let restored = Python::attach(|py| -> Result<(), anyhow::Error> {
+            // Unset AE and AWB are not sent, so they are set back to the camera default
+            let pydict = camera_service.get_controls_limits(py)?;
+            let (_, _, default) = CameraControlsLimit::from_control_triplets(pydict)?;
+            let mut restored_controls = stored_controls.clone();
+            // Algorithms run unless disabled, if the camera reports no default
+            restored_controls.ae_enable = stored_controls
+                .ae_enable
+                .or(default.ae_enable)
+                .or(Some(true));
+            restored_controls.awb_enable = stored_controls
+                .awb_enable
+                .or(default.awb_enable)
+                .or(Some(true));
+            camera_service.set_controls(py, restored_controls.to_pydict(py)?)?;
+            Ok(())
+        });
+        match (&result, restored) {
+            (Ok(_), Err(e)) => return Err(e),
+            (Err(_), Err(e)) => println!("Failed to restore controls: {:?}", e),
+            _ => {}
+        }
+    }
+    result
+}
+
+/// Stored controls with auto exposure and white balance replaced by the measured values
+fn manual_exposure_controls(
+    stored_controls: &CameraControls,
+    convergence: &Convergence,
+) -> Result<CameraControls, anyhow::Error> {
+    if !convergence.converged {
+        anyhow::bail!("Exposure did not converge, controls not locked");
+    }
+    let Some(EffectiveControls {
+        exposure_time: Some(exposure_time),
+        analogue_gain: Some(analogue_gain),
+        colour_gains,
+        ..
+    }) = convergence.effective_controls.clone()
+    else {
+        anyhow::bail!("Camera did not report exposure time and gain");
+    };
+
+    let mut camera_controls = stored_controls.clone();
+    camera_controls.ae_enable = Some(false);
+    camera_controls.exposure_time = Some(exposure_time);
+    camera_controls.analogue_gain = Some(analogue_gain);
+    if colour_gains.is_some() {
+        camera_controls.awb_enable = Some(false);
+        camera_controls.colour_gains = colour_gains;
+    }
+    Ok(camera_controls)
+}
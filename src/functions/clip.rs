@@ -0,0 +1,309 @@
+use crate::camera::{CameraMode, CameraService, ClipConfig, ClipFormat, ClipMetadata};
+use crate::endpoints::get_upload_clip_url;
+use crate::functions::camera::{
+    epoch_to_monotonic, get_metadata_filename, get_photos_path, ScheduledTime,
+};
+use crate::functions::requests::{RecordClip, SendPicture};
+use crate::functions::responses::{CameraResponse, RecordClipResponse, SendClipResponse};
+use crate::settings::{BaseSettings, Settings};
+use crate::utils::{AsyncClientExt, SuccessWrapper};
+use pyo3::Python;
+use reqwest::{multipart, Client};
+use rumqttc::v5::AsyncClient;
+use std::path::Path;
+use tokio::fs;
+use uuid::Uuid;
+
+pub async fn record_clip(
+    base_settings: &BaseSettings,
+    settings: &Settings,
+    mqtt_client: &AsyncClient,
+    camera_service: &CameraService,
+    request: &RecordClip,
+    message_received_nanos: Option<i64>,
+) -> Result<(), anyhow::Error> {
+    if camera_service.camera_mode != CameraMode::Still {
+        anyhow::bail!("Preview is running, stop it before recording a clip");
+    }
+    let clip_config = ClipConfig::new(
+        request.resolution,
+        request.frame_rate,
+        request.duration_millis,
+        request.format,
+        &camera_service.sensor_modes,
+    )?;
+
+    // Same conversion as pictures, so all Pis start on the same frame
+    let ScheduledTime {
+        wall_nanoseconds,
+        wait_time,
+        monotonic_nanoseconds,
+    } = epoch_to_monotonic(request.start_epoch)?;
+    // return error, if wait time is negative
+    if wait_time < 0 {
+        let err = RecordClipResponse::ClipFailedToSchedule {
+            uuid: request.uuid,
+            message: format!(
+                "Current time: {}, clip time: {}, late by {} ns",
+                wall_nanoseconds, request.start_epoch, wait_time
+            ),
+            message_received_nanos,
+            wait_time_nanos: wait_time,
+        };
+        let success_wrapper = SuccessWrapper::failure(err);
+        let response = CameraResponse::RecordClip {
+            response: success_wrapper,
+        };
+
+        mqtt_client
+            .publish_individual(
+                &settings.camera_topic,
+                &base_settings.pi_zero_id,
+                response.into_bytes()?,
+            )
+            .await?;
+
+        // Return ok, as error handled in this function
+        return Ok(());
+    }
+
+    let clip_path = get_photos_path(&get_clip_filename(
+        &request.uuid,
+        &base_settings.pi_zero_id,
+        clip_config.format,
+    ));
+    let end = monotonic_nanoseconds as u64 + request.duration_millis * 1_000_000;
+    let record_result = Python::attach(|py| {
+        camera_service.record_clip(
+            py,
+            &clip_config,
+            monotonic_nanoseconds as u64,
+            end,
+            &clip_path,
+        )
+    });
+
+    let frame_timestamps = match record_result {
+        Ok(frame_timestamps) => {
+            let expected_frame_count = clip_config.expected_frame_count(request.duration_millis);
+            let clip_recorded = RecordClipResponse::ClipRecorded {
+                uuid: request.uuid,
+                monotonic_time: monotonic_nanoseconds,
+                frame_count: frame_timestamps.len(),
+                expected_frame_count,
+                dropped_frames: expected_frame_count.saturating_sub(frame_timestamps.len()),
+                message_received_nanos,
+                wait_time_nanos: wait_time,
+            };
+            // It's ok if it fails, we will still try to save metadata
+            let success_wrapper = SuccessWrapper::success(clip_recorded);
+            let response = CameraResponse::RecordClip {
+                response: success_wrapper,
+            }
+            .into_bytes()
+            .ok();
+            if let Some(clip_recorded) = response {
+                mqtt_client
+                    .publish_individual(
+                        &settings.camera_topic,
+                        &base_settings.pi_zero_id,
+                        clip_recorded,
+                    )
+                    .await
+                    .unwrap_or_default();
+            }
+            frame_timestamps
+        }
+        Err(e) => {
+            let err = RecordClipResponse::ClipFailedToRecord {
+                uuid: request.uuid,
+                message: e.to_string(),
+                message_received_nanos,
+                wait_time_nanos: wait_time,
+            };
+            let success_wrapper = SuccessWrapper::failure(err);
+            let response = CameraResponse::RecordClip {
+                response: success_wrapper,
+            };
+
+            mqtt_client
+                .publish_individual(
+                    &settings.camera_topic,
+                    &base_settings.pi_zero_id,
+                    response.into_bytes()?,
+                )
+                .await?;
+
+            // Return ok, as error handled in this function
+            return Ok(());
+        }
+    };
+
+    let clip_metadata = ClipMetadata {
+        clip_config,
+        start_monotonic_nanos: monotonic_nanoseconds,
+        duration_millis: request.duration_millis,
+        frame_timestamps,
+    };
+    let metadata_path = get_photos_path(&get_metadata_filename(
+        &request.uuid,
+        &base_settings.pi_zero_id,
+    ));
+    let save_result = async {
+        let metadata_json = serde_json::to_string(&clip_metadata)?;
+        fs::write(&metadata_path, metadata_json).await?;
+        Ok::<(), anyhow::Error>(())
+    }
+    .await;
+
+    let success_wrapper = match save_result {
+        Ok(_) => {
+            SuccessWrapper::success(RecordClipResponse::ClipSavedOnDevice { uuid: request.uuid })
+        }
+        Err(e) => SuccessWrapper::failure(RecordClipResponse::ClipFailedToSave {
+            uuid: request.uuid,
+            message: e.to_string(),
+        }),
+    };
+    let response = CameraResponse::RecordClip {
+        response: success_wrapper,
+    };
+
+    mqtt_client
+        .publish_individual(
+            &settings.camera_topic,
+            &base_settings.pi_zero_id,
+            response.into_bytes()?,
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub async fn send_clip(
+    base_settings: &BaseSettings,
+    settings: &Settings,
+    mqtt_client: &AsyncClient,
+    http_client: &Client,
+    request: &SendPicture,
+) -> Result<(), anyhow::Error> {
+    let metadata_path = get_photos_path(&get_metadata_filename(
+        &request.uuid,
+        &base_settings.pi_zero_id,
+    ));
+
+    // Read clip, format is not known, so use the file that exists
+    let read_result = match find_clip_filename(&request.uuid, &base_settings.pi_zero_id) {
+        Some(filename) => fs::read(get_photos_path(&filename))
+            .await
+            .map(|bytes| (filename, bytes))
+            .map_err(anyhow::Error::from),
+        None => Err(anyhow::Error::msg("Clip not found")),
+    };
+    let (filename, bytes) = match read_result {
+        Ok(v) => v,
+        Err(e) => {
+            let err = SendClipResponse::ClipFailedToRead {
+                uuid: request.uuid,
+                message: e.to_string(),
+            };
+            let success_wrapper = SuccessWrapper::failure(err);
+            let response = CameraResponse::SendClip {
+                response: success_wrapper,
+            };
+
+            mqtt_client
+                .publish_individual(
+                    &settings.camera_topic,
+                    &base_settings.pi_zero_id,
+                    response.into_bytes()?,
+                )
+                .await?;
+
+            // Return ok, as error handled in this function
+            return Ok(());
+        }
+    };
+
+    let metadata_json = fs::read_to_string(metadata_path)
+        .await
+        .unwrap_or("{}".to_string());
+
+    let send_result = clip_send(
+        base_settings,
+        request,
+        http_client,
+        bytes,
+        filename,
+        metadata_json,
+    )
+    .await;
+
+    let success_wrapper = match send_result {
+        Ok(_) => SuccessWrapper::success(SendClipResponse::ClipSent { uuid: request.uuid }),
+        Err(e) => SuccessWrapper::failure(SendClipResponse::ClipFailedToSend {
+            uuid: request.uuid,
+            message: e.to_string(),
+        }),
+    };
+    let response = CameraResponse::SendClip {
+        response: success_wrapper,
+    };
+
+    mqtt_client
+        .publish_individual(
+            &settings.camera_topic,
+            &base_settings.pi_zero_id,
+            response.into_bytes()?,
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Uploads clip with its metadata sidecar
+async fn clip_send(
+    base_settings: &BaseSettings,
+    request: &SendPicture,
+    http_client: &Client,
+    bytes: Vec<u8>,
+    filename: String,
+    metadata_json: String,
+) -> Result<(), anyhow::Error> {
+    let uuid = &request.uuid.simple();
+    let form = multipart::Form::new()
+        .part(
+            "clip",
+            multipart::Part::bytes(bytes)
+                .file_name(filename)
+                .mime_str("application/octet-stream")?,
+        )
+        .text("metadata", metadata_json)
+        .text("uuid", uuid.to_string());
+
+    let response = http_client
+        .post(get_upload_clip_url(&base_settings.server_url))
+        .multipart(form)
+        .send()
+        .await?;
+
+    let status = response.status();
+
+    if status.is_success() {
+        Ok(())
+    } else {
+        Err(anyhow::Error::msg(status.to_string()))
+    }
+}
+
+fn get_clip_filename(uuid: &Uuid, pi_zero_id: &str, format: ClipFormat) -> String {
+    format!("{}_{}.{}", &uuid, &pi_zero_id, format.extension())
+}
+
+/// Finds clip filename in photos, regardless of format
+fn find_clip_filename(uuid: &Uuid, pi_zero_id: &str) -> Option<String> {
+    ClipFormat::ALL
+        .iter()
+        .map(|format| get_clip_filename(uuid, pi_zero_id, *format))
+        .find(|filename| Path::new(&get_photos_path(filename)).exists())
+}
@@ -0,0 +1,178 @@
+use crate::camera::{CameraMode, CameraService, FocusAssistConfig, FocusAssistStopReason};
+use crate::functions::blocking::run_blocking;
+use crate::functions::responses::{CameraResponse, FocusAssistResponse};
+use crate::settings::{BaseSettings, Settings};
+use crate::utils::{AsyncClientExt, ResultExt, SuccessWrapper};
+use pyo3::Python;
+use rumqttc::v5::AsyncClient;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+/// How often to check for started focus assist
+const FOCUS_IDLE_INTERVAL: Duration = Duration::from_millis(500);
+
+pub async fn start_focus_assist(
+    base_settings: &BaseSettings,
+    settings: &Settings,
+    mqtt_client: &AsyncClient,
+    camera_service: &mut CameraService,
+    config: FocusAssistConfig,
+) -> Result<(), anyhow::Error> {
+    let result = (|| {
+        let Some(preview_config) = camera_service.preview_config.as_ref() else {
+            anyhow::bail!("Focus assist needs a running preview");
+        };
+        config.validate(
+            preview_config.resolution.width,
+            preview_config.resolution.height,
+        )?;
+        Python::attach(|py| camera_service.set_focus_assist(py, Some(&config)))
+    })();
+
+    let success_wrapper = match result {
+        Ok(()) => SuccessWrapper::success(FocusAssistResponse::FocusAssistStarted { config }),
+        Err(e) => SuccessWrapper::failure(FocusAssistResponse::Failed {
+            message: e.to_string(),
+        }),
+    };
+    publish_focus_response(base_settings, settings, mqtt_client, success_wrapper).await
+}
+
+pub async fn stop_focus_assist(
+    base_settings: &BaseSettings,
+    settings: &Settings,
+    mqtt_client: &AsyncClient,
+    camera_service: &mut CameraService,
+    reason: FocusAssistStopReason,
+) -> Result<(), anyhow::Error> {
+    let result = if camera_service.focus_assist.is_none() {
+        Err(anyhow::anyhow!("Focus assist is not running"))
+    } else {
+        // Keep the last score, the running max is what the lens was focused to
+        Python::attach(|py| -> Result<_, anyhow::Error> {
+            let score = camera_service.get_focus_score(py)?;
+            camera_service.set_focus_assist(py, None)?;
+            Ok(score)
+        })
+    };
+
+    let success_wrapper = match result {
+        Ok(last_score) => {
+            SuccessWrapper::success(FocusAssistResponse::FocusAssistStopped { reason, last_score })
+        }
+        Err(e) => SuccessWrapper::failure(FocusAssistResponse::Failed {
+            message: e.to_string(),
+        }),
+    };
+    publish_focus_response(base_settings, settings, mqtt_client, success_wrapper).await
+}
+
+/// Publishes sharpness scores of preview frames while focus assist is running.
+/// Scores are read on a blocking thread, so the MQTT loop is not held up.
+/// Runs forever, spawned next to the MQTT loop.
+pub async fn watch_focus_assist(
+    base_settings: Arc<BaseSettings>,
+    settings: Arc<Settings>,
+    mqtt_client: Arc<AsyncClient>,
+    camera_service: Arc<Mutex<CameraService>>,
+) {
+    // Only new frames are published
+    let mut last_timestamp = None;
+    loop {
+        let interval = check_focus_assist(
+            &base_settings,
+            &settings,
+            &mqtt_client,
+            &camera_service,
+            &mut last_timestamp,
+        )
+        .await;
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// Publishes the latest score, returns the time until the next one
+async fn check_focus_assist(
+    base_settings: &BaseSettings,
+    settings: &Settings,
+    mqtt_client: &AsyncClient,
+    camera_service: &Arc<Mutex<CameraService>>,
+    last_timestamp: &mut Option<i64>,
+) -> Duration {
+    let interval = {
+        let mut camera_service = camera_service.lock().await;
+        let Some(config) = camera_service.focus_assist.as_ref() else {
+            return FOCUS_IDLE_INTERVAL;
+        };
+        let interval = Duration::from_millis(config.publish_interval_millis);
+        // Python stops measuring with the preview, report it here
+        if camera_service.camera_mode != CameraMode::Video {
+            camera_service.focus_assist = None;
+            let success_wrapper =
+                SuccessWrapper::success(FocusAssistResponse::FocusAssistStopped {
+                    reason: FocusAssistStopReason::PreviewStopped,
+                    last_score: None,
+                });
+            publish_focus_response(base_settings, settings, mqtt_client, success_wrapper)
+                .await
+                .send_if_err(base_settings, mqtt_client, &settings.camera_topic)
+                .await
+                .unwrap_or_default();
+            return FOCUS_IDLE_INTERVAL;
+        }
+        interval
+    };
+
+    // Guard is dropped right away, the camera is not needed for publishing
+    let score = run_blocking(camera_service, |camera_service| {
+        Python::attach(|py| camera_service.get_focus_score(py))
+    })
+    .await
+    .map(|(_, score)| score);
+    let score = match score {
+        Ok(Ok(Some(score))) => score,
+        Ok(Ok(None)) => return interval,
+        Ok(Err(e)) => {
+            println!("Failed to get focus score: {:?}", e);
+            return interval;
+        }
+        Err(e) => {
+            println!("Failed to get focus score: {:?}", e);
+            return interval;
+        }
+    };
+    if *last_timestamp == Some(score.sensor_timestamp) {
+        return interval;
+    }
+    *last_timestamp = Some(score.sensor_timestamp);
+
+    let success_wrapper = SuccessWrapper::success(FocusAssistResponse::FocusScore { score });
+    publish_focus_response(base_settings, settings, mqtt_client, success_wrapper)
+        .await
+        .send_if_err(base_settings, mqtt_client, &settings.camera_topic)
+        .await
+        .unwrap_or_default();
+    interval
+}
+
+async fn publish_focus_response(
+    base_settings: &BaseSettings,
+    settings: &Settings,
+    mqtt_client: &AsyncClient,
+    success_wrapper: SuccessWrapper<FocusAssistResponse>,
+) -> Result<(), anyhow::Error> {
+    let response = CameraResponse::FocusAssist {
+        response: success_wrapper,
+    };
+
+    mqtt_client
+        .publish_individual(
+            &settings.camera_topic,
+            &base_settings.pi_zero_id,
+            response.into_bytes()?,
+        )
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,194 @@
+use crate::camera::{CameraMode, CameraService, PreviewFrame};
+use crate::functions::blocking::run_blocking;
+use crate::functions::camera::{apply_controls, set_controls_response};
+use crate::functions::requests::SetControls;
+use crate::settings::Settings;
+use crate::utils::SuccessWrapper;
+use futures_util::{SinkExt, StreamExt};
+use pyo3::Python;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex;
+use tokio::time::MissedTickBehavior;
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::header::AUTHORIZATION;
+use tokio_tungstenite::tungstenite::http::StatusCode;
+use tokio_tungstenite::tungstenite::Message;
+
+/// Limits live view to 10 frames per second
+const LIVE_VIEW_FRAME_INTERVAL: Duration = Duration::from_millis(100);
+
+/// WebSocket server for live view. Pushes preview frames as binary jpeg messages and
+/// applies SetControls json text messages, replying with the result.
+/// Failures to get a frame are sent as failure json text messages.
+/// Only started if a port and token are configured. Runs forever.
+pub async fn serve_live_view(settings: Arc<Settings>, camera_service: Arc<Mutex<CameraService>>) {
+    let Some(port) = settings.live_view_port else {
+        println!("Live view disabled, no port set");
+        // Returning would end the main loop
+        return std::future::pending().await;
+    };
+    let Some(token) = settings
+        .live_view_token
+        .clone()
+        .filter(|token| !token.is_empty())
+    else {
+        println!("Live view disabled, no token set");
+        return std::future::pending().await;
+    };
+    let token = Arc::new(token);
+    let listener = match TcpListener::bind((settings.live_view_address.as_str(), port)).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            println!("Failed to start live view server: {:?}", e);
+            return std::future::pending().await;
+        }
+    };
+
+    loop {
+        let (stream, address) = match listener.accept().await {
+            Ok(v) => v,
+            Err(e) => {
+                println!("Failed to accept live view client: {:?}", e);
+                continue;
+            }
+        };
+        println!("Live view client connected: {}", address);
+
+        let camera_service = Arc::clone(&camera_service);
+        let token = Arc::clone(&token);
+        tokio::spawn(async move {
+            let result = handle_live_view_client(stream, &camera_service, &token).await;
+            println!("Live view client disconnected: {}, {:?}", address, result);
+        });
+    }
+}
+
+// The handshake callback must return tungstenite's error response, it can't be boxed
+#[allow(clippy::result_large_err)]
+async fn handle_live_view_client(
+    stream: TcpStream,
+    camera_service: &Arc<Mutex<CameraService>>,
+    token: &str,
+) -> Result<(), anyhow::Error> {
+    let websocket =
+        tokio_tungstenite::accept_hdr_async(stream, |request: &Request, response: Response| {
+            if request_token(request)
+                .is_some_and(|request_token| tokens_match(request_token, token))
+            {
+                Ok(response)
+            } else {
+                let mut error = ErrorResponse::new(Some("Invalid live view token".to_string()));
+                *error.status_mut() = StatusCode::UNAUTHORIZED;
+                Err(error)
+            }
+        })
+        .await?;
+    let (mut sender, mut receiver) = websocket.split();
+
+    let mut interval = tokio::time::interval(LIVE_VIEW_FRAME_INTERVAL);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let mut last_frame: Option<Vec<u8>> = None;
+    // Errors are sent once, not on every frame until they are resolved
+    let mut last_error: Option<String> = None;
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                // Guard is dropped right away, the camera is not needed for sending
+                let frame = run_blocking(camera_service, get_live_view_frame)
+                    .await
+                    .and_then(|(_, frame)| frame);
+                match frame {
+                    // Do not resend the same frame, if preview is slower than live view
+                    Ok(Some(frame)) if last_frame.as_ref() != Some(&frame.jpeg) => {
+                        sender.send(Message::binary(frame.jpeg.clone())).await?;
+                        last_frame = Some(frame.jpeg);
+                        last_error = None;
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        let message = e.to_string();
+                        if last_error.as_ref() != Some(&message) {
+                            let reply = serde_json::to_string(&SuccessWrapper::failure(&message))?;
+                            sender.send(Message::text(reply)).await?;
+                            last_error = Some(message);
+                        }
+                    }
+                }
+            }
+            message = receiver.next() => {
+                let Some(message) = message else {
+                    return Ok(());
+                };
+                match message? {
+                    Message::Text(text) => {
+                        let reply = set_live_view_controls(camera_service, text.as_str()).await;
+                        sender.send(Message::text(reply)).await?;
+                    }
+                    Message::Close(_) => return Ok(()),
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+/// Token from the `Authorization: Bearer` header, or the `token` query parameter for browsers,
+/// which can't set WebSocket headers
+fn request_token(request: &Request) -> Option<&str> {
+    let header = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    header.or_else(|| {
+        request
+            .uri()
+            .query()?
+            .split('&')
+            .find_map(|parameter| parameter.strip_prefix("token="))
+    })
+}
+
+/// Compares every byte, so the time taken doesn't reveal how much of the token matched
+fn tokens_match(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+/// Gets latest preview frame, None if preview is not running.
+/// Counts as a stream client for the preview idle timeout.
+fn get_live_view_frame(
+    camera_service: &mut CameraService,
+) -> Result<Option<PreviewFrame>, anyhow::Error> {
+    if camera_service.camera_mode != CameraMode::Video {
+        return Ok(None);
+    }
+    if let Some(session) = camera_service.preview_session.as_mut() {
+        session.last_client = Instant::now();
+    }
+
+    let frame = Python::attach(|py| camera_service.get_preview_frame(py))?;
+    Ok(Some(frame))
+}
+
+/// Applies SetControls json, returns reply json
+async fn set_live_view_controls(camera_service: &Mutex<CameraService>, text: &str) -> String {
+    let controls: SetControls = match serde_json::from_str(text) {
+        Ok(controls) => controls,
+        Err(e) => {
+            return serde_json::to_string(&SuccessWrapper::failure(e.to_string()))
+                .unwrap_or_default();
+        }
+    };
+
+    let mut camera_service = camera_service.lock().await;
+    let apply_result = apply_controls(&mut camera_service, &controls).await;
+    serde_json::to_string(&set_controls_response(&controls.camera_mode, apply_result))
+        .unwrap_or_default()
+}
@@ -1,6 +1,14 @@
+mod blocking;
+mod calibration;
 mod camera;
+mod clip;
 mod command;
+mod focus;
+mod live_view;
+mod motion;
 mod ntp;
+mod preset;
+mod preview_timeout;
 mod requests;
 mod responses;
 mod status;
@@ -11,10 +19,19 @@ use crate::functions::ntp::handle_ntp;
 use crate::settings::{BaseSettings, Settings};
 use crate::utils::PublishExt;
 use crate::utils::ResultExt;
+pub use calibration::{COLOUR_CORRECTION_FILENAME, INTRINSICS_FILENAME};
 use camera::*;
-pub use camera::{STILL_CAMERA_CONTROLS_FILENAME, VIDEO_CAMERA_CONTROLS_FILENAME};
+pub use camera::{
+    ORIENTATION_FILENAME, STILL_CAMERA_CONTROLS_FILENAME, STILL_CAPTURE_CONFIG_FILENAME,
+    VIDEO_CAMERA_CONTROLS_FILENAME, VIDEO_CAPTURE_CONFIG_FILENAME,
+};
 use command::*;
+pub use focus::watch_focus_assist;
+pub use live_view::serve_live_view;
+pub use motion::{cancel_motion_capture, watch_motion};
 pub use ntp::sync_ntp;
+pub use preset::ACTIVE_PRESET_FILENAME;
+pub use preview_timeout::watch_preview_timeout;
 pub use requests::NtpRequest;
 use reqwest::Client;
 use rumqttc::v5::mqttbytes::v5::Publish;
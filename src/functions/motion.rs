@@ -0,0 +1,277 @@
+use crate::camera::{CameraMode, CameraService, MotionConfig, MotionSession, MotionStopReason};
+use crate::functions::blocking::run_blocking;
+use crate::functions::camera::take_picture;
+use crate::functions::requests::TakePicture;
+use crate::functions::responses::{CameraResponse, MotionCaptureResponse};
+use crate::settings::{BaseSettings, Settings};
+use crate::utils::{AsyncClientExt, ResultExt, SuccessWrapper};
+use pyo3::Python;
+use rumqttc::v5::AsyncClient;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+use tokio::task::JoinSet;
+use uuid::Uuid;
+
+/// How often to check for a started session
+const MOTION_IDLE_INTERVAL: Duration = Duration::from_millis(500);
+/// Pictures are scheduled this far ahead, so they are not late by the time they are taken
+const MOTION_CAPTURE_DELAY_MILLIS: u64 = 100;
+
+pub async fn start_motion_capture(
+    base_settings: &BaseSettings,
+    settings: &Settings,
+    mqtt_client: &AsyncClient,
+    camera_service: &mut CameraService,
+    config: MotionConfig,
+) -> Result<(), anyhow::Error> {
+    let result = config.validate().and_then(|_| {
+        // Pictures go through the still pipeline, so frames are analysed in still mode
+        if camera_service.camera_mode != CameraMode::Still {
+            anyhow::bail!("Motion capture runs in still mode, stop the preview first");
+        }
+        camera_service.motion_session = Some(MotionSession::start(config.clone()));
+        Ok(())
+    });
+
+    let success_wrapper = match result {
+        Ok(()) => SuccessWrapper::success(MotionCaptureResponse::MotionCaptureStarted { config }),
+        Err(e) => SuccessWrapper::failure(MotionCaptureResponse::Failed {
+            message: e.to_string(),
+        }),
+    };
+    publish_motion_response(base_settings, settings, mqtt_client, success_wrapper).await
+}
+
+pub async fn stop_motion_capture(
+    base_settings: &BaseSettings,
+    settings: &Settings,
+    mqtt_client: &AsyncClient,
+    camera_service: &mut CameraService,
+    reason: MotionStopReason,
+) -> Result<(), anyhow::Error> {
+    let success_wrapper = match camera_service.motion_session.take() {
+        Some(session) => SuccessWrapper::success(MotionCaptureResponse::MotionCaptureStopped {
+            reason,
+            captures: session.captures,
+            duration_seconds: session.started.elapsed().as_secs(),
+        }),
+        None => SuccessWrapper::failure(MotionCaptureResponse::Failed {
+            message: "Motion capture is not running".to_string(),
+        }),
+    };
+    publish_motion_response(base_settings, settings, mqtt_client, success_wrapper).await
+}
+
+/// Stops motion capture for the cancel topic, returns whether it was running
+pub async fn cancel_motion_capture(
+    base_settings: &BaseSettings,
+    settings: &Settings,
+    mqtt_client: &AsyncClient,
+    camera_service: &mut CameraService,
+) -> bool {
+    if camera_service.motion_session.is_none() {
+        return false;
+    }
+    stop_motion_capture(
+        base_settings,
+        settings,
+        mqtt_client,
+        camera_service,
+        MotionStopReason::Cancelled,
+    )
+    .await
+    .send_if_err(base_settings, mqtt_client, &settings.camera_topic)
+    .await
+    .unwrap_or_default();
+    true
+}
+
+/// Analyses frames of the running motion capture session and takes pictures on motion.
+/// Frames are analysed on a blocking thread, pictures are taken in the MQTT loop's tasks,
+/// so the cancel topic aborts them. Runs forever, spawned next to the MQTT loop.
+pub async fn watch_motion(
+    base_settings: Arc<BaseSettings>,
+    settings: Arc<Settings>,
+    mqtt_client: Arc<AsyncClient>,
+    camera_service: Arc<Mutex<CameraService>>,
+    join_set: Arc<Mutex<JoinSet<()>>>,
+) {
+    loop {
+        let interval = check_motion(
+            &base_settings,
+            &settings,
+            &mqtt_client,
+            &camera_service,
+            &join_set,
+        )
+        .await;
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// Outcome of analysing one frame
+enum MotionCheck {
+    NotRunning,
+    CameraModeChanged,
+    NoMotion,
+    /// Changed fraction of the region
+    Motion(f32),
+}
+
+/// Analyses one frame, returns the time until the next one
+async fn check_motion(
+    base_settings: &Arc<BaseSettings>,
+    settings: &Arc<Settings>,
+    mqtt_client: &Arc<AsyncClient>,
+    camera_service: &Arc<Mutex<CameraService>>,
+    join_set: &Arc<Mutex<JoinSet<()>>>,
+) -> Duration {
+    // Avoid a blocking thread, while motion capture is not running
+    if camera_service.lock().await.motion_session.is_none() {
+        return MOTION_IDLE_INTERVAL;
+    }
+
+    let (mut camera_guard, check) = match run_blocking(camera_service, analyse_motion_frame).await {
+        Ok(checked) => checked,
+        Err(e) => {
+            println!("Failed to analyse motion frame: {:?}", e);
+            return MOTION_IDLE_INTERVAL;
+        }
+    };
+    let interval = camera_guard
+        .motion_session
+        .as_ref()
+        .map_or(MOTION_IDLE_INTERVAL, |session| {
+            Duration::from_millis(session.config.interval_millis)
+        });
+
+    match check {
+        Ok(MotionCheck::NotRunning) | Ok(MotionCheck::NoMotion) => {}
+        Ok(MotionCheck::CameraModeChanged) => {
+            stop_motion_capture(
+                base_settings,
+                settings,
+                mqtt_client,
+                &mut camera_guard,
+                MotionStopReason::CameraModeChanged,
+            )
+            .await
+            .send_if_err(base_settings, mqtt_client, &settings.camera_topic)
+            .await
+            .unwrap_or_default();
+            return MOTION_IDLE_INTERVAL;
+        }
+        Ok(MotionCheck::Motion(changed_fraction)) => {
+            drop(camera_guard);
+            let base_settings = Arc::clone(base_settings);
+            let settings = Arc::clone(settings);
+            let mqtt_client = Arc::clone(mqtt_client);
+            let camera_service = Arc::clone(camera_service);
+            join_set.lock().await.spawn(async move {
+                let mut camera_guard = camera_service.lock().await;
+                // Session may have been stopped while waiting for the camera
+                if camera_guard.motion_session.is_none() {
+                    return;
+                }
+                capture_motion(
+                    &base_settings,
+                    &settings,
+                    &mqtt_client,
+                    &mut camera_guard,
+                    changed_fraction,
+                )
+                .await
+                .send_if_err(&base_settings, &mqtt_client, &settings.camera_topic)
+                .await
+                .unwrap_or_default();
+            });
+        }
+        Err(e) => println!("Failed to analyse motion frame: {:?}", e),
+    }
+    interval
+}
+
+/// Captures and compares a low resolution frame, records the capture if there is motion
+fn analyse_motion_frame(camera_service: &mut CameraService) -> Result<MotionCheck, anyhow::Error> {
+    let Some(session) = camera_service.motion_session.as_ref() else {
+        return Ok(MotionCheck::NotRunning);
+    };
+    // Pictures go through the still pipeline, so frames are analysed in still mode
+    if camera_service.camera_mode != CameraMode::Still {
+        return Ok(MotionCheck::CameraModeChanged);
+    }
+
+    let analysis_width = session.config.analysis_width;
+    let image = Python::attach(|py| camera_service.capture_motion_frame(py, analysis_width))?;
+    let Some(session) = camera_service.motion_session.as_mut() else {
+        return Ok(MotionCheck::NotRunning);
+    };
+    match session.changed_fraction(&image)? {
+        Some(changed_fraction) if session.should_capture(changed_fraction) => {
+            session.record_capture();
+            Ok(MotionCheck::Motion(changed_fraction))
+        }
+        _ => Ok(MotionCheck::NoMotion),
+    }
+}
+
+/// Publishes the motion event, then takes the picture like a TakePicture request
+async fn capture_motion(
+    base_settings: &BaseSettings,
+    settings: &Settings,
+    mqtt_client: &AsyncClient,
+    camera_service: &mut CameraService,
+    changed_fraction: f32,
+) -> Result<(), anyhow::Error> {
+    let uuid = Uuid::new_v4();
+    let picture_epoch = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64
+        + MOTION_CAPTURE_DELAY_MILLIS;
+    let success_wrapper = SuccessWrapper::success(MotionCaptureResponse::MotionCapture {
+        uuid,
+        picture_epoch,
+        changed_fraction,
+    });
+    publish_motion_response(base_settings, settings, mqtt_client, success_wrapper).await?;
+
+    let request = TakePicture {
+        picture_epoch,
+        uuid,
+        wait_for_convergence: false,
+        convergence_timeout_millis: None,
+        calibration_correction: false,
+        stack_frames: None,
+        stack_sigma_clip: None,
+        detect_markers: false,
+    };
+    take_picture(
+        base_settings,
+        settings,
+        mqtt_client,
+        camera_service,
+        &request,
+        None,
+    )
+    .await
+}
+
+async fn publish_motion_response(
+    base_settings: &BaseSettings,
+    settings: &Settings,
+    mqtt_client: &AsyncClient,
+    success_wrapper: SuccessWrapper<MotionCaptureResponse>,
+) -> Result<(), anyhow::Error> {
+    let response = CameraResponse::MotionCapture {
+        response: success_wrapper,
+    };
+
+    mqtt_client
+        .publish_individual(
+            &settings.camera_topic,
+            &base_settings.pi_zero_id,
+            response.into_bytes()?,
+        )
+        .await?;
+
+    Ok(())
+}
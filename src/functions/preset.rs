@@ -0,0 +1,243 @@
+use crate::camera::{CameraMode, CameraService, ControlPreset};
+use crate::functions::camera::{store_controls, validate_controls};
+use crate::functions::requests::{PresetName, SavePreset, SetControls};
+use crate::functions::responses::{CameraResponse, PresetResponse};
+use crate::settings::{BaseSettings, Settings};
+use crate::utils::{AsyncClientExt, SuccessWrapper};
+use rumqttc::v5::AsyncClient;
+use std::io::ErrorKind;
+use std::path::Path;
+use tokio::fs;
+
+pub const PRESETS_PATH: &str = "presets";
+pub const ACTIVE_PRESET_FILENAME: &str = "preset_active.json";
+const MAX_PRESET_NAME_LENGTH: usize = 64;
+
+pub async fn save_preset(
+    base_settings: &BaseSettings,
+    settings: &Settings,
+    mqtt_client: &AsyncClient,
+    camera_service: &CameraService,
+    request: &SavePreset,
+) -> Result<(), anyhow::Error> {
+    let save_result = async {
+        let path = get_preset_path(&request.name)?;
+        // Current controls are saved, if not given
+        let preset = ControlPreset {
+            still_controls: request
+                .still_controls
+                .clone()
+                .or_else(|| camera_service.still_controls.clone()),
+            video_controls: request
+                .video_controls
+                .clone()
+                .or_else(|| camera_service.video_controls.clone()),
+        };
+
+        fs::create_dir_all(PRESETS_PATH).await?;
+        fs::write(path, serde_json::to_string(&preset)?).await?;
+
+        Ok(PresetResponse::PresetSaved {
+            name: request.name.clone(),
+            preset: Box::new(preset),
+        })
+    }
+    .await;
+
+    publish_preset_response(
+        base_settings,
+        settings,
+        mqtt_client,
+        Some(&request.name),
+        save_result,
+    )
+    .await
+}
+
+pub async fn apply_preset(
+    base_settings: &BaseSettings,
+    settings: &Settings,
+    mqtt_client: &AsyncClient,
+    camera_service: &mut CameraService,
+    request: &PresetName,
+) -> Result<(), anyhow::Error> {
+    let apply_result = async {
+        let path = get_preset_path(&request.name)?;
+        let json = match fs::read_to_string(&path).await {
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                anyhow::bail!("Preset {} does not exist", request.name)
+            }
+            result => result?,
+        };
+        let preset: ControlPreset = serde_json::from_str(&json)?;
+
+        let modes = [
+            (CameraMode::Still, &preset.still_controls),
+            (CameraMode::Video, &preset.video_controls),
+        ];
+        // Both modes are validated first, so an invalid preset changes nothing
+        let mut validated = Vec::new();
+        for (camera_mode, camera_controls) in modes {
+            if let Some(camera_controls) = camera_controls {
+                let controls = SetControls {
+                    camera_mode,
+                    camera_controls: camera_controls.clone(),
+                    clamp: false,
+                };
+                let (camera_controls, _) = validate_controls(camera_service, &controls)?;
+                validated.push((controls.camera_mode, camera_controls));
+            }
+        }
+        for (camera_mode, camera_controls) in &validated {
+            store_controls(camera_service, camera_mode, camera_controls).await?;
+        }
+
+        // Saved, so status is correct after restart
+        camera_service.active_preset = Some(request.name.clone());
+        fs::write(
+            ACTIVE_PRESET_FILENAME,
+            serde_json::to_string(&request.name)?,
+        )
+        .await?;
+
+        Ok(PresetResponse::PresetApplied {
+            name: request.name.clone(),
+            preset: Box::new(preset),
+        })
+    }
+    .await;
+
+    publish_preset_response(
+        base_settings,
+        settings,
+        mqtt_client,
+        Some(&request.name),
+        apply_result,
+    )
+    .await
+}
+
+pub async fn delete_preset(
+    base_settings: &BaseSettings,
+    settings: &Settings,
+    mqtt_client: &AsyncClient,
+    camera_service: &mut CameraService,
+    request: &PresetName,
+) -> Result<(), anyhow::Error> {
+    let delete_result = async {
+        let path = get_preset_path(&request.name)?;
+        match fs::remove_file(&path).await {
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                anyhow::bail!("Preset {} does not exist", request.name)
+            }
+            result => result?,
+        };
+
+        // Controls stay, but they no longer match a preset
+        if camera_service.active_preset.as_ref() == Some(&request.name) {
+            clear_active_preset(camera_service).await?;
+        }
+
+        Ok(PresetResponse::PresetDeleted {
+            name: request.name.clone(),
+        })
+    }
+    .await;
+
+    publish_preset_response(
+        base_settings,
+        settings,
+        mqtt_client,
+        Some(&request.name),
+        delete_result,
+    )
+    .await
+}
+
+pub async fn list_presets(
+    base_settings: &BaseSettings,
+    settings: &Settings,
+    mqtt_client: &AsyncClient,
+    camera_service: &CameraService,
+) -> Result<(), anyhow::Error> {
+    let list_result = async {
+        let mut names = Vec::new();
+        if Path::new(PRESETS_PATH).exists() {
+            let mut entries = fs::read_dir(PRESETS_PATH).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                if path
+                    .extension()
+                    .is_some_and(|extension| extension == "json")
+                    && let Some(name) = path.file_stem().and_then(|stem| stem.to_str())
+                {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        names.sort();
+
+        Ok(PresetResponse::Presets {
+            names,
+            active_preset: camera_service.active_preset.clone(),
+        })
+    }
+    .await;
+
+    publish_preset_response(base_settings, settings, mqtt_client, None, list_result).await
+}
+
+/// Forgets the active preset, when controls are changed
+pub async fn clear_active_preset(camera_service: &mut CameraService) -> Result<(), anyhow::Error> {
+    camera_service.active_preset = None;
+    match fs::remove_file(ACTIVE_PRESET_FILENAME).await {
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        result => Ok(result?),
+    }
+}
+
+/// Path of the preset file, errors if the name can't be used as a filename
+fn get_preset_path(name: &str) -> Result<String, anyhow::Error> {
+    let valid = !name.is_empty()
+        && name.len() <= MAX_PRESET_NAME_LENGTH
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        anyhow::bail!(
+            "Invalid preset name {:?}, use up to {} letters, digits, '-' and '_'",
+            name,
+            MAX_PRESET_NAME_LENGTH
+        );
+    }
+    Ok(format!("{}/{}.json", PRESETS_PATH, name))
+}
+
+async fn publish_preset_response(
+    base_settings: &BaseSettings,
+    settings: &Settings,
+    mqtt_client: &AsyncClient,
+    name: Option<&String>,
+    result: Result<PresetResponse, anyhow::Error>,
+) -> Result<(), anyhow::Error> {
+    let success_wrapper = match result {
+        Ok(response) => SuccessWrapper::success(response),
+        Err(e) => SuccessWrapper::failure(PresetResponse::Failed {
+            name: name.cloned(),
+            message: e.to_string(),
+        }),
+    };
+    let response = CameraResponse::Preset {
+        response: success_wrapper,
+    };
+
+    mqtt_client
+        .publish_individual(
+            &settings.camera_topic,
+            &base_settings.pi_zero_id,
+            response.into_bytes()?,
+        )
+        .await?;
+
+    Ok(())
+}
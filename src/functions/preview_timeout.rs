@@ -0,0 +1,79 @@
+use crate::camera::{CameraMode, CameraService, PreviewStopReason};
+use crate::functions::blocking::run_blocking;
+use crate::functions::camera::stop_preview;
+use crate::settings::{BaseSettings, Settings};
+use crate::utils::ResultExt;
+use pyo3::Python;
+use rumqttc::v5::AsyncClient;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+const PREVIEW_TIMEOUT_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Stops preview and switches back to still mode when the preview session times out.
+/// Runs forever, spawned next to the MQTT loop.
+pub async fn watch_preview_timeout(
+    base_settings: Arc<BaseSettings>,
+    settings: Arc<Settings>,
+    mqtt_client: Arc<AsyncClient>,
+    camera_service: Arc<Mutex<CameraService>>,
+) {
+    let mut interval = tokio::time::interval(PREVIEW_TIMEOUT_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        let (mut camera_guard, reason) =
+            match run_blocking(&camera_service, check_preview_timeout).await {
+                Ok(checked) => checked,
+                Err(e) => {
+                    println!("Failed to check preview timeout: {:?}", e);
+                    continue;
+                }
+            };
+        let Some(reason) = reason else {
+            continue;
+        };
+
+        println!("Stopping preview: {:?}", reason);
+        stop_preview(
+            &base_settings,
+            &settings,
+            &mqtt_client,
+            &mut camera_guard,
+            reason,
+        )
+        .await
+        .send_if_err(&base_settings, &mqtt_client, &settings.camera_topic)
+        .await
+        .unwrap_or_default();
+    }
+}
+
+/// Updates stream client activity, returns why the preview should be stopped, if it should
+fn check_preview_timeout(camera_service: &mut CameraService) -> Option<PreviewStopReason> {
+    if camera_service.camera_mode != CameraMode::Video {
+        return None;
+    }
+
+    let stream_clients = Python::attach(|py| camera_service.get_stream_clients(py));
+    match stream_clients {
+        Ok(0) => {}
+        Ok(_) => {
+            if let Some(session) = camera_service.preview_session.as_mut() {
+                session.last_client = Instant::now();
+            }
+        }
+        // Do not count as idle, if clients could not be checked
+        Err(e) => {
+            println!("Failed to get stream clients: {:?}", e);
+            if let Some(session) = camera_service.preview_session.as_mut() {
+                session.last_client = Instant::now();
+            }
+        }
+    }
+
+    let session = camera_service.preview_session.as_ref()?;
+    let preview_config = camera_service.preview_config.as_ref()?;
+    session.expired(preview_config)
+}
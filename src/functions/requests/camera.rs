@@ -1,5 +1,10 @@
-use crate::camera::{CameraControls, CameraMode};
+use crate::camera::{
+    CalibrationFrameKind, CameraControls, CameraMode, ClipFormat, ColourSpace, EncoderQuality,
+    FocusAssistConfig, MotionConfig, Orientation, OverlayConfig, PixelFormat, Point, Resolution,
+    Roi,
+};
 use serde::Deserialize;
+use serde_json::{Map, Value};
 use uuid::Uuid;
 
 #[derive(Deserialize, Debug)]
@@ -7,6 +12,21 @@ use uuid::Uuid;
 pub struct TakePicture {
     pub picture_epoch: u64,
     pub uuid: Uuid,
+    /// Poll frame metadata until AE/AWB converge, at most until the picture time
+    #[serde(default)]
+    pub wait_for_convergence: bool,
+    /// Shorter deadline for the convergence wait
+    pub convergence_timeout_millis: Option<u64>,
+    /// Subtract the dark frame and apply the flat field of the still configuration
+    #[serde(default)]
+    pub calibration_correction: bool,
+    /// Consecutive frames averaged into the picture, starting at the picture time
+    pub stack_frames: Option<u32>,
+    /// Leave out values further than this many standard deviations when stacking
+    pub stack_sigma_clip: Option<f32>,
+    /// Detect fiducial markers in the picture and add them to the metadata
+    #[serde(default)]
+    pub detect_markers: bool,
 }
 
 #[derive(Deserialize, Debug)]
@@ -15,11 +35,155 @@ pub struct SendPicture {
     pub uuid: Uuid,
 }
 
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordClip {
+    /// Wall clock time of the first frame, milliseconds
+    pub start_epoch: u64,
+    pub duration_millis: u64,
+    pub resolution: Resolution,
+    pub frame_rate: f32,
+    pub format: Option<ClipFormat>,
+    pub uuid: Uuid,
+}
+
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct SetControls {
     pub camera_mode: CameraMode,
     pub camera_controls: CameraControls,
+    /// Clamp values outside of camera limits, instead of rejecting the controls
+    #[serde(default)]
+    pub clamp: bool,
+}
+
+/// Controls merged into the stored controls, null resets a control to the camera default.
+/// Controls of the inactive mode are reset when the mode is entered.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PatchControls {
+    pub camera_mode: CameraMode,
+    pub camera_controls: Map<String, Value>,
+    /// Clamp values outside of camera limits, instead of rejecting the controls
+    #[serde(default)]
+    pub clamp: bool,
+}
+
+/// Preview stream parameters, unset values fall back to defaults
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct StartPreview {
+    pub resolution: Option<Resolution>,
+    pub frame_rate: Option<f32>,
+    pub quality: Option<EncoderQuality>,
+    pub port: Option<u16>,
+    /// Overrides the timeout from settings, 0 to disable
+    pub timeout_minutes: Option<u64>,
+    /// Overrides the idle timeout from settings, 0 to disable
+    pub idle_timeout_minutes: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct GetPreviewFrame {
+    /// Upload the frame to the server instead of publishing it over MQTT
+    #[serde(default)]
+    pub upload: bool,
+}
+
+/// Capture config of a camera mode, unset values fall back to defaults
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SetCaptureConfig {
+    pub camera_mode: CameraMode,
+    pub resolution: Option<Resolution>,
+    pub format: Option<PixelFormat>,
+    /// Index into the sensor mode list, chosen by resolution if unset
+    pub sensor_mode: Option<usize>,
+    pub buffer_count: Option<u32>,
+    pub colour_space: Option<ColourSpace>,
+}
+
+/// Controls to save as a named preset, current controls are saved for unset modes
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SavePreset {
+    pub name: String,
+    pub still_controls: Option<CameraControls>,
+    pub video_controls: Option<CameraControls>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PresetName {
+    pub name: String,
+}
+
+/// Autofocus cycle in the current camera mode
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AutoFocus {
+    pub timeout_millis: Option<u64>,
+    /// Store the focused lens position in controls of the current camera mode with manual focus
+    #[serde(default)]
+    pub lock: bool,
+}
+
+/// Auto exposure and white balance measurement in the current camera mode
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct MeasureExposure {
+    pub timeout_millis: Option<u64>,
+    /// Store the measured values as manual controls of the current camera mode
+    #[serde(default)]
+    pub lock: bool,
+}
+
+/// Grey card white balance, the region should only contain the card
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CalibrateWhiteBalance {
+    /// Whole frame if unset
+    pub roi: Option<Roi>,
+}
+
+/// Colour checker calibration of the still pipeline
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CalibrateColour {
+    /// Centres of the dark skin, bluish green, black and white patches, normalized to 0..1 of
+    /// the frame. The checker is detected if unset.
+    pub corners: Option<[Point; 4]>,
+    /// Apply the matrix to captures, true if unset
+    pub enable: Option<bool>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SetColourCorrection {
+    pub enabled: bool,
+}
+
+/// Averages frames into a dark frame or flat field for the current still configuration
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CaptureCalibrationFrames {
+    pub kind: CalibrationFrameKind,
+    pub frames: Option<u32>,
+}
+
+/// Finds checkerboard corners in the next still frame and keeps them as a view for SolveIntrinsics
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DetectCalibrationPattern {
+    /// Inner corners along a row
+    pub columns: usize,
+    /// Inner corners along a column
+    pub rows: usize,
+    /// Discard the views collected so far. Views of another pattern or still
+    /// configuration are always discarded.
+    #[serde(default)]
+    pub reset: bool,
 }
 
 #[derive(Deserialize, Debug)]
@@ -27,10 +191,38 @@ pub struct SetControls {
 pub enum CameraRequest {
     TakePicture(TakePicture),
     SendPicture(SendPicture),
+    RecordClip(RecordClip),
+    SendClip(SendPicture),
+    GetStatistics(SendPicture),
     GetSyncStatus,
-    SetControls(SetControls),
+    SetControls(Box<SetControls>),
+    PatchControls(PatchControls),
     GetControls(CameraMode),
+    GetEffectiveControls(CameraMode),
+    AutoFocus(AutoFocus),
+    MeasureExposure(MeasureExposure),
+    CalibrateWhiteBalance(CalibrateWhiteBalance),
+    CalibrateColour(CalibrateColour),
+    SetColourCorrection(SetColourCorrection),
+    CaptureCalibrationFrames(CaptureCalibrationFrames),
+    DetectCalibrationPattern(DetectCalibrationPattern),
+    SolveIntrinsics,
+    DetectMarkers,
+    StartMotionCapture(MotionConfig),
+    StopMotionCapture,
+    StartFocusAssist(FocusAssistConfig),
+    StopFocusAssist,
     GetControlLimits,
-    StartPreview,
+    StartPreview(StartPreview),
     StopPreview,
+    GetPreviewFrame(GetPreviewFrame),
+    SetPreviewOverlay(OverlayConfig),
+    SetCaptureConfig(SetCaptureConfig),
+    GetCaptureConfig(CameraMode),
+    ListSensorModes,
+    SetOrientation(Orientation),
+    SavePreset(Box<SavePreset>),
+    ApplyPreset(PresetName),
+    ListPresets,
+    DeletePreset(PresetName),
 }
\ No newline at end of file
@@ -1,3 +1,11 @@
+use crate::camera::{
+    AfState, CalibrationFrameInfo, CalibrationFrameKind, CalibrationView, CameraControls,
+    CameraIntrinsics, CameraMode, CaptureConfig, ColourChecker, ColourCorrection, ColourGain,
+    ControlLimitViolation, ControlPreset, Convergence, EffectiveControls, FocusAssistConfig,
+    FocusAssistStopReason, FocusScore, FrameSource, IspStatistics, Marker, MotionConfig,
+    MotionStopReason, Orientation, OverlayConfig, PreviewConfig, PreviewStopReason, Roi,
+    SensorMode,
+};
 use crate::utils::SuccessWrapper;
 use bytes::Bytes;
 use serde::Serialize;
@@ -12,9 +20,81 @@ pub enum CameraResponse {
     SendPicture {
         response: SuccessWrapper<SendPictureResponse>,
     },
+    RecordClip {
+        response: SuccessWrapper<RecordClipResponse>,
+    },
+    SendClip {
+        response: SuccessWrapper<SendClipResponse>,
+    },
     SyncStatus {
         response: SuccessWrapper<SyncStatusResponse>,
-    }
+    },
+    StartPreview {
+        response: SuccessWrapper<StartPreviewResponse>,
+    },
+    StopPreview {
+        response: SuccessWrapper<StopPreviewResponse>,
+    },
+    PreviewFrame {
+        response: SuccessWrapper<PreviewFrameResponse>,
+    },
+    PreviewOverlay {
+        response: SuccessWrapper<PreviewOverlayResponse>,
+    },
+    CaptureConfig {
+        response: SuccessWrapper<CaptureConfigResponse>,
+    },
+    SensorModes {
+        response: SuccessWrapper<Vec<SensorMode>>,
+    },
+    Orientation {
+        response: SuccessWrapper<OrientationResponse>,
+    },
+    Preset {
+        response: SuccessWrapper<PresetResponse>,
+    },
+    SetControls {
+        response: SuccessWrapper<SetControlsResponse>,
+    },
+    PatchControls {
+        response: SuccessWrapper<PatchControlsResponse>,
+    },
+    EffectiveControls {
+        response: SuccessWrapper<EffectiveControlsResponse>,
+    },
+    AutoFocus {
+        response: SuccessWrapper<AutoFocusResponse>,
+    },
+    MeasureExposure {
+        response: SuccessWrapper<MeasureExposureResponse>,
+    },
+    WhiteBalance {
+        response: SuccessWrapper<WhiteBalanceResponse>,
+    },
+    ColourCorrection {
+        response: SuccessWrapper<ColourCorrectionResponse>,
+    },
+    CalibrationFrames {
+        response: SuccessWrapper<CalibrationFramesResponse>,
+    },
+    CalibrationPattern {
+        response: SuccessWrapper<CalibrationPatternResponse>,
+    },
+    Intrinsics {
+        response: SuccessWrapper<IntrinsicsResponse>,
+    },
+    Markers {
+        response: SuccessWrapper<MarkersResponse>,
+    },
+    MotionCapture {
+        response: SuccessWrapper<MotionCaptureResponse>,
+    },
+    FocusAssist {
+        response: SuccessWrapper<FocusAssistResponse>,
+    },
+    Statistics {
+        response: SuccessWrapper<StatisticsResponse>,
+    },
 }
 
 #[derive(Serialize, Debug)]
@@ -32,6 +112,8 @@ pub enum TakePictureResponse {
         monotonic_time: i64,
         message_received_nanos: Option<i64>,
         wait_time_nanos: i64,
+        /// None if not requested
+        convergence: Option<Convergence>,
     },
     PictureFailedToTake {
         uuid: Uuid,
@@ -62,6 +144,55 @@ pub enum SendPictureResponse {
     PictureFailedToSend { uuid: Uuid, message: String },
 }
 
+#[derive(Serialize, Debug)]
+#[serde(tag = "type")]
+#[serde(rename_all_fields = "camelCase")]
+pub enum RecordClipResponse {
+    ClipFailedToSchedule {
+        uuid: Uuid,
+        message: String,
+        message_received_nanos: Option<i64>,
+        wait_time_nanos: i64,
+    },
+    ClipRecorded {
+        uuid: Uuid,
+        monotonic_time: i64,
+        frame_count: usize,
+        /// Frame rate times duration
+        expected_frame_count: usize,
+        dropped_frames: usize,
+        message_received_nanos: Option<i64>,
+        wait_time_nanos: i64,
+    },
+    ClipFailedToRecord {
+        uuid: Uuid,
+        message: String,
+        message_received_nanos: Option<i64>,
+        wait_time_nanos: i64,
+    },
+    ClipSavedOnDevice {
+        uuid: Uuid,
+    },
+    ClipFailedToSave {
+        uuid: Uuid,
+        message: String,
+    },
+    Failed {
+        uuid: Uuid,
+        message: String,
+    },
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type")]
+#[serde(rename_all_fields = "camelCase")]
+pub enum SendClipResponse {
+    Failed { uuid: Uuid, message: String },
+    ClipFailedToRead { uuid: Uuid, message: String },
+    ClipSent { uuid: Uuid },
+    ClipFailedToSend { uuid: Uuid, message: String },
+}
+
 #[derive(Serialize, Debug)]
 #[serde(tag = "type")]
 #[serde(rename_all_fields = "camelCase")]
@@ -70,8 +201,376 @@ pub enum SyncStatusResponse {
     Success { sync_ready: bool, sync_timing: i64 },
 }
 
+#[derive(Serialize, Debug)]
+#[serde(tag = "type")]
+#[serde(rename_all_fields = "camelCase")]
+pub enum StartPreviewResponse {
+    Failed { message: String },
+    PreviewStarted { preview_config: PreviewConfig },
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type")]
+#[serde(rename_all_fields = "camelCase")]
+pub enum StopPreviewResponse {
+    Failed {
+        reason: PreviewStopReason,
+        message: String,
+    },
+    PreviewStopped {
+        reason: PreviewStopReason,
+        duration_seconds: Option<u64>,
+    },
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type")]
+#[serde(rename_all_fields = "camelCase")]
+pub enum PreviewFrameResponse {
+    Failed {
+        message: String,
+    },
+    FrameUploaded {
+        width: u32,
+        height: u32,
+        source: FrameSource,
+    },
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type")]
+#[serde(rename_all_fields = "camelCase")]
+pub enum PreviewOverlayResponse {
+    Failed {
+        message: String,
+    },
+    PreviewOverlay {
+        /// None if the overlay is disabled
+        overlay_config: Option<OverlayConfig>,
+    },
+}
+
+/// Header of a preview frame published over MQTT.
+/// Payload is the header json, a newline and the jpeg bytes.
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename = "PreviewFrame")]
+#[serde(rename_all = "camelCase")]
+pub struct PreviewFrameHeader {
+    pub width: u32,
+    pub height: u32,
+    pub source: FrameSource,
+    pub size: usize,
+}
+
 impl CameraResponse {
     pub fn into_bytes(self) -> Result<Bytes, serde_json::error::Error> {
         serde_json::to_string(&self).map(|s| s.into())
     }
 }
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type")]
+#[serde(rename_all_fields = "camelCase")]
+pub enum CaptureConfigResponse {
+    Failed {
+        camera_mode: CameraMode,
+        message: String,
+    },
+    CaptureConfig {
+        camera_mode: CameraMode,
+        capture_config: CaptureConfig,
+        /// True if the camera is running with the config,
+        /// false if it is used the next time the camera mode is configured
+        applied: bool,
+    },
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type")]
+#[serde(rename_all_fields = "camelCase")]
+pub enum OrientationResponse {
+    Failed {
+        message: String,
+    },
+    Orientation {
+        orientation: Orientation,
+        /// False if the orientation is used the next time the camera is configured
+        applied: bool,
+    },
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type")]
+#[serde(rename_all_fields = "camelCase")]
+pub enum PresetResponse {
+    Failed {
+        name: Option<String>,
+        message: String,
+    },
+    PresetSaved {
+        name: String,
+        preset: Box<ControlPreset>,
+    },
+    PresetApplied {
+        name: String,
+        preset: Box<ControlPreset>,
+    },
+    PresetDeleted {
+        name: String,
+    },
+    Presets {
+        names: Vec<String>,
+        active_preset: Option<String>,
+    },
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type")]
+#[serde(rename_all_fields = "camelCase")]
+pub enum SetControlsResponse {
+    Failed {
+        camera_mode: CameraMode,
+        message: String,
+    },
+    /// Controls outside of camera limits, nothing was saved
+    InvalidControls {
+        camera_mode: CameraMode,
+        violations: Vec<ControlLimitViolation>,
+    },
+    ControlsSet {
+        camera_mode: CameraMode,
+        camera_controls: Box<CameraControls>,
+        /// Values that were clamped to camera limits
+        clamped: Vec<ControlLimitViolation>,
+    },
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type")]
+#[serde(rename_all_fields = "camelCase")]
+pub enum PatchControlsResponse {
+    Failed {
+        camera_mode: CameraMode,
+        message: String,
+    },
+    /// Controls outside of camera limits, nothing was saved
+    InvalidControls {
+        camera_mode: CameraMode,
+        violations: Vec<ControlLimitViolation>,
+    },
+    ControlsPatched {
+        camera_mode: CameraMode,
+        /// Stored controls after the patch
+        stored_controls: Box<CameraControls>,
+        /// Controls of the first frame after applying, None if the camera is not in the mode
+        effective_controls: Option<EffectiveControls>,
+        /// Values that were clamped to camera limits
+        clamped: Vec<ControlLimitViolation>,
+    },
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type")]
+#[serde(rename_all_fields = "camelCase")]
+pub enum EffectiveControlsResponse {
+    Failed {
+        camera_mode: CameraMode,
+        message: String,
+    },
+    EffectiveControls {
+        camera_mode: CameraMode,
+        stored_controls: Option<Box<CameraControls>>,
+        /// None if the camera is not in the requested mode
+        effective_controls: Option<EffectiveControls>,
+    },
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type")]
+#[serde(rename_all_fields = "camelCase")]
+pub enum AutoFocusResponse {
+    Failed {
+        message: String,
+    },
+    AutoFocus {
+        /// None if not reported by the camera
+        af_state: Option<AfState>,
+        lens_position: Option<f32>,
+        /// Lens position was stored in still controls
+        locked: bool,
+    },
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type")]
+#[serde(rename_all_fields = "camelCase")]
+pub enum MeasureExposureResponse {
+    Failed {
+        camera_mode: CameraMode,
+        message: String,
+    },
+    ExposureMeasured {
+        camera_mode: CameraMode,
+        exposure_time: Option<i64>,
+        analogue_gain: Option<f32>,
+        colour_gains: Option<ColourGain>,
+        lux: Option<f32>,
+        /// AE/AWB converged before the timeout
+        converged: bool,
+        /// Values were stored as manual controls
+        locked: bool,
+    },
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type")]
+#[serde(rename_all_fields = "camelCase")]
+pub enum WhiteBalanceResponse {
+    Failed {
+        message: String,
+    },
+    WhiteBalanceCalibrated {
+        roi: Roi,
+        /// Mean red, green and blue before calibration
+        mean_before: [f32; 3],
+        /// Mean red, green and blue with the new gains
+        mean_after: [f32; 3],
+        /// Largest relative difference of red and blue from green in linear light after calibration
+        residual: f32,
+        camera_controls: Box<CameraControls>,
+        /// Values that were clamped to camera limits
+        clamped: Vec<ControlLimitViolation>,
+    },
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type")]
+#[serde(rename_all_fields = "camelCase")]
+pub enum ColourCorrectionResponse {
+    Failed {
+        message: String,
+    },
+    ColourCalibrated {
+        colour_correction: ColourCorrection,
+        /// Patches the matrix was fitted to
+        checker: ColourChecker,
+        /// CIE76 colour difference of each patch after correction
+        patch_errors: Vec<f32>,
+    },
+    ColourCorrection {
+        colour_correction: ColourCorrection,
+    },
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type")]
+#[serde(rename_all_fields = "camelCase")]
+pub enum CalibrationFramesResponse {
+    Failed {
+        kind: CalibrationFrameKind,
+        message: String,
+    },
+    CalibrationFramesCaptured {
+        /// Still configuration and transform the frames are used for
+        key: String,
+        info: CalibrationFrameInfo,
+    },
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type")]
+#[serde(rename_all_fields = "camelCase")]
+pub enum CalibrationPatternResponse {
+    Failed {
+        message: String,
+    },
+    PatternDetected {
+        view: CalibrationView,
+        /// Views collected for SolveIntrinsics, including this one
+        views: usize,
+    },
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type")]
+#[serde(rename_all_fields = "camelCase")]
+pub enum IntrinsicsResponse {
+    Failed { message: String },
+    IntrinsicsSolved { intrinsics: CameraIntrinsics },
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type")]
+#[serde(rename_all_fields = "camelCase")]
+pub enum MarkersResponse {
+    Failed {
+        camera_mode: CameraMode,
+        message: String,
+    },
+    MarkersDetected {
+        camera_mode: CameraMode,
+        /// Corners are in the frame after the orientation's quarter turn, like picture markers
+        orientation: Orientation,
+        /// Size of the frame the corners are in
+        width: usize,
+        height: usize,
+        markers: Vec<Marker>,
+    },
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type")]
+#[serde(rename_all_fields = "camelCase")]
+pub enum MotionCaptureResponse {
+    Failed {
+        message: String,
+    },
+    MotionCaptureStarted {
+        config: MotionConfig,
+    },
+    MotionCaptureStopped {
+        reason: MotionStopReason,
+        /// Pictures taken during the session
+        captures: u32,
+        duration_seconds: u64,
+    },
+    /// Motion was detected, the picture is taken with this uuid
+    MotionCapture {
+        uuid: Uuid,
+        picture_epoch: u64,
+        changed_fraction: f32,
+    },
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type")]
+#[serde(rename_all_fields = "camelCase")]
+pub enum FocusAssistResponse {
+    Failed {
+        message: String,
+    },
+    FocusAssistStarted {
+        config: FocusAssistConfig,
+    },
+    FocusAssistStopped {
+        reason: FocusAssistStopReason,
+        /// Score of the last measured frame, None if the preview was already stopped
+        last_score: Option<FocusScore>,
+    },
+    /// Published every publish interval while running
+    FocusScore {
+        score: FocusScore,
+    },
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type")]
+#[serde(rename_all_fields = "camelCase")]
+pub enum StatisticsResponse {
+    /// Picture does not exist or was taken without StatsOutputEnable
+    StatisticsFailedToRead { uuid: Uuid, message: String },
+    Statistics {
+        uuid: Uuid,
+        statistics: IspStatistics,
+    },
+}
@@ -1,6 +1,9 @@
-use crate::camera::{CameraMode, CameraService};
+use crate::camera::{
+    CameraIntrinsics, CameraMode, CameraService, CaptureConfig, ColourCorrection,
+    FocusAssistConfig, MotionConfig, Orientation, PreviewConfig,
+};
 use crate::settings::{BaseSettings, Settings};
-use crate::utils::{AsyncClientExt, SuccessWrapper, execute_command};
+use crate::utils::{execute_command, AsyncClientExt, SuccessWrapper};
 use rumqttc::v5::AsyncClient;
 use serde::Serialize;
 
@@ -15,11 +18,32 @@ pub async fn handle_status(
         .ok()
         .map(|v| v.trim().to_string());
     let camera_mode = (&camera_service.camera_mode).clone();
+    let preview_config = camera_service.preview_config.clone();
+    let still_capture_config = camera_service.still_capture_config.clone();
+    let video_capture_config = camera_service.video_capture_config.clone();
+    let orientation = camera_service.orientation;
+    let active_preset = camera_service.active_preset.clone();
+    let colour_correction = camera_service.colour_correction.clone();
+    let intrinsics = camera_service.intrinsics.clone();
+    let motion_capture = camera_service
+        .motion_session
+        .as_ref()
+        .map(|session| session.config.clone());
+    let focus_assist = camera_service.focus_assist.clone();
 
     let status = Status {
         version,
         ip_address,
         camera_mode,
+        preview_config,
+        still_capture_config,
+        video_capture_config,
+        orientation,
+        active_preset,
+        colour_correction,
+        intrinsics,
+        motion_capture,
+        focus_assist,
     };
 
     let status_msg = SuccessWrapper::success(status);
@@ -41,4 +65,13 @@ pub struct Status {
     version: String,
     ip_address: Option<String>,
     camera_mode: CameraMode,
+    preview_config: Option<PreviewConfig>,
+    still_capture_config: CaptureConfig,
+    video_capture_config: CaptureConfig,
+    orientation: Orientation,
+    active_preset: Option<String>,
+    colour_correction: Option<ColourCorrection>,
+    intrinsics: Option<CameraIntrinsics>,
+    motion_capture: Option<MotionConfig>,
+    focus_assist: Option<FocusAssistConfig>,
 }
@@ -15,7 +15,10 @@ mod updater;
 /// Misc
 mod utils;
 
-use crate::functions::handle_notification;
+use crate::functions::{
+    cancel_motion_capture, handle_notification, serve_live_view, watch_focus_assist, watch_motion,
+    watch_preview_timeout,
+};
 use crate::startup::{critical_startup, startup};
 use crate::updater::restart;
 use crate::utils::{AsyncClientExt, PublishExt, SuccessWrapper};
@@ -51,8 +54,10 @@ async fn main() {
     let camera_service = Arc::new(Mutex::new(camera_service));
     let current_exe = Arc::new(current_exe);
 
+    // Shared with background loops, so tasks they start can be cancelled too
+    let join_set = Arc::new(Mutex::new(JoinSet::new()));
+
     let mqtt_loop = async {
-        let mut join_set = JoinSet::new();
         loop {
             // Restart, if needed
             if should_restart.load(Ordering::Relaxed) {
@@ -62,10 +67,13 @@ async fn main() {
             }
 
             // Clean up tasks
-            while let Some(res) = join_set.try_join_next() {
-                match res {
-                    Ok(_) => { /* task completed successfully, cleaned up */ }
-                    Err(e) => eprintln!("Task failed: {:?}", e),
+            {
+                let mut join_set = join_set.lock().await;
+                while let Some(res) = join_set.try_join_next() {
+                    match res {
+                        Ok(_) => { /* task completed successfully, cleaned up */ }
+                        Err(e) => eprintln!("Task failed: {:?}", e),
+                    }
                 }
             }
 
@@ -105,22 +113,45 @@ async fn main() {
                     println!("Received payload: {:?}", &p.payload);
 
                     if p.topic_matches_pi(&settings.cancel_topic, &base_settings.pi_zero_id) {
-                        let task_count = join_set.len();
-                        join_set.shutdown().await;
-                        let cancelled_tasks = task_count - join_set.len();
-                        println!("Cancelled {} tasks", cancelled_tasks);
-
-                        let success_wrapper = SuccessWrapper::success(cancelled_tasks);
-                        let json = serde_json::to_string(&success_wrapper).unwrap();
-
-                        mqtt_client
-                            .publish_individual(
-                                &settings.cancel_topic,
-                                &base_settings.pi_zero_id,
-                                json,
+                        let mut cancelled_tasks = {
+                            let mut join_set = join_set.lock().await;
+                            let task_count = join_set.len();
+                            join_set.shutdown().await;
+                            task_count - join_set.len()
+                        };
+
+                        // Motion capture session needs the camera, do not wait for it here
+                        let base_settings = Arc::clone(&base_settings);
+                        let settings = Arc::clone(&settings);
+                        let mqtt_client = Arc::clone(&mqtt_client);
+                        let camera_service = Arc::clone(&camera_service);
+                        tokio::spawn(async move {
+                            let mut camera_guard = camera_service.lock().await;
+                            if cancel_motion_capture(
+                                &base_settings,
+                                &settings,
+                                &mqtt_client,
+                                camera_guard.deref_mut(),
                             )
                             .await
-                            .unwrap_or_default();
+                            {
+                                cancelled_tasks += 1;
+                            }
+                            drop(camera_guard);
+                            println!("Cancelled {} tasks", cancelled_tasks);
+
+                            let success_wrapper = SuccessWrapper::success(cancelled_tasks);
+                            let json = serde_json::to_string(&success_wrapper).unwrap();
+
+                            mqtt_client
+                                .publish_individual(
+                                    &settings.cancel_topic,
+                                    &base_settings.pi_zero_id,
+                                    json,
+                                )
+                                .await
+                                .unwrap_or_default();
+                        });
                     } else {
                         // Reference counting
                         let base_settings = Arc::clone(&base_settings);
@@ -131,7 +162,7 @@ async fn main() {
                         let camera_service = Arc::clone(&camera_service);
                         let p = p.clone();
                         // Spawn task
-                        join_set.spawn(async move {
+                        join_set.lock().await.spawn(async move {
                             let mut camera_guard = camera_service.lock().await;
 
                             handle_notification(
@@ -162,8 +193,37 @@ async fn main() {
         println!("Received CTRL+C, shutting down...");
     };
 
+    // Automatic preview shutdown, spawned so it cannot hold up the MQTT loop
+    tokio::spawn(watch_preview_timeout(
+        Arc::clone(&base_settings),
+        Arc::clone(&settings),
+        Arc::clone(&mqtt_client),
+        Arc::clone(&camera_service),
+    ));
+
+    // Motion triggered capture, pictures are taken in the MQTT loop's tasks
+    tokio::spawn(watch_motion(
+        Arc::clone(&base_settings),
+        Arc::clone(&settings),
+        Arc::clone(&mqtt_client),
+        Arc::clone(&camera_service),
+        Arc::clone(&join_set),
+    ));
+
+    // Focus assist scores
+    tokio::spawn(watch_focus_assist(
+        Arc::clone(&base_settings),
+        Arc::clone(&settings),
+        Arc::clone(&mqtt_client),
+        Arc::clone(&camera_service),
+    ));
+
+    // WebSocket live view
+    let live_view = serve_live_view(Arc::clone(&settings), Arc::clone(&camera_service));
+
     tokio::select! {
         _ = mqtt_loop => {},
+        _ = live_view => {},
         _ = ctrl_c => {
             std::process::exit(1);
         },
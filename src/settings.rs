@@ -30,4 +30,31 @@ pub struct Settings {
     pub status_topic: String,
     /// MQTT topic for cancelling tasks
     pub cancel_topic: String,
+    /// Preview is stopped after this many minutes, 0 to disable
+    #[serde(default = "default_preview_timeout_minutes")]
+    pub preview_timeout_minutes: u64,
+    /// Preview is stopped after this many minutes without stream clients, 0 to disable
+    #[serde(default = "default_preview_idle_timeout_minutes")]
+    pub preview_idle_timeout_minutes: u64,
+    /// Port for the live view WebSocket server, live view is disabled if unset
+    #[serde(default)]
+    pub live_view_port: Option<u16>,
+    /// Address the live view WebSocket server binds to
+    #[serde(default = "default_live_view_address")]
+    pub live_view_address: String,
+    /// Token live view clients must send when connecting, required to enable live view
+    #[serde(default)]
+    pub live_view_token: Option<String>,
+}
+
+fn default_preview_timeout_minutes() -> u64 {
+    30
+}
+
+fn default_preview_idle_timeout_minutes() -> u64 {
+    5
+}
+
+fn default_live_view_address() -> String {
+    "127.0.0.1".to_string()
 }
\ No newline at end of file
@@ -1,5 +1,12 @@
-use crate::camera::{CameraControls, CameraService};
-use crate::functions::{handle_status, handle_update, sync_ntp, NtpRequest, STILL_CAMERA_CONTROLS_FILENAME, VIDEO_CAMERA_CONTROLS_FILENAME};
+use crate::camera::{
+    CameraControls, CameraIntrinsics, CameraService, CaptureConfig, ColourCorrection, Orientation,
+};
+use crate::functions::{
+    handle_status, handle_update, sync_ntp, NtpRequest, ACTIVE_PRESET_FILENAME,
+    COLOUR_CORRECTION_FILENAME, INTRINSICS_FILENAME, ORIENTATION_FILENAME,
+    STILL_CAMERA_CONTROLS_FILENAME, STILL_CAPTURE_CONFIG_FILENAME, VIDEO_CAMERA_CONTROLS_FILENAME,
+    VIDEO_CAPTURE_CONFIG_FILENAME,
+};
 use crate::settings::{BaseSettings, Settings};
 use crate::updater::restart;
 use crate::utils::{AsyncClientExt, ErrorExt, ResultExt};
@@ -8,6 +15,7 @@ use pyo3::Python;
 use reqwest::Client;
 use rumqttc::v5::mqttbytes::v5::Packet;
 use rumqttc::v5::{AsyncClient, Event, EventLoop, MqttOptions};
+use serde::de::DeserializeOwned;
 use std::env;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
@@ -122,23 +130,50 @@ pub async fn startup(
     println!("Subscribed");
 
     let still_controls: Option<CameraControls> =
-        read_camera_controls(&base_settings, &mqtt_client, STILL_CAMERA_CONTROLS_FILENAME).await;
+        read_json_file(&base_settings, &mqtt_client, STILL_CAMERA_CONTROLS_FILENAME).await;
     let video_controls: Option<CameraControls> =
-        read_camera_controls(&base_settings, &mqtt_client, VIDEO_CAMERA_CONTROLS_FILENAME).await;
+        read_json_file(&base_settings, &mqtt_client, VIDEO_CAMERA_CONTROLS_FILENAME).await;
 
     println!("Read controls from file");
 
-    let camera_service = Python::attach(|py| -> Result<CameraService, anyhow::Error> {
-        let still_controls_pydict = match &still_controls {
-            Some(v) => Some(v.to_pydict(py)?),
-            None => None,
-        };
-        let camera_service =
-            CameraService::new(py, &still_controls, &video_controls, still_controls_pydict)?;
+    // Defaults are used, if not set
+    let still_capture_config: Option<CaptureConfig> =
+        read_json_file(base_settings, mqtt_client, STILL_CAPTURE_CONFIG_FILENAME).await;
+    let video_capture_config: Option<CaptureConfig> =
+        read_json_file(base_settings, mqtt_client, VIDEO_CAPTURE_CONFIG_FILENAME).await;
+
+    println!("Read capture configs from file");
+
+    // Per Pi, as cameras are mounted in different orientations
+    let orientation: Option<Orientation> =
+        read_json_file(base_settings, mqtt_client, ORIENTATION_FILENAME).await;
+
+    let active_preset: Option<String> =
+        read_json_file(base_settings, mqtt_client, ACTIVE_PRESET_FILENAME).await;
+
+    let colour_correction: Option<ColourCorrection> =
+        read_json_file(base_settings, mqtt_client, COLOUR_CORRECTION_FILENAME).await;
+
+    let intrinsics: Option<CameraIntrinsics> =
+        read_json_file(base_settings, mqtt_client, INTRINSICS_FILENAME).await;
+
+    let mut camera_service = Python::attach(|py| -> Result<CameraService, anyhow::Error> {
+        let camera_service = CameraService::new(
+            py,
+            &still_controls,
+            &video_controls,
+            still_capture_config,
+            video_capture_config,
+            orientation,
+        )?;
         Ok(camera_service)
     })
     .unwrap();
 
+    camera_service.active_preset = active_preset;
+    camera_service.colour_correction = colour_correction;
+    camera_service.intrinsics = intrinsics;
+
     println!("Set up camera service");
 
     handle_status(&base_settings, &settings, &mqtt_client, &camera_service)
@@ -156,11 +191,11 @@ pub async fn startup(
     (settings, camera_service)
 }
 
-async fn read_camera_controls(
+async fn read_json_file<T: DeserializeOwned>(
     base_settings: &BaseSettings,
     mqtt_client: &AsyncClient,
     filename: &str,
-) -> Option<CameraControls> {
+) -> Option<T> {
     if Path::new(filename).exists() {
         let json = tokio::fs::read_to_string(filename)
             .await
@@ -168,7 +203,7 @@ async fn read_camera_controls(
             .send_if_err(&base_settings, &mqtt_client, "error")
             .await;
         match json {
-            Ok(v) => serde_json::from_str::<CameraControls>(&v)
+            Ok(v) => serde_json::from_str::<T>(&v)
                 .map_err(|e| anyhow::Error::from(e))
                 .send_if_err(&base_settings, &mqtt_client, "error")
                 .await